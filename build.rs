@@ -0,0 +1,60 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+fn main() {
+        println!("cargo:rerun-if-changed=build.rs");
+        rustc_version();
+
+        #[cfg(feature = "capi")]
+        generate_header();
+}
+
+/// Records the compiler version, reported by plugins built with the SDK.
+fn rustc_version() {
+        let rustc   = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+        let version = std::process::Command::new(rustc)
+                .arg("--version")
+                .output()
+                .ok()
+                .and_then(|o| String::from_utf8(o.stdout).ok())
+                .map(|v| v.trim().to_owned())
+                .unwrap_or_else(|| "rustc (unknown version)".into());
+
+        println!("cargo:rustc-env=VPLUGIN_RUSTC_VERSION={}", version);
+}
+
+/// Generates `vplugin.h` from the C API into `OUT_DIR`, and only updates `include/vplugin.h`
+/// when `VPLUGIN_UPDATE_HEADER` is set, so building never modifies the source tree.
+#[cfg(feature = "capi")]
+fn generate_header() {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir   = std::env::var("OUT_DIR").unwrap();
+        println!("cargo:rerun-if-changed=src");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-env-changed=VPLUGIN_UPDATE_HEADER");
+
+        let bindings = match cbindgen::generate(&crate_dir) {
+                Ok (bindings) => bindings,
+                Err(e) => {
+                        println!("cargo:warning=Couldn't generate vplugin.h: {}", e);
+                        return;
+                }
+        };
+        bindings.write_to_file(std::path::Path::new(&out_dir).join("vplugin.h"));
+        if std::env::var_os("VPLUGIN_UPDATE_HEADER").is_some() {
+                bindings.write_to_file(std::path::Path::new(&crate_dir).join("include/vplugin.h"));
+        }
+}
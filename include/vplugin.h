@@ -0,0 +1,550 @@
+/* VPlugin C API. Licensed under the Apache License, Version 2.0. */
+
+#ifndef VPLUGIN_H
+#define VPLUGIN_H
+
+/* This file is generated by cbindgen from src/capi.rs. Do not edit it manually. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The version of the binary interface between VPlugin and its plugins.
+ * Plugins export it as `VPLUGIN_ABI_VERSION` (A `uint32_t`), which is checked
+ * before they are loaded. The SDK exports it automatically.
+ */
+#define VPLUGIN_CURRENT_ABI_VERSION 1
+
+/**
+ * The version of the [`VPluginHost`] layout. New fields are only
+ * ever appended, so plugins can also check `size` before using them.
+ */
+#define VPLUGIN_HOST_API_VERSION 1
+
+/**
+ * The operation succeeded.
+ */
+#define VPLUGIN_OK 0
+
+/**
+ * Invalid parameters were passed to the function.
+ */
+#define VPLUGIN_ERR_PARAMETERS 1
+
+/**
+ * The plugin is not valid for this operation.
+ */
+#define VPLUGIN_ERR_INVALID_PLUGIN 2
+
+/**
+ * The file requested couldn't be found.
+ */
+#define VPLUGIN_ERR_NO_SUCH_FILE 3
+
+/**
+ * Access to a file was denied, or the host runs as root.
+ */
+#define VPLUGIN_ERR_PERMISSION_DENIED 4
+
+/**
+ * The symbol requested is not exported by the plugin.
+ */
+#define VPLUGIN_ERR_MISSING_SYMBOL 5
+
+/**
+ * The plugin's entry point failed.
+ */
+#define VPLUGIN_ERR_FAILED_TO_INITIALIZE 6
+
+/**
+ * Any other error, including panics inside VPlugin.
+ */
+#define VPLUGIN_ERR_INTERNAL 7
+
+/**
+ * A hook's declared signature doesn't match the expected one.
+ */
+#define VPLUGIN_ERR_SIGNATURE_MISMATCH 8
+
+/**
+ * A hook reported a failure.
+ */
+#define VPLUGIN_ERR_HOOK_FAILED 9
+
+/**
+ * The plugin was built for an incompatible ABI version.
+ */
+#define VPLUGIN_ERR_INCOMPATIBLE_ABI 10
+
+/**
+ * A user setting doesn't match the plugin's configuration schema.
+ */
+#define VPLUGIN_ERR_INVALID_CONFIG 11
+
+/**
+ * Object files can't be loaded from the work directory (It's mounted `noexec`).
+ */
+#define VPLUGIN_ERR_NOT_EXECUTABLE 12
+
+/**
+ * An object file of the plugin is not a shared object built for the host's architecture.
+ */
+#define VPLUGIN_ERR_INCOMPATIBLE_OBJECT 13
+
+/**
+ * An object file of the plugin needs a library that isn't installed.
+ */
+#define VPLUGIN_ERR_MISSING_LIBRARY 14
+
+/**
+ * The plugin implements another version of an interface than the host.
+ */
+#define VPLUGIN_ERR_INTERFACE_MISMATCH 15
+
+/**
+ * A data symbol of the plugin doesn't have the size or alignment of the type requested.
+ */
+#define VPLUGIN_ERR_STATIC_MISMATCH 16
+
+/**
+ * Opaque handle to a hook of a plugin, keeping its object file loaded.
+ */
+typedef struct VPluginHook VPluginHook;
+
+/**
+ * Opaque handle to an instance of a plugin.
+ */
+typedef struct VPluginInstance VPluginInstance;
+
+/**
+ * Opaque handle to a hook bound to an instance of a plugin, keeping its object file loaded.
+ */
+typedef struct VPluginInstanceHook VPluginInstanceHook;
+
+/**
+ * Opaque handle to a plugin manager.
+ */
+typedef struct VPluginManager VPluginManager;
+
+/**
+ * Opaque handle to a loaded plugin.
+ */
+typedef struct VPluginModule VPluginModule;
+
+/**
+ * Frees the buffer of a [VVec], along with its first `len` elements.
+ */
+typedef void (*FreeVec_u8)(uint8_t *ptr, uintptr_t len, uintptr_t capacity);
+
+/**
+ * ## VVec
+ * An owned, growable array (`Vec<T>`).
+ */
+typedef struct VVec_u8 {
+  uint8_t *ptr;
+  uintptr_t len;
+  uintptr_t capacity;
+  FreeVec_u8 free;
+} VVec_u8;
+
+/**
+ * ## VString
+ * An owned string (`String`), which may not be valid UTF-8 if it comes from a plugin.
+ */
+typedef struct VString {
+  struct VVec_u8 bytes;
+} VString;
+
+/**
+ * ## VOption
+ * An optional value (`Option<T>`).
+ */
+enum VOption_VString_Tag
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  None_VString,
+  Some_VString,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum VOption_VString_Tag VOption_VString_Tag;
+#else
+typedef uint8_t VOption_VString_Tag;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+typedef struct VOption_VString {
+  VOption_VString_Tag tag;
+  union {
+    struct {
+      struct VString some;
+    };
+  };
+} VOption_VString;
+
+/**
+ * A hook declared in the `[hooks]` table, and its signature descriptor.
+ */
+typedef struct VHookDeclaration {
+  struct VString name;
+  struct VString signature;
+} VHookDeclaration;
+
+/**
+ * Frees the buffer of a [VVec], along with its first `len` elements.
+ */
+typedef void (*FreeVec_VHookDeclaration)(struct VHookDeclaration *ptr,
+                                         uintptr_t len,
+                                         uintptr_t capacity);
+
+/**
+ * ## VVec
+ * An owned, growable array (`Vec<T>`).
+ */
+typedef struct VVec_VHookDeclaration {
+  struct VHookDeclaration *ptr;
+  uintptr_t len;
+  uintptr_t capacity;
+  FreeVec_VHookDeclaration free;
+} VVec_VHookDeclaration;
+
+/**
+ * Frees the buffer of a [VVec], along with its first `len` elements.
+ */
+typedef void (*FreeVec_VString)(struct VString *ptr, uintptr_t len, uintptr_t capacity);
+
+/**
+ * ## VVec
+ * An owned, growable array (`Vec<T>`).
+ */
+typedef struct VVec_VString {
+  struct VString *ptr;
+  uintptr_t len;
+  uintptr_t capacity;
+  FreeVec_VString free;
+} VVec_VString;
+
+/**
+ * ## VPluginMetadata
+ * The metadata of a plugin, in a layout that can be passed across the FFI boundary.
+ */
+typedef struct VPluginMetadata {
+  struct VString name;
+  struct VString version;
+  struct VOption_VString description;
+  struct VString objfile;
+  int32_t priority;
+  struct VVec_VHookDeclaration hooks;
+  struct VVec_VString libraries;
+} VPluginMetadata;
+
+/**
+ * ## EventCallback
+ * A function provided by a plugin to receive events it subscribed to.
+ * - `event` is the NUL-terminated name of the event,
+ * - `data` and `len` are the serialized (JSON) payload, only valid during the call,
+ * - `user_data` is the pointer the plugin passed when subscribing.
+ */
+typedef void (*EventCallback)(const char *event, const uint8_t *data, uintptr_t len, void *user_data);
+
+/**
+ * ## VPluginHost
+ * The callbacks VPlugin provides to a plugin when it's started.
+ *
+ * If a plugin exports a function named `vplugin_attach`, taking a
+ * `*const VPluginHost`, it will be called right before its entry point.
+ * The pointer stays valid until the plugin is terminated, and every callback
+ * must be passed the `context` field as its first argument:
+ * ```c
+ * static const VPluginHost *host;
+ *
+ * void vplugin_attach(const VPluginHost *h) {
+ *         host = h;
+ *         host->subscribe(host->context, "file_opened", on_file_opened, NULL);
+ * }
+ * ```
+ */
+typedef struct VPluginHost {
+  /**
+   * The version of this struct's layout ([`HOST_API_VERSION`]).
+   */
+  uint32_t version;
+  /**
+   * The size of this struct in bytes.
+   */
+  uintptr_t size;
+  /**
+   * Opaque pointer identifying the plugin to VPlugin.
+   */
+  void *context;
+  /**
+   * Subscribes to the event named `event`. Returns `0` on success.
+   */
+  int (*subscribe)(void *context, const char *event, EventCallback callback, void *user_data);
+  /**
+   * Removes a subscription made with `subscribe`. Returns `0` if it existed.
+   */
+  int (*unsubscribe)(void *context, const char *event, EventCallback callback);
+  /**
+   * Publishes a `#[repr(C)]` interface (Usually a table of function pointers)
+   * under `name` for other plugins to use. Returns `0` on success.
+   */
+  int (*publish_service)(void *context,
+                         const char *name,
+                         uint32_t major,
+                         uint32_t minor,
+                         const void *interface);
+  /**
+   * Withdraws a service published by the plugin, notifying its consumers.
+   */
+  int (*withdraw_service)(void *context, const char *name);
+  /**
+   * Returns the interface of the service `name`, if it's published with the same
+   * `major` and at least `minor` version, or NULL. `on_lost` (Which may be NULL)
+   * is called with `user_data` if the service is withdrawn.
+   */
+  const void *(*request_service)(void *context,
+                                 const char *name,
+                                 uint32_t major,
+                                 uint32_t minor,
+                                 void (*on_lost)(const char *service, void *user_data),
+                                 void *user_data);
+  /**
+   * Tells VPlugin the plugin doesn't use a service anymore.
+   */
+  int (*release_service)(void *context, const char *name);
+  /**
+   * Explains why the entry point or a hook is about to fail. The message is reported
+   * to the host along with the returned code, and cleared before every call.
+   */
+  int (*set_error)(void *context, const char *message);
+  /**
+   * Returns the directory the plugin keeps its data in (Never deleted by VPlugin),
+   * or NULL if the plugin was not loaded through a manager.
+   */
+  const char *(*data_dir)(void *context);
+  /**
+   * Returns the directory the plugin keeps its settings in, or NULL.
+   */
+  const char *(*config_dir)(void *context);
+  /**
+   * Returns the directory the plugin keeps its caches in, or NULL.
+   */
+  const char *(*cache_dir)(void *context);
+  /**
+   * Returns the plugin's settings (Its defaults merged with the user's `config.toml`)
+   * as a TOML document, or NULL if it doesn't declare any.
+   */
+  const char *(*get_config)(void *context);
+} VPluginHost;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns a description of the last error that occurred on the calling thread,
+ * or NULL if no error occurred. The string stays valid until the next failing call.
+ */
+const char *vplugin_last_error_message(void);
+
+/**
+ * Creates a new plugin manager, or returns NULL on failure.
+ */
+struct VPluginManager *vplugin_manager_new(void);
+
+/**
+ * Sets the name of the plugins' entry point.
+ *
+ * # Safety
+ * `manager` must come from `vplugin_manager_new`, and `entry_point` must be a valid C string.
+ */
+int vplugin_manager_set_entry_point(struct VPluginManager *manager, const char *entry_point);
+
+/**
+ * Sets the directory plugins are extracted to, instead of `<temp>/vplugin`.
+ *
+ * # Safety
+ * `manager` must come from `vplugin_manager_new`, and `dir` must be a valid C string.
+ */
+int vplugin_manager_set_work_dir(struct VPluginManager *manager, const char *dir);
+
+/**
+ * Loads object files from memory instead of the work directory if `enabled`
+ * is non-zero (Linux only).
+ *
+ * # Safety
+ * `manager` must come from `vplugin_manager_new`.
+ */
+int vplugin_manager_set_memfd_loading(struct VPluginManager *manager, int enabled);
+
+/**
+ * Loads the plugin at `filename`, storing its handle in `plugin`.
+ * The handle must be released with `vplugin_plugin_free`, unless it is registered.
+ *
+ * # Safety
+ * `manager` must come from `vplugin_manager_new`, `filename` must be a valid
+ * C string and `plugin` must point to writable memory.
+ */
+int vplugin_manager_load_plugin(struct VPluginManager *manager,
+                                const char *filename,
+                                struct VPluginModule **plugin);
+
+/**
+ * Runs the entry point of a plugin.
+ *
+ * # Safety
+ * `manager` and `plugin` must be valid handles.
+ */
+int vplugin_manager_begin_plugin(struct VPluginManager *manager, struct VPluginModule *plugin);
+
+/**
+ * Looks up a hook (`int hook(void *)`) in a started plugin, storing its handle in `hook`.
+ * The plugin's object file stays loaded until the handle is released with `vplugin_hook_free`.
+ *
+ * # Safety
+ * `manager` and `plugin` must be valid handles, `name` a valid C
+ * string and `hook` must point to writable memory.
+ */
+int vplugin_manager_get_hook(struct VPluginManager *manager,
+                             struct VPluginModule *plugin,
+                             const char *name,
+                             struct VPluginHook **hook);
+
+/**
+ * Calls a hook with `data`, storing what it returned in `result`.
+ * Fails with `VPLUGIN_ERR_INVALID_PLUGIN` if the plugin was terminated meanwhile.
+ *
+ * # Safety
+ * `hook` must be a valid handle, `data` what the hook expects
+ * and `result` must point to writable memory.
+ */
+int vplugin_hook_call(struct VPluginHook *hook, void *data, int *result);
+
+/**
+ * Releases a hook handle. Passing NULL does nothing.
+ *
+ * # Safety
+ * `hook` must be a valid handle or NULL, and must not be used afterwards.
+ */
+void vplugin_hook_free(struct VPluginHook *hook);
+
+/**
+ * Creates an instance of the registered plugin named `name`, storing its handle in `instance`.
+ * The handle must be released with `vplugin_instance_free`.
+ *
+ * # Safety
+ * `manager` must be a valid handle, `name` a valid C string
+ * and `instance` must point to writable memory.
+ */
+int vplugin_manager_instantiate(struct VPluginManager *manager,
+                                const char *name,
+                                struct VPluginInstance **instance);
+
+/**
+ * Looks up a hook (`int hook(void *instance, void *)`) of the plugin `instance` belongs to,
+ * storing its handle, bound to the instance, in `hook`. The plugin's object file stays
+ * loaded until the handle is released with `vplugin_instance_hook_free`.
+ *
+ * # Safety
+ * `manager` and `instance` must be valid handles, `name` a valid
+ * C string and `hook` must point to writable memory.
+ */
+int vplugin_manager_get_instance_hook(struct VPluginManager *manager,
+                                      struct VPluginInstance *instance,
+                                      const char *name,
+                                      struct VPluginInstanceHook **hook);
+
+/**
+ * Calls a hook with its instance and `data`, storing what it returned in `result`.
+ * Fails with `VPLUGIN_ERR_INVALID_PLUGIN` if the plugin was terminated meanwhile.
+ *
+ * # Safety
+ * `hook` must be a valid handle, its instance must not have been freed, `data`
+ * must be what the hook expects and `result` must point to writable memory.
+ */
+int vplugin_instance_hook_call(struct VPluginInstanceHook *hook, void *data, int *result);
+
+/**
+ * Releases an instance hook handle. Passing NULL does nothing.
+ *
+ * # Safety
+ * `hook` must be a valid handle or NULL, and must not be used afterwards.
+ */
+void vplugin_instance_hook_free(struct VPluginInstanceHook *hook);
+
+/**
+ * Returns the pointer the plugin returned for the instance, or NULL if `instance` is NULL.
+ *
+ * # Safety
+ * `instance` must be a valid handle or NULL.
+ */
+void *vplugin_instance_ptr(struct VPluginInstance *instance);
+
+/**
+ * Destroys an instance and releases its handle. Passing NULL does nothing.
+ *
+ * # Safety
+ * `instance` must be a valid handle or NULL, and must not be used afterwards.
+ */
+void vplugin_instance_free(struct VPluginInstance *instance);
+
+/**
+ * Hands a plugin over to the manager, which will terminate it on shutdown.
+ * The plugin handle is consumed and must not be used afterwards, even on failure.
+ *
+ * # Safety
+ * `manager` and `plugin` must be valid handles.
+ */
+int vplugin_manager_register_plugin(struct VPluginManager *manager, struct VPluginModule *plugin);
+
+/**
+ * Terminates a started plugin, calling its destructor.
+ *
+ * # Safety
+ * `plugin` must be a valid handle.
+ */
+int vplugin_plugin_terminate(struct VPluginModule *plugin);
+
+/**
+ * Copies the metadata of a loaded plugin into `*metadata`, to be released with
+ * `vplugin_metadata_free()`. Strings are UTF-8 and not NUL-terminated.
+ *
+ * # Safety
+ * `plugin` must be a valid handle, and `metadata` must point to writable memory.
+ */
+int vplugin_plugin_get_metadata(struct VPluginModule *plugin, struct VPluginMetadata *metadata);
+
+/**
+ * Releases metadata returned by `vplugin_plugin_get_metadata()`. Passing NULL does nothing.
+ *
+ * # Safety
+ * `metadata` must be NULL or filled by `vplugin_plugin_get_metadata()`, and must not be used afterwards.
+ */
+void vplugin_metadata_free(struct VPluginMetadata *metadata);
+
+/**
+ * Releases a plugin that was not registered. Passing NULL does nothing.
+ *
+ * # Safety
+ * `plugin` must be a valid handle or NULL, and must not be used afterwards.
+ */
+void vplugin_plugin_free(struct VPluginModule *plugin);
+
+/**
+ * Terminates every registered plugin and destroys the manager. Passing NULL does nothing.
+ *
+ * # Safety
+ * `manager` must be a valid handle or NULL, and must not be used afterwards.
+ */
+void vplugin_manager_shutdown(struct VPluginManager *manager);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* VPLUGIN_H */
@@ -0,0 +1,124 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::collections::BTreeMap;
+use crate::error::VPluginError;
+use crate::plugin::Plugin;
+
+/// The version of the binary interface between VPlugin and its plugins.
+/// Plugins export it as `VPLUGIN_ABI_VERSION` (A `uint32_t`), which is checked
+/// before they are loaded. The SDK exports it automatically.
+pub const ABI_VERSION: u32 = 1;
+
+/// The VPlugin and compiler versions this copy of VPlugin was built with.
+/// Plugins built with the SDK export it as `VPLUGIN_BUILD_INFO`.
+pub const BUILD_INFO: &str = concat!("vplugin ", env!("CARGO_PKG_VERSION"), ", ", env!("VPLUGIN_RUSTC_VERSION"));
+
+/// ## AbiShim
+/// A compatibility shim for plugins built against an older ABI version,
+/// registered with [register_abi_shim](crate::PluginManager::register_abi_shim).
+///
+/// It is called once the plugin is loaded, before it is returned to the host,
+/// and may refuse the plugin by returning an error. A shim for version 0 is also
+/// called for plugins not exporting `VPLUGIN_ABI_VERSION`.
+pub type AbiShim = fn(&mut Plugin) -> Result<(), VPluginError>;
+
+/// Refuses plugins built for another ABI version, unless a shim accepts the older version
+/// they were built for. Plugins not exporting one predate the check, they're loaded with a
+/// warning (Through the shim for version 0, if there is one).
+///
+/// This runs before the object file is loaded, the shim returned must be called once it is.
+pub(crate) fn check(
+        name   : &str,
+        version: Option<u32>,
+        build  : Option<&str>,
+        shims  : &BTreeMap<u32, AbiShim>
+) -> Result<Option<AbiShim>, VPluginError> {
+        let build   = build.unwrap_or("unknown build");
+        let Some(version) = version else {
+                log::warn!("Plugin '{}' does not export VPLUGIN_ABI_VERSION, its ABI can't be checked.", name);
+                return Ok(shims.get(&0).copied());
+        };
+
+        if version == ABI_VERSION {
+                if build != BUILD_INFO {
+                        log::debug!("Plugin '{}' was built with {}, the host with {}.", name, build, BUILD_INFO);
+                }
+                return Ok(None);
+        }
+
+        if let Some(shim) = shims.get(&version).filter(|_| version < ABI_VERSION) {
+                log::info!("Plugin '{}' targets ABI version {}, loading it through a compatibility shim.", name, version);
+                return Ok(Some(*shim));
+        }
+
+        log::error!(
+                "Plugin '{}' was built for ABI version {} ({}), but the host uses version {} ({}).",
+                name,
+                version,
+                build,
+                ABI_VERSION,
+                BUILD_INFO
+        );
+        Err(VPluginError::IncompatibleAbi {
+                plugin  : name.into(),
+                found   : version,
+                expected: ABI_VERSION,
+                build   : build.into(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        fn accept(_: &mut Plugin) -> Result<(), VPluginError> {
+                Ok(())
+        }
+
+        #[test]
+        fn accepts_the_current_version() {
+                assert!(matches!(check("test", Some(ABI_VERSION), Some(BUILD_INFO), &BTreeMap::new()), Ok(None)));
+                assert!(matches!(check("test", Some(ABI_VERSION), None, &BTreeMap::new()), Ok(None)));
+        }
+
+        #[test]
+        fn accepts_plugins_without_a_version() {
+                assert!(matches!(check("test", None, None, &BTreeMap::new()), Ok(None)));
+                let shims = BTreeMap::from([(0, accept as AbiShim)]);
+                assert!(matches!(check("test", None, None, &shims), Ok(Some(_))));
+        }
+
+        #[test]
+        fn accepts_older_versions_through_a_shim() {
+                let shims = BTreeMap::from([(ABI_VERSION - 1, accept as AbiShim)]);
+                assert!(matches!(check("test", Some(ABI_VERSION - 1), None, &shims), Ok(Some(_))));
+        }
+
+        #[test]
+        fn refuses_other_versions_without_a_shim() {
+                let shims = BTreeMap::from([(ABI_VERSION - 1, accept as AbiShim)]);
+                assert!(matches!(
+                        check("test", Some(ABI_VERSION + 1), Some("build"), &shims),
+                        Err(VPluginError::IncompatibleAbi { found, expected: ABI_VERSION, build, .. })
+                                if found == ABI_VERSION + 1 && build == "build"
+                ));
+                /* Shims are only for older versions. */
+                let shims = BTreeMap::from([(ABI_VERSION + 1, accept as AbiShim)]);
+                assert!(check("test", Some(ABI_VERSION + 1), None, &shims).is_err());
+                assert!(check("test", Some(ABI_VERSION - 1), None, &BTreeMap::new()).is_err());
+        }
+}
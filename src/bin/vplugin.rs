@@ -0,0 +1,423 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+//! # vplugin
+//! A small command line tool to inspect, validate, pack and smoke-test
+//! VPlugin packages without writing a host application for them.
+//!
+//! ```text
+//! $ vplugin inspect  example.vpl
+//! $ vplugin validate example.vpl --entry app_entry
+//! $ vplugin pack     plugin/ -o example.vpl
+//! $ vplugin run      --entry app_entry example.vpl
+//! ```
+
+extern crate vplugin;
+extern crate log;
+extern crate toml;
+extern crate zip;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use vplugin::{ExportedSymbolKind, Plugin, PluginManager};
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+const USAGE: &str = "\
+Usage: vplugin [-v] <command> [options]
+
+Commands:
+    inspect  <plugin.vpl>                      Print the metadata and files of a plugin.
+    validate [--entry <name>] <plugin.vpl>     Run every check performed while loading a plugin.
+    pack     <directory> [-o <output.vpl>]     Package a plugin directory into a .vpl archive.
+    run      [--entry <name>] <plugin.vpl>     Load and start a plugin, then terminate it.
+
+Options:
+    -v, --verbose    Print VPlugin's log messages.
+    -h, --help       Print this message.
+";
+
+/// Prints VPlugin's log messages to stderr.
+struct Logger;
+
+impl log::Log for Logger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+        }
+
+        fn log(&self, record: &log::Record) {
+                eprintln!("[{}] {}", record.level(), record.args());
+        }
+
+        fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger;
+
+/// Options shared by all subcommands.
+struct Args {
+        entry  : Option<String>,
+        output : Option<PathBuf>,
+        targets: Vec<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+        let mut parsed = Args {
+                entry  : None,
+                output : None,
+                targets: Vec::new(),
+        };
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+                match arg.as_str() {
+                        "--entry" | "-e" => match iter.next() {
+                                Some(v) => parsed.entry = Some(v.clone()),
+                                None    => return Err(format!("'{}' requires a value", arg)),
+                        },
+                        "--output" | "-o" => match iter.next() {
+                                Some(v) => parsed.output = Some(PathBuf::from(v)),
+                                None    => return Err(format!("'{}' requires a value", arg)),
+                        },
+                        a if a.starts_with('-') => return Err(format!("Unknown option '{}'", a)),
+                        _ => parsed.targets.push(arg.clone()),
+                }
+        }
+
+        Ok(parsed)
+}
+
+/// Returns the single positional argument of a subcommand.
+fn single_target(args: &Args, what: &str) -> Result<String, String> {
+        match args.targets.as_slice() {
+                [target] => Ok(target.clone()),
+                []       => Err(format!("Missing {}", what)),
+                _        => Err(format!("Expected a single {}", what)),
+        }
+}
+
+/// Resolves a path given on the command line, failing early if it doesn't exist.
+fn absolute(path: &str) -> Result<String, String> {
+        fs::canonicalize(path)
+                .map(|p| p.to_string_lossy().into_owned())
+                .map_err(|e| format!("{}: {}", path, e))
+}
+
+fn inspect(args: &Args) -> Result<(), String> {
+        let filename = absolute(&single_target(args, "plugin")?)?;
+        let plugin   = Plugin::load(filename.as_str()).map_err(|e| e.report())?;
+        let metadata = plugin.get_metadata().as_ref().unwrap();
+
+        println!("Plugin:      {}", metadata.name);
+        println!("Version:     {}", metadata.version);
+        println!("Object file: {}", metadata.objfile);
+        if let Some(description) = &metadata.description {
+                println!("Description: {}", description);
+        }
+        match plugin.abi_version() {
+                Some(version) => println!("ABI version: {}", version),
+                None          => println!("ABI version: (Not exported)"),
+        }
+        if let Some(build) = plugin.build_info() {
+                println!("Built with:  {}", build);
+        }
+        if !metadata.libraries.is_empty() {
+                println!("Libraries:   {}", metadata.libraries.join(", "));
+        }
+
+        let flags = plugin.load_flags();
+        let set: Vec<&str> = [("now", flags.now), ("global", flags.global), ("deepbind", flags.deepbind), ("namespace", flags.namespace)]
+                .into_iter()
+                .filter_map(|(name, set)| set.then_some(name))
+                .collect();
+        if !set.is_empty() {
+                println!("Loaded with: {}", set.join(", "));
+        }
+
+        println!("Files:");
+        let mut files = plugin.files();
+        files.sort();
+        for file in files {
+                println!("    {}", file);
+        }
+
+        let schema = plugin.config_schema();
+        if !schema.is_empty() {
+                println!("Settings:");
+                for setting in schema.settings() {
+                        println!(
+                                "    {} ({:?}, default: {}){}",
+                                setting.name,
+                                setting.kind,
+                                setting.default,
+                                setting.description.as_ref().map(|d| format!(" - {}", d)).unwrap_or_default()
+                        );
+                }
+        }
+
+        let hooks = plugin.declared_hooks();
+        if !hooks.is_empty() {
+                println!("Declared hooks:");
+                for (hook, signature) in hooks {
+                        println!("    {} = {}", hook, signature);
+                }
+        }
+
+        println!("Exported symbols:");
+        match plugin.exported_symbols() {
+                Ok(symbols) => for symbol in symbols {
+                        let kind = match symbol.kind {
+                                ExportedSymbolKind::Function => "function",
+                                ExportedSymbolKind::Data     => "data",
+                                ExportedSymbolKind::Unknown  => "unknown",
+                        };
+                        println!("    {:<8} {}", kind, symbol.name);
+                },
+                Err(e) => println!("    (Couldn't read symbols: {})", e.report()),
+        }
+
+        Ok(())
+}
+
+/// Checks a plugin archive the same way `Plugin::load` does, but collects
+/// every problem found instead of stopping (or panicking) on the first one.
+fn validate_archive(filename: &str, entry: Option<&str>) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let file = match File::open(filename) {
+                Ok (f) => f,
+                Err(e) => return vec![format!("Cannot open archive: {}", e)],
+        };
+        let mut archive = match ZipArchive::new(file) {
+                Ok (a) => a,
+                Err(e) => return vec![format!("Not a valid ZIP archive: {}", e)],
+        };
+
+        let mut contents = String::new();
+        match archive.by_name("metadata.toml") {
+                Ok(mut f) => if let Err(e) = f.read_to_string(&mut contents) {
+                        return vec![format!("Cannot read metadata.toml: {}", e)];
+                },
+                Err(_) => return vec!["Archive does not contain a metadata.toml file".into()],
+        }
+
+        let data: toml::Value = match toml::from_str(&contents) {
+                Ok (v) => v,
+                Err(e) => return vec![format!("metadata.toml is not valid TOML: {}", e)],
+        };
+        let metadata = match data.get("metadata").and_then(|m| m.as_table()) {
+                Some(m) => m,
+                None    => return vec!["metadata.toml has no [metadata] table".into()],
+        };
+
+        for field in ["name", "version", "objfile"] {
+                match metadata.get(field).map(|v| v.as_str()) {
+                        None             => problems.push(format!("Missing required field '{}'", field)),
+                        Some(None)       => problems.push(format!("Field '{}' must be a string", field)),
+                        Some(Some(""))   => problems.push(format!("Field '{}' must not be empty", field)),
+                        Some(Some(v)) if field != "objfile" && v.contains(' ') => {
+                                problems.push(format!("Field '{}' must not contain spaces", field))
+                        },
+                        Some(Some(_))    => (),
+                }
+        }
+        if let Some(description) = metadata.get("description") {
+                if !description.is_str() {
+                        problems.push("Field 'description' must be a string".into());
+                }
+        }
+        if let Some(hooks) = data.get("hooks") {
+                match hooks.as_table() {
+                        Some(hooks) => for (hook, signature) in hooks {
+                                if !signature.is_str() {
+                                        problems.push(format!("Signature of hook '{}' must be a string", hook));
+                                }
+                        },
+                        None => problems.push("'hooks' must be a table".into()),
+                }
+        }
+        if let Some(objfile) = metadata.get("objfile").and_then(|v| v.as_str()) {
+                if !objfile.is_empty() && archive.by_name(objfile).is_err() {
+                        problems.push(format!("Object file '{}' is not present in the archive", objfile));
+                }
+        }
+
+        /* Only try loading the object file if the package itself is sane. */
+        if !problems.is_empty() {
+                return problems;
+        }
+        match Plugin::load(filename) {
+                Ok(plugin) => if let Some(entry) = entry {
+                        if !plugin.is_function_available(entry) {
+                                problems.push(format!("Entry point '{}' is not exported by the object file", entry));
+                        }
+                },
+                Err(e) => problems.push(format!("Couldn't load plugin: {}", e.report())),
+        }
+
+        problems
+}
+
+fn validate(args: &Args) -> Result<(), String> {
+        if args.targets.is_empty() {
+                return Err("Missing plugin".into());
+        }
+
+        let mut failed = false;
+        let filenames: Vec<_> = args.targets.iter().map(|t| absolute(t)).collect();
+        for (target, filename) in args.targets.iter().zip(filenames) {
+                let problems = match filename {
+                        Ok (filename) => validate_archive(&filename, args.entry.as_deref()),
+                        Err(e)        => vec![e],
+                };
+                if problems.is_empty() {
+                        println!("{}: OK", target);
+                } else {
+                        failed = true;
+                        println!("{}: {} problem(s) found", target, problems.len());
+                        for problem in problems {
+                                println!("    - {}", problem);
+                        }
+                }
+        }
+
+        if failed {
+                Err("Validation failed".into())
+        } else {
+                Ok(())
+        }
+}
+
+/// Recursively collects all regular files under `dir`.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                        collect_files(&path, files)?;
+                } else {
+                        files.push(path);
+                }
+        }
+        Ok(())
+}
+
+fn pack(args: &Args) -> Result<(), String> {
+        let directory = PathBuf::from(single_target(args, "plugin directory")?);
+        let contents  = fs::read_to_string(directory.join("metadata.toml"))
+                .map_err(|e| format!("{}: {}", directory.join("metadata.toml").display(), e))?;
+        let data: toml::Value = toml::from_str(&contents)
+                .map_err(|e| format!("metadata.toml is not valid TOML: {}", e))?;
+
+        let field = |name: &str| data
+                .get("metadata")
+                .and_then(|m| m.get(name))
+                .and_then(|v| v.as_str())
+                .filter(|v| !v.is_empty())
+                .map(String::from)
+                .ok_or(format!("metadata.toml is missing the '{}' field", name));
+        let name    = field("name")?;
+        let objfile = field("objfile")?;
+        if !directory.join(&objfile).is_file() {
+                return Err(format!("Object file '{}' does not exist in {}", objfile, directory.display()));
+        }
+
+        let output = args.output.clone().unwrap_or_else(|| PathBuf::from(format!("{}.vpl", name)));
+        let mut files = Vec::new();
+        collect_files(&directory, &mut files).map_err(|e| format!("{}: {}", directory.display(), e))?;
+        files.sort();
+
+        let out = File::create(&output).map_err(|e| format!("{}: {}", output.display(), e))?;
+        let output_abs = fs::canonicalize(&output).ok();
+        let mut writer = ZipWriter::new(out);
+        for path in files {
+                if fs::canonicalize(&path).ok() == output_abs {
+                        continue;
+                }
+                /* Archive paths always use forward slashes. */
+                let relative = path
+                        .strip_prefix(&directory)
+                        .unwrap()
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+
+                writer.start_file(relative.as_str(), FileOptions::default()).map_err(|e| e.to_string())?;
+                let mut input = File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                io::copy(&mut input, &mut writer).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        writer.finish().map_err(|e| e.to_string())?;
+
+        println!("Packed '{}' into {}", name, output.display());
+        Ok(())
+}
+
+fn run(args: &Args) -> Result<(), String> {
+        let filename    = absolute(&single_target(args, "plugin")?)?;
+        let mut manager = PluginManager::try_new().map_err(|e| e.report())?;
+        if let Some(entry) = &args.entry {
+                manager.set_entry_point(entry);
+        }
+        let mut plugin  = manager.load_plugin(&filename).map_err(|e| e.report())?;
+
+        manager.begin_plugin(&mut plugin).map_err(|e| format!("Couldn't start plugin: {}", e.report()))?;
+        println!("Plugin started successfully.");
+
+        if let Err(e) = plugin.terminate() {
+                log::warn!("Couldn't terminate plugin: {}", e.report());
+        }
+        manager.shutdown();
+        Ok(())
+}
+
+fn main() -> ExitCode {
+        let mut argv: Vec<String> = env::args().skip(1).collect();
+        let verbose = argv.iter().any(|a| a == "-v" || a == "--verbose");
+        argv.retain(|a| a != "-v" && a != "--verbose");
+
+        /* Checked after removing the flags, 'vplugin -v' alone has no command either. */
+        if argv.iter().any(|a| a == "-h" || a == "--help") || argv.is_empty() {
+                print!("{}", USAGE);
+                return ExitCode::SUCCESS;
+        }
+
+        if log::set_logger(&LOGGER).is_ok() {
+                log::set_max_level(if verbose {
+                        log::LevelFilter::Trace
+                } else {
+                        log::LevelFilter::Warn
+                });
+        }
+
+        let command = argv.remove(0);
+        let result  = parse_args(&argv).and_then(|args| match command.as_str() {
+                "inspect"  => inspect(&args),
+                "validate" => validate(&args),
+                "pack"     => pack(&args),
+                "run"      => run(&args),
+                other      => Err(format!("Unknown command '{}'. See 'vplugin --help'.", other)),
+        });
+
+        match result {
+                Ok (_) => ExitCode::SUCCESS,
+                Err(e) => {
+                        eprintln!("vplugin: {}", e);
+                        ExitCode::FAILURE
+                }
+        }
+}
@@ -0,0 +1,336 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+extern crate sha2;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::ZipArchive;
+use crate::error::VPluginError;
+
+/// The file describing a cache entry, inside the entry itself.
+const MANIFEST: &str = ".vplugin-cache.toml";
+
+/// How much space the cache may take by default: 512 MiB.
+pub(crate) const DEFAULT_CACHE_LIMIT: u64 = 512 * 1024 * 1024;
+
+/// Cache entries plugins loaded by this process use as they are.
+static IN_USE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Marks `entry` as used by a plugin, returning `false` if another one already uses it
+/// (And should get its own copy of the object files, see [release]).
+pub(crate) fn acquire(entry: &Path) -> bool {
+        let mut in_use = IN_USE.lock().unwrap_or_else(|e| e.into_inner());
+        if in_use.iter().any(|e| e == entry) {
+                return false;
+        }
+        in_use.push(entry.to_owned());
+        true
+}
+
+/// Allows other plugins to use `entry` once [acquire] returned `true` for it.
+pub(crate) fn release(entry: &Path) {
+        IN_USE.lock().unwrap_or_else(|e| e.into_inner()).retain(|e| e != entry);
+}
+
+/// Returns whether a plugin loaded by this process uses `entry`, so it must not be evicted.
+fn in_use(entry: &Path) -> bool {
+        IN_USE.lock().unwrap_or_else(|e| e.into_inner()).iter().any(|e| e == entry)
+}
+
+/// This is purely for (de)serialization.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+        /// When the entry was last used, in seconds since the Unix epoch.
+        last_used: u64,
+        /// The size of every extracted file, in bytes.
+        size     : u64,
+        /// Every extracted file, mapped to the SHA-256 hash of its contents.
+        files    : BTreeMap<String, String>,
+}
+
+/// Hashes everything written through it.
+struct HashingWriter<W: Write> {
+        inner : W,
+        hasher: Sha256,
+        size  : u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let written = self.inner.write(buf)?;
+                self.hasher.update(&buf[..written]);
+                self.size += written as u64;
+                Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+                self.inner.flush()
+        }
+}
+
+fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn hash<R: Read>(mut reader: R) -> io::Result<String> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut reader, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// ## ExtractionCache
+/// Keeps plugin archives extracted across runs, in `<dir>/<SHA-256 of the archive>`,
+/// so unchanged plugins are loaded without being extracted again.
+#[derive(Debug, Clone)]
+pub(crate) struct ExtractionCache {
+        pub(crate) dir  : PathBuf,
+        pub(crate) limit: u64,
+}
+
+impl ExtractionCache {
+        pub(crate) fn new(dir: PathBuf) -> Self {
+                Self { dir, limit: DEFAULT_CACHE_LIMIT }
+        }
+
+        /// Returns the directory the archive at `path` (Opened as `file` and `archive`) is
+        /// extracted to, extracting it first unless an intact entry is already there.
+        pub(crate) fn extract(
+                &self,
+                path   : &Path,
+                file   : &mut File,
+                archive: &mut ZipArchive<File>
+        ) -> Result<PathBuf, VPluginError> {
+                let key = hash(&mut *file).map_err(|e| VPluginError::io(path, e))?;
+                file.rewind().map_err(|e| VPluginError::io(path, e))?;
+                let entry = self.dir.join(&key);
+
+                match self.verify(&entry) {
+                        Ok (true)  => {
+                                log::trace!("Reusing extracted plugin from {}", entry.display());
+                                return Ok(entry);
+                        }
+                        Ok (false) => (),
+                        Err(e)     => {
+                                log::warn!("Cached plugin at {} is corrupted, extracting it again: {}", entry.display(), e);
+                                fs::remove_dir_all(&entry).map_err(|e| VPluginError::io(&entry, e))?;
+                        }
+                }
+
+                /* Extracted next to the entry and renamed, so a half-extracted entry is never used. */
+                let tmp = self.dir.join(format!("{}.tmp-{}", key, std::process::id()));
+                let manifest = Self::unpack(archive, path, &tmp).inspect_err(|_| {
+                        let _ = fs::remove_dir_all(&tmp);
+                })?;
+                let contents = toml::to_string(&manifest).map_err(|e| VPluginError::InternalError { err: e.to_string() })?;
+                fs::write(tmp.join(MANIFEST), contents).map_err(|e| VPluginError::io(&tmp, e))?;
+
+                if let Err(e) = fs::rename(&tmp, &entry) {
+                        /* Another process extracted the same archive meanwhile. */
+                        let _ = fs::remove_dir_all(&tmp);
+                        if !entry.is_dir() {
+                                return Err(VPluginError::io(&entry, e));
+                        }
+                }
+                self.evict(&key);
+                Ok(entry)
+        }
+
+        /// Extracts every file of `archive` (Read from `path`) into `dir`.
+        fn unpack(archive: &mut ZipArchive<File>, path: &Path, dir: &Path) -> Result<Manifest, VPluginError> {
+                fs::create_dir_all(dir).map_err(|e| VPluginError::io(dir, e))?;
+                let mut manifest = Manifest { last_used: now(), size: 0, files: BTreeMap::new() };
+                for i in 0..archive.len() {
+                        let mut file = archive
+                                .by_index(i)
+                                .map_err(|source| VPluginError::InvalidArchive { path: path.to_owned(), source })?;
+                        let Some(name) = file.enclosed_name().map(Path::to_owned) else {
+                                continue;
+                        };
+                        let outpath = dir.join(&name);
+                        if file.is_dir() {
+                                fs::create_dir_all(&outpath).map_err(|e| VPluginError::io(&outpath, e))?;
+                                continue;
+                        }
+                        if let Some(p) = outpath.parent() {
+                                fs::create_dir_all(p).map_err(|e| VPluginError::io(p, e))?;
+                        }
+
+                        let outfile = File::create(&outpath).map_err(|e| VPluginError::io(&outpath, e))?;
+                        let mut writer = HashingWriter { inner: outfile, hasher: Sha256::new(), size: 0 };
+                        io::copy(&mut file, &mut writer).map_err(|e| VPluginError::io(&outpath, e))?;
+
+                        manifest.size += writer.size;
+                        manifest.files.insert(name.to_string_lossy().into_owned(), format!("{:x}", writer.hasher.finalize()));
+                }
+                Ok(manifest)
+        }
+
+        fn read_manifest(entry: &Path) -> io::Result<Manifest> {
+                let contents = fs::read_to_string(entry.join(MANIFEST))?;
+                toml::from_str(&contents).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+        }
+
+        /// Checks that every file of the entry is still there and unmodified, returning
+        /// `false` if there's no such entry. Intact entries are marked as used.
+        fn verify(&self, entry: &Path) -> io::Result<bool> {
+                if !entry.is_dir() {
+                        return Ok(false);
+                }
+                let mut manifest = Self::read_manifest(entry)?;
+                for (name, expected) in &manifest.files {
+                        if hash(File::open(entry.join(name))?)? != *expected {
+                                return Err(io::Error::new(ErrorKind::InvalidData, format!("'{}' was modified", name)));
+                        }
+                }
+
+                manifest.last_used = now();
+                let contents = toml::to_string(&manifest).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                fs::write(entry.join(MANIFEST), contents)?;
+                Ok(true)
+        }
+
+        /// Removes the least recently used entries (Except `keep` and those in use) until the cache fits its limit.
+        fn evict(&self, keep: &str) {
+                let Ok(dir) = fs::read_dir(&self.dir) else {
+                        return;
+                };
+
+                let mut entries = Vec::new();
+                let mut total   = 0;
+                for entry in dir.filter_map(Result::ok) {
+                        let name = entry.file_name().to_string_lossy().into_owned();
+                        if name.contains(".tmp-") {
+                                continue;
+                        }
+                        match Self::read_manifest(&entry.path()) {
+                                Ok (m) => {
+                                        total += m.size;
+                                        if name != keep && !in_use(&entry.path()) {
+                                                entries.push((m.last_used, m.size, entry.path()));
+                                        }
+                                }
+                                /* Not something the cache can use anymore. */
+                                Err(_) if !in_use(&entry.path()) => entries.push((0, 0, entry.path())),
+                                Err(_) => (),
+                        }
+                }
+
+                entries.sort();
+                for (last_used, size, path) in entries {
+                        if total <= self.limit && last_used != 0 {
+                                break;
+                        }
+                        log::trace!("Evicting cached plugin {}", path.display());
+                        match fs::remove_dir_all(&path) {
+                                Ok (_) => total -= size,
+                                Err(e) => log::warn!("Couldn't remove cached plugin {}: {}", path.display(), e),
+                        }
+                }
+        }
+
+        /// Removes every entry of the cache.
+        pub(crate) fn clear(&self) -> Result<(), VPluginError> {
+                match fs::remove_dir_all(&self.dir) {
+                        Err(e) if e.kind() != ErrorKind::NotFound => Err(VPluginError::io(&self.dir, e)),
+                        _ => Ok(()),
+                }
+        }
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        fn cache(name: &str, limit: u64) -> ExtractionCache {
+                let dir = std::env::temp_dir().join(format!("vplugin-cache-{}-{}", std::process::id(), name));
+                let _ = fs::remove_dir_all(&dir);
+                fs::create_dir_all(&dir).unwrap();
+                ExtractionCache { dir, limit }
+        }
+
+        /// Adds an entry holding a single file, as if it was extracted at `last_used`.
+        fn add(cache: &ExtractionCache, key: &str, contents: &str, last_used: u64) -> PathBuf {
+                let entry = cache.dir.join(key);
+                fs::create_dir_all(&entry).unwrap();
+                fs::write(entry.join("plugin.so"), contents).unwrap();
+                let manifest = Manifest {
+                        last_used,
+                        size : contents.len() as u64,
+                        files: BTreeMap::from([("plugin.so".into(), hash(contents.as_bytes()).unwrap())]),
+                };
+                fs::write(entry.join(MANIFEST), toml::to_string(&manifest).unwrap()).unwrap();
+                entry
+        }
+
+        #[test]
+        fn verifies_entries() {
+                let cache = cache("verify", DEFAULT_CACHE_LIMIT);
+                assert!(!cache.verify(&cache.dir.join("missing")).unwrap());
+
+                let entry = add(&cache, "intact", "code", 1);
+                assert!(cache.verify(&entry).unwrap());
+                assert!(ExtractionCache::read_manifest(&entry).unwrap().last_used > 1);
+
+                let entry = add(&cache, "modified", "code", 1);
+                fs::write(entry.join("plugin.so"), "other code").unwrap();
+                assert!(cache.verify(&entry).is_err());
+
+                let entry = add(&cache, "removed", "code", 1);
+                fs::remove_file(entry.join("plugin.so")).unwrap();
+                assert!(cache.verify(&entry).is_err());
+                fs::remove_dir_all(&cache.dir).unwrap();
+        }
+
+        #[test]
+        fn evicts_least_recently_used() {
+                let cache = cache("evict", 20);
+                let old   = add(&cache, "old", "0123456789", 1);
+                let new   = add(&cache, "new", "0123456789", 3);
+                let kept  = add(&cache, "kept", "0123456789", 2);
+                let other = cache.dir.join("broken");
+                fs::create_dir_all(&other).unwrap();
+
+                cache.evict("kept");
+                assert!(!old.exists() && !other.exists());
+                assert!(new.exists() && kept.exists());
+                fs::remove_dir_all(&cache.dir).unwrap();
+        }
+
+        #[test]
+        fn never_evicts_entries_in_use() {
+                let cache = cache("in-use", 10);
+                let used  = add(&cache, "used", "0123456789", 1);
+                let old   = add(&cache, "old", "0123456789", 2);
+                let new   = add(&cache, "new", "0123456789", 3);
+                assert!(acquire(&used));
+
+                cache.evict("new");
+                assert!(used.exists() && new.exists());
+                assert!(!old.exists());
+
+                release(&used);
+                cache.evict("new");
+                assert!(!used.exists());
+                fs::remove_dir_all(&cache.dir).unwrap();
+        }
+}
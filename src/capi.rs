@@ -0,0 +1,472 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+//! # C API
+//! `extern "C"` bindings to VPlugin, for hosts written in C, C++ or any other
+//! language able to call C functions. Build them with the `capi` feature:
+//! ```text
+//! $ cargo rustc --lib --release --features capi --crate-type cdylib
+//! $ cargo rustc --lib --release --features capi --crate-type staticlib
+//! ```
+//! The header, `include/vplugin.h`, is regenerated by building with `VPLUGIN_UPDATE_HEADER=1`.
+//!
+//! Every function returns `VPLUGIN_OK` (`0`) on success, or one of the `VPLUGIN_ERR_*`
+//! codes, in which case `vplugin_last_error_message()` describes what went wrong.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use crate::error::VPluginError;
+use crate::hook::Hook;
+use crate::instance::{InstanceHook, PluginInstance};
+use crate::plugin::{Plugin, VPluginMetadata};
+use crate::plugin_manager::{PluginManager, VHook};
+
+/// The operation succeeded.
+pub const VPLUGIN_OK: c_int = 0;
+/// Invalid parameters were passed to the function.
+pub const VPLUGIN_ERR_PARAMETERS: c_int = 1;
+/// The plugin is not valid for this operation.
+pub const VPLUGIN_ERR_INVALID_PLUGIN: c_int = 2;
+/// The file requested couldn't be found.
+pub const VPLUGIN_ERR_NO_SUCH_FILE: c_int = 3;
+/// Access to a file was denied, or the host runs as root.
+pub const VPLUGIN_ERR_PERMISSION_DENIED: c_int = 4;
+/// The symbol requested is not exported by the plugin.
+pub const VPLUGIN_ERR_MISSING_SYMBOL: c_int = 5;
+/// The plugin's entry point failed.
+pub const VPLUGIN_ERR_FAILED_TO_INITIALIZE: c_int = 6;
+/// Any other error, including panics inside VPlugin.
+pub const VPLUGIN_ERR_INTERNAL: c_int = 7;
+/// A hook's declared signature doesn't match the expected one.
+pub const VPLUGIN_ERR_SIGNATURE_MISMATCH: c_int = 8;
+/// A hook reported a failure.
+pub const VPLUGIN_ERR_HOOK_FAILED: c_int = 9;
+/// The plugin was built for an incompatible ABI version.
+pub const VPLUGIN_ERR_INCOMPATIBLE_ABI: c_int = 10;
+/// A user setting doesn't match the plugin's configuration schema.
+pub const VPLUGIN_ERR_INVALID_CONFIG: c_int = 11;
+/// Object files can't be loaded from the work directory (It's mounted `noexec`).
+pub const VPLUGIN_ERR_NOT_EXECUTABLE: c_int = 12;
+/// An object file of the plugin is not a shared object built for the host's architecture.
+pub const VPLUGIN_ERR_INCOMPATIBLE_OBJECT: c_int = 13;
+/// An object file of the plugin needs a library that isn't installed.
+pub const VPLUGIN_ERR_MISSING_LIBRARY: c_int = 14;
+/// The plugin implements another version of an interface than the host.
+pub const VPLUGIN_ERR_INTERFACE_MISMATCH: c_int = 15;
+/// A data symbol of the plugin doesn't have the size or alignment of the type requested.
+pub const VPLUGIN_ERR_STATIC_MISMATCH: c_int = 16;
+
+/// Opaque handle to a plugin manager.
+pub struct VPluginManager {
+        inner: PluginManager,
+}
+
+/// Opaque handle to a loaded plugin.
+pub struct VPluginModule {
+        inner: Plugin,
+}
+
+/// Opaque handle to an instance of a plugin.
+pub struct VPluginInstance {
+        inner: PluginInstance,
+}
+
+/// Opaque handle to a hook of a plugin, keeping its object file loaded.
+pub struct VPluginHook {
+        inner: Hook<VHook>,
+}
+
+/// Opaque handle to a hook bound to an instance of a plugin, keeping its object file loaded.
+pub struct VPluginInstanceHook {
+        inner: InstanceHook,
+}
+
+thread_local! {
+        static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+        let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+        LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+fn fail(e: VPluginError) -> c_int {
+        set_last_error(e.report());
+        e.code()
+}
+
+/// Runs `f`, turning panics into `VPLUGIN_ERR_INTERNAL` so they never unwind into C.
+fn guard(f: impl FnOnce() -> Result<(), VPluginError>) -> c_int {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(Ok(()))  => VPLUGIN_OK,
+                Ok(Err(e))  => fail(e),
+                Err(_)      => {
+                        set_last_error("VPlugin panicked (See the log for details)".into());
+                        VPLUGIN_ERR_INTERNAL
+                }
+        }
+}
+
+unsafe fn string<'a>(s: *const c_char) -> Result<&'a str, VPluginError> {
+        if s.is_null() {
+                return Err(VPluginError::ParametersError);
+        }
+        CStr::from_ptr(s).to_str().map_err(|_| VPluginError::ParametersError)
+}
+
+unsafe fn handle<'a, T>(p: *mut T) -> Result<&'a mut T, VPluginError> {
+        p.as_mut().ok_or(VPluginError::ParametersError)
+}
+
+/// Returns a description of the last error that occurred on the calling thread,
+/// or NULL if no error occurred. The string stays valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn vplugin_last_error_message() -> *const c_char {
+        LAST_ERROR.with(|e| e.borrow().as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null()))
+}
+
+/// Creates a new plugin manager, or returns NULL on failure.
+#[no_mangle]
+pub extern "C" fn vplugin_manager_new() -> *mut VPluginManager {
+        let mut manager = ptr::null_mut();
+        guard(|| {
+                manager = Box::into_raw(Box::new(VPluginManager { inner: PluginManager::try_new()? }));
+                Ok(())
+        });
+        manager
+}
+
+/// Sets the name of the plugins' entry point.
+///
+/// # Safety
+/// `manager` must come from `vplugin_manager_new`, and `entry_point` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_set_entry_point(manager: *mut VPluginManager, entry_point: *const c_char) -> c_int {
+        guard(|| {
+                handle(manager)?.inner.set_entry_point(string(entry_point)?);
+                Ok(())
+        })
+}
+
+/// Sets the directory plugins are extracted to, instead of `<temp>/vplugin`.
+///
+/// # Safety
+/// `manager` must come from `vplugin_manager_new`, and `dir` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_set_work_dir(manager: *mut VPluginManager, dir: *const c_char) -> c_int {
+        guard(|| {
+                handle(manager)?.inner.set_work_dir(string(dir)?);
+                Ok(())
+        })
+}
+
+/// Loads object files from memory instead of the work directory if `enabled`
+/// is non-zero (Linux only).
+///
+/// # Safety
+/// `manager` must come from `vplugin_manager_new`.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_set_memfd_loading(manager: *mut VPluginManager, enabled: c_int) -> c_int {
+        guard(|| {
+                handle(manager)?.inner.set_memfd_loading(enabled != 0);
+                Ok(())
+        })
+}
+
+/// Loads the plugin at `filename`, storing its handle in `plugin`.
+/// The handle must be released with `vplugin_plugin_free`, unless it is registered.
+///
+/// # Safety
+/// `manager` must come from `vplugin_manager_new`, `filename` must be a valid
+/// C string and `plugin` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_load_plugin(
+        manager : *mut VPluginManager,
+        filename: *const c_char,
+        plugin  : *mut *mut VPluginModule
+) -> c_int {
+        guard(|| {
+                if plugin.is_null() {
+                        return Err(VPluginError::ParametersError);
+                }
+                let loaded = handle(manager)?.inner.load_plugin(string(filename)?)?;
+                plugin.write(Box::into_raw(Box::new(VPluginModule { inner: loaded })));
+                Ok(())
+        })
+}
+
+/// Runs the entry point of a plugin.
+///
+/// # Safety
+/// `manager` and `plugin` must be valid handles.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_begin_plugin(manager: *mut VPluginManager, plugin: *mut VPluginModule) -> c_int {
+        guard(|| {
+                let manager = handle(manager)?;
+                manager.inner.begin_plugin(&mut handle(plugin)?.inner)
+        })
+}
+
+/// Looks up a hook (`int hook(void *)`) in a started plugin, storing its handle in `hook`.
+/// The plugin's object file stays loaded until the handle is released with `vplugin_hook_free`.
+///
+/// # Safety
+/// `manager` and `plugin` must be valid handles, `name` a valid C
+/// string and `hook` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_get_hook(
+        manager: *mut VPluginManager,
+        plugin : *mut VPluginModule,
+        name   : *const c_char,
+        hook   : *mut *mut VPluginHook
+) -> c_int {
+        guard(|| {
+                if hook.is_null() {
+                        return Err(VPluginError::ParametersError);
+                }
+                let found = handle(manager)?.inner.hook::<VHook>(&handle(plugin)?.inner, string(name)?)?;
+                hook.write(Box::into_raw(Box::new(VPluginHook { inner: found })));
+                Ok(())
+        })
+}
+
+/// Calls a hook with `data`, storing what it returned in `result`.
+/// Fails with `VPLUGIN_ERR_INVALID_PLUGIN` if the plugin was terminated meanwhile.
+///
+/// # Safety
+/// `hook` must be a valid handle, `data` what the hook expects
+/// and `result` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_hook_call(hook: *mut VPluginHook, data: *mut c_void, result: *mut c_int) -> c_int {
+        guard(|| {
+                let hook = &handle(hook)?.inner;
+                if result.is_null() {
+                        return Err(VPluginError::ParametersError);
+                }
+                if !hook.is_alive() {
+                        return Err(VPluginError::invalid_plugin(hook.plugin(), "terminated since the hook was looked up"));
+                }
+                result.write(hook.call((data,)));
+                Ok(())
+        })
+}
+
+/// Releases a hook handle. Passing NULL does nothing.
+///
+/// # Safety
+/// `hook` must be a valid handle or NULL, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_hook_free(hook: *mut VPluginHook) {
+        if !hook.is_null() {
+                guard(|| {
+                        drop(Box::from_raw(hook));
+                        Ok(())
+                });
+        }
+}
+
+/// Creates an instance of the registered plugin named `name`, storing its handle in `instance`.
+/// The handle must be released with `vplugin_instance_free`.
+///
+/// # Safety
+/// `manager` must be a valid handle, `name` a valid C string
+/// and `instance` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_instantiate(
+        manager : *mut VPluginManager,
+        name    : *const c_char,
+        instance: *mut *mut VPluginInstance
+) -> c_int {
+        guard(|| {
+                if instance.is_null() {
+                        return Err(VPluginError::ParametersError);
+                }
+                let created = handle(manager)?.inner.instantiate(string(name)?)?;
+                instance.write(Box::into_raw(Box::new(VPluginInstance { inner: created })));
+                Ok(())
+        })
+}
+
+/// Looks up a hook (`int hook(void *instance, void *)`) of the plugin `instance` belongs to,
+/// storing its handle, bound to the instance, in `hook`. The plugin's object file stays
+/// loaded until the handle is released with `vplugin_instance_hook_free`.
+///
+/// # Safety
+/// `manager` and `instance` must be valid handles, `name` a valid
+/// C string and `hook` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_get_instance_hook(
+        manager : *mut VPluginManager,
+        instance: *mut VPluginInstance,
+        name    : *const c_char,
+        hook    : *mut *mut VPluginInstanceHook
+) -> c_int {
+        guard(|| {
+                if hook.is_null() {
+                        return Err(VPluginError::ParametersError);
+                }
+                let found = handle(manager)?.inner.get_instance_hook(&handle(instance)?.inner, string(name)?)?;
+                hook.write(Box::into_raw(Box::new(VPluginInstanceHook { inner: found })));
+                Ok(())
+        })
+}
+
+/// Calls a hook with its instance and `data`, storing what it returned in `result`.
+/// Fails with `VPLUGIN_ERR_INVALID_PLUGIN` if the plugin was terminated meanwhile.
+///
+/// # Safety
+/// `hook` must be a valid handle, its instance must not have been freed, `data`
+/// must be what the hook expects and `result` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_instance_hook_call(hook: *mut VPluginInstanceHook, data: *mut c_void, result: *mut c_int) -> c_int {
+        guard(|| {
+                let hook = &handle(hook)?.inner;
+                if result.is_null() {
+                        return Err(VPluginError::ParametersError);
+                }
+                if !hook.is_alive() {
+                        return Err(VPluginError::invalid_plugin(hook.plugin(), "terminated since the hook was looked up"));
+                }
+                result.write(hook.call(data));
+                Ok(())
+        })
+}
+
+/// Releases an instance hook handle. Passing NULL does nothing.
+///
+/// # Safety
+/// `hook` must be a valid handle or NULL, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_instance_hook_free(hook: *mut VPluginInstanceHook) {
+        if !hook.is_null() {
+                guard(|| {
+                        drop(Box::from_raw(hook));
+                        Ok(())
+                });
+        }
+}
+
+/// Returns the pointer the plugin returned for the instance, or NULL if `instance` is NULL.
+///
+/// # Safety
+/// `instance` must be a valid handle or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_instance_ptr(instance: *mut VPluginInstance) -> *mut c_void {
+        instance.as_ref().map(|i| i.inner.as_ptr()).unwrap_or(ptr::null_mut())
+}
+
+/// Destroys an instance and releases its handle. Passing NULL does nothing.
+///
+/// # Safety
+/// `instance` must be a valid handle or NULL, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_instance_free(instance: *mut VPluginInstance) {
+        if !instance.is_null() {
+                guard(|| {
+                        drop(Box::from_raw(instance));
+                        Ok(())
+                });
+        }
+}
+
+/// Hands a plugin over to the manager, which will terminate it on shutdown.
+/// The plugin handle is consumed and must not be used afterwards, even on failure.
+///
+/// # Safety
+/// `manager` and `plugin` must be valid handles.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_register_plugin(manager: *mut VPluginManager, plugin: *mut VPluginModule) -> c_int {
+        guard(|| {
+                if plugin.is_null() {
+                        return Err(VPluginError::ParametersError);
+                }
+                let plugin = Box::from_raw(plugin).inner;
+                handle(manager)?.inner.register_plugin(plugin)
+        })
+}
+
+/// Terminates a started plugin, calling its destructor.
+///
+/// # Safety
+/// `plugin` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_plugin_terminate(plugin: *mut VPluginModule) -> c_int {
+        guard(|| handle(plugin)?.inner.terminate())
+}
+
+/// Copies the metadata of a loaded plugin into `*metadata`, to be released with
+/// `vplugin_metadata_free()`. Strings are UTF-8 and not NUL-terminated.
+///
+/// # Safety
+/// `plugin` must be a valid handle, and `metadata` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_plugin_get_metadata(plugin: *mut VPluginModule, metadata: *mut VPluginMetadata) -> c_int {
+        guard(|| {
+                if metadata.is_null() {
+                        return Err(VPluginError::ParametersError);
+                }
+                let plugin = &handle(plugin)?.inner;
+                let Some(m) = plugin.get_metadata() else {
+                        return Err(VPluginError::invalid_plugin(plugin.display_name(), "Metadata not loaded"));
+                };
+                metadata.write(m.to_ffi());
+                Ok(())
+        })
+}
+
+/// Releases metadata returned by `vplugin_plugin_get_metadata()`. Passing NULL does nothing.
+///
+/// # Safety
+/// `metadata` must be NULL or filled by `vplugin_plugin_get_metadata()`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_metadata_free(metadata: *mut VPluginMetadata) {
+        if !metadata.is_null() {
+                guard(|| {
+                        ptr::drop_in_place(metadata);
+                        Ok(())
+                });
+        }
+}
+
+/// Releases a plugin that was not registered. Passing NULL does nothing.
+///
+/// # Safety
+/// `plugin` must be a valid handle or NULL, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_plugin_free(plugin: *mut VPluginModule) {
+        if !plugin.is_null() {
+                guard(|| {
+                        drop(Box::from_raw(plugin));
+                        Ok(())
+                });
+        }
+}
+
+/// Terminates every registered plugin and destroys the manager. Passing NULL does nothing.
+///
+/// # Safety
+/// `manager` must be a valid handle or NULL, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vplugin_manager_shutdown(manager: *mut VPluginManager) {
+        if !manager.is_null() {
+                guard(|| {
+                        Box::from_raw(manager).inner.shutdown();
+                        Ok(())
+                });
+        }
+}
@@ -0,0 +1,378 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_void};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use serde_derive::Deserialize;
+use crate::error::VPluginError;
+use crate::host::PluginContext;
+
+/// The name of the file plugins may declare their settings in,
+/// instead of a `[config]` table in `metadata.toml`.
+pub(crate) const SCHEMA_FILE: &str = "config.schema.toml";
+
+/// The name of the file holding the user's settings, inside the plugin's config directory.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// The type of a setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigType {
+        String,
+        Integer,
+        Float,
+        Boolean,
+}
+
+/// The value of a setting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigValue {
+        String(String),
+        Integer(i64),
+        Float(f64),
+        Boolean(bool),
+}
+
+impl ConfigValue {
+        /// Converts a TOML value to a setting of type `kind`. Integers are
+        /// accepted for floats, anything else must match exactly.
+        fn from_toml(value: &toml::Value, kind: ConfigType) -> Option<Self> {
+                match (kind, value) {
+                        (ConfigType::String , toml::Value::String(s))  => Some(Self::String(s.clone())),
+                        (ConfigType::Integer, toml::Value::Integer(i)) => Some(Self::Integer(*i)),
+                        (ConfigType::Float  , toml::Value::Float(f))   => Some(Self::Float(*f)),
+                        (ConfigType::Float  , toml::Value::Integer(i)) => Some(Self::Float(*i as f64)),
+                        (ConfigType::Boolean, toml::Value::Boolean(b)) => Some(Self::Boolean(*b)),
+                        _ => None,
+                }
+        }
+
+        fn to_toml(&self) -> toml::Value {
+                match self {
+                        Self::String(s)  => toml::Value::String(s.clone()),
+                        Self::Integer(i) => toml::Value::Integer(*i),
+                        Self::Float(f)   => toml::Value::Float(*f),
+                        Self::Boolean(b) => toml::Value::Boolean(*b),
+                }
+        }
+
+        fn as_number(&self) -> Option<f64> {
+                match self {
+                        Self::Integer(i) => Some(*i as f64),
+                        Self::Float(f)   => Some(*f),
+                        _ => None,
+                }
+        }
+}
+
+impl fmt::Display for ConfigValue {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                        Self::String(s)  => write!(f, "{}", s),
+                        Self::Integer(i) => write!(f, "{}", i),
+                        Self::Float(v)   => write!(f, "{}", v),
+                        Self::Boolean(b) => write!(f, "{}", b),
+                }
+        }
+}
+
+/// The resolved settings of a plugin, mapped by name.
+pub type Config = BTreeMap<String, ConfigValue>;
+
+/// This is purely for deserialization.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawSetting {
+        #[serde(rename = "type")]
+        kind       : ConfigType,
+        default    : toml::Value,
+        description: Option<String>,
+        #[serde(default)]
+        choices    : Vec<toml::Value>,
+        min        : Option<f64>,
+        max        : Option<f64>,
+}
+
+/// `config.schema.toml`, with the same `[config]` table as `metadata.toml`.
+#[derive(Deserialize)]
+pub(crate) struct SchemaFile {
+        #[serde(default)]
+        pub(crate) config: BTreeMap<String, RawSetting>,
+}
+
+/// A setting declared by a plugin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigSetting {
+        pub name       : String,
+        pub kind       : ConfigType,
+        /// The value used unless the user overrides it.
+        pub default    : ConfigValue,
+        pub description: Option<String>,
+        /// The only values allowed, if not empty.
+        pub choices    : Vec<ConfigValue>,
+        /// The smallest value allowed for numbers.
+        pub min        : Option<f64>,
+        /// The largest value allowed for numbers.
+        pub max        : Option<f64>,
+}
+
+impl ConfigSetting {
+        /// Checks `value` against the setting, returning why it's not allowed.
+        fn check(&self, value: &toml::Value) -> Result<ConfigValue, String> {
+                let value = ConfigValue::from_toml(value, self.kind)
+                        .ok_or_else(|| format!("expected {:?}, found {}", self.kind, value.type_str()))?;
+
+                if !self.choices.is_empty() && !self.choices.contains(&value) {
+                        let choices: Vec<String> = self.choices.iter().map(|c| c.to_string()).collect();
+                        return Err(format!("'{}' is not one of {}", value, choices.join(", ")));
+                }
+                if let Some(number) = value.as_number() {
+                        if self.min.is_some_and(|min| number < min) || self.max.is_some_and(|max| number > max) {
+                                return Err(format!(
+                                        "{} is out of range ({} to {})",
+                                        number,
+                                        self.min.map(|m| m.to_string()).unwrap_or_else(|| "-".into()),
+                                        self.max.map(|m| m.to_string()).unwrap_or_else(|| "-".into())
+                                ));
+                        }
+                }
+                Ok(value)
+        }
+}
+
+/// ## ConfigSchema
+/// The settings a plugin declares, either in a `[config]` table of its
+/// `metadata.toml` or in a `config.schema.toml` file:
+/// ```toml
+/// [config.font_size]
+/// type        = "integer"
+/// default     = 12
+/// description = "Size of the editor's font"
+/// min         = 6
+/// max         = 72
+///
+/// [config.theme]
+/// type    = "string"
+/// default = "dark"
+/// choices = [ "dark", "light" ]
+/// ```
+/// Hosts can enumerate the settings, for example to generate a settings page.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigSchema {
+        settings: Vec<ConfigSetting>,
+}
+
+impl ConfigSchema {
+        pub(crate) const EMPTY: Self = Self { settings: Vec::new() };
+
+        /// Parses the settings declared by `plugin`, checking their defaults.
+        pub(crate) fn parse(plugin: &str, raw: BTreeMap<String, RawSetting>) -> Result<Self, VPluginError> {
+                let mut settings = Vec::with_capacity(raw.len());
+                for (name, raw) in raw {
+                        let invalid = |reason: String| {
+                                log::error!("Setting '{}' of plugin '{}' is invalid: {}", name, plugin, reason);
+                                VPluginError::invalid_plugin(plugin, format!("setting '{}' is invalid: {}", name, reason))
+                        };
+                        let convert = |value: &toml::Value| ConfigValue::from_toml(value, raw.kind)
+                                .ok_or_else(|| invalid(format!("{} is not of type {:?}", value, raw.kind)));
+
+                        let mut setting = ConfigSetting {
+                                name       : name.clone(),
+                                kind       : raw.kind,
+                                default    : convert(&raw.default)?,
+                                description: raw.description,
+                                choices    : raw.choices.iter().map(convert).collect::<Result<_, _>>()?,
+                                min        : raw.min,
+                                max        : raw.max,
+                        };
+                        /* The default must satisfy the constraints as well. */
+                        setting.default = setting.check(&raw.default).map_err(|e| invalid(format!("default value {}", e)))?;
+                        settings.push(setting);
+                }
+                Ok(Self { settings })
+        }
+
+        /// Returns every setting, sorted by name.
+        pub fn settings(&self) -> &[ConfigSetting] {
+                &self.settings
+        }
+
+        /// Returns the setting named `name`.
+        pub fn get(&self, name: &str) -> Option<&ConfigSetting> {
+                self.settings.iter().find(|s| s.name == name)
+        }
+
+        pub fn is_empty(&self) -> bool {
+                self.settings.is_empty()
+        }
+
+        /// Returns the default value of every setting.
+        pub fn defaults(&self) -> Config {
+                self.settings.iter().map(|s| (s.name.clone(), s.default.clone())).collect()
+        }
+
+        /// Merges the user's settings from `<config_dir>/config.toml` over the defaults.
+        /// Unknown settings are ignored, but invalid values are refused.
+        pub(crate) fn resolve(&self, plugin: &str, config_dir: Option<&Path>) -> Result<Config, VPluginError> {
+                let mut config = self.defaults();
+                let Some(path) = config_dir.map(|d| d.join(CONFIG_FILE)) else {
+                        return Ok(config);
+                };
+
+                let contents = match fs::read_to_string(&path) {
+                        Ok (c) => c,
+                        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(config),
+                        Err(e) => return Err(VPluginError::io(path, e)),
+                };
+                let overrides: toml::value::Table = toml::from_str(&contents)
+                        .map_err(|e| VPluginError::InvalidMetadata { path: path.clone(), source: e })?;
+
+                for (name, value) in overrides {
+                        let Some(setting) = self.get(&name) else {
+                                log::warn!("Ignoring unknown setting '{}' of plugin '{}' in {}.", name, plugin, path.display());
+                                continue;
+                        };
+                        match setting.check(&value) {
+                                Ok (value)  => { config.insert(name, value); },
+                                Err(reason) => {
+                                        log::error!("Invalid value for setting '{}' of plugin '{}': {}", name, plugin, reason);
+                                        return Err(VPluginError::InvalidConfig { plugin: plugin.to_owned(), setting: name, reason });
+                                }
+                        }
+                }
+                Ok(config)
+        }
+}
+
+/// Serializes a resolved configuration as a TOML document.
+pub(crate) fn to_toml(config: &Config) -> String {
+        let table: toml::value::Table = config.iter().map(|(k, v)| (k.clone(), v.to_toml())).collect();
+        toml::to_string(&toml::Value::Table(table)).unwrap_or_default()
+}
+
+/// Host callback: returns the plugin's resolved settings as a TOML
+/// document, or NULL if it doesn't declare any.
+pub(crate) unsafe extern "C" fn get_config(context: *mut c_void) -> *const c_char {
+        match PluginContext::from_raw(context).and_then(|c| c.config.as_ref()) {
+                Some(config) => config.as_ptr(),
+                None         => std::ptr::null(),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+        use std::path::PathBuf;
+        use super::*;
+
+        const SCHEMA: &str = r#"
+                [config.font_size]
+                type    = "integer"
+                default = 12
+                min     = 6
+                max     = 72
+
+                [config.theme]
+                type    = "string"
+                default = "dark"
+                choices = [ "dark", "light" ]
+
+                [config.scale]
+                type    = "float"
+                default = 1
+        "#;
+
+        fn schema(contents: &str) -> Result<ConfigSchema, VPluginError> {
+                let file: SchemaFile = toml::from_str(contents).unwrap();
+                ConfigSchema::parse("test", file.config)
+        }
+
+        /// A config directory holding `contents` as the user's settings.
+        fn config_dir(name: &str, contents: &str) -> PathBuf {
+                let dir = std::env::temp_dir().join(format!("vplugin-config-{}-{}", std::process::id(), name));
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join(CONFIG_FILE), contents).unwrap();
+                dir
+        }
+
+        #[test]
+        fn parses_settings() {
+                let schema = schema(SCHEMA).unwrap();
+                let names: Vec<&str> = schema.settings().iter().map(|s| s.name.as_str()).collect();
+                assert_eq!(names, ["font_size", "scale", "theme"]);
+
+                let theme = schema.get("theme").unwrap();
+                assert_eq!(theme.kind, ConfigType::String);
+                assert_eq!(theme.choices, [ConfigValue::String("dark".into()), ConfigValue::String("light".into())]);
+                assert_eq!(schema.get("font_size").unwrap().min, Some(6.0));
+                /* Integers are accepted for floats. */
+                assert_eq!(schema.get("scale").unwrap().default, ConfigValue::Float(1.0));
+        }
+
+        #[test]
+        fn refuses_invalid_defaults() {
+                let out_of_range = "[config.size]\ntype = \"integer\"\ndefault = 100\nmax = 72";
+                let not_a_choice = "[config.theme]\ntype = \"string\"\ndefault = \"blue\"\nchoices = [ \"dark\" ]";
+                let wrong_type   = "[config.size]\ntype = \"integer\"\ndefault = \"big\"";
+                for contents in [out_of_range, not_a_choice, wrong_type] {
+                        assert!(matches!(schema(contents), Err(VPluginError::InvalidPlugin { .. })), "{}", contents);
+                }
+        }
+
+        #[test]
+        fn resolves_defaults_without_overrides() {
+                let schema = schema(SCHEMA).unwrap();
+                assert_eq!(schema.resolve("test", None).unwrap(), schema.defaults());
+
+                let missing = std::env::temp_dir().join(format!("vplugin-config-{}-missing", std::process::id()));
+                assert_eq!(schema.resolve("test", Some(&missing)).unwrap(), schema.defaults());
+        }
+
+        #[test]
+        fn resolves_overrides() {
+                let schema = schema(SCHEMA).unwrap();
+                let dir    = config_dir("overrides", "font_size = 14\ntheme = \"light\"\nunknown = true\n");
+                let config = schema.resolve("test", Some(&dir)).unwrap();
+                fs::remove_dir_all(&dir).unwrap();
+
+                assert_eq!(config["font_size"], ConfigValue::Integer(14));
+                assert_eq!(config["theme"], ConfigValue::String("light".into()));
+                assert_eq!(config["scale"], ConfigValue::Float(1.0));
+                assert!(!config.contains_key("unknown"));
+        }
+
+        #[test]
+        fn refuses_invalid_overrides() {
+                let schema = schema(SCHEMA).unwrap();
+                for (name, contents) in [
+                        ("choice", "theme = \"blue\""),
+                        ("min", "font_size = 2"),
+                        ("max", "font_size = 100"),
+                        ("type", "font_size = \"big\""),
+                ] {
+                        let dir    = config_dir(name, contents);
+                        let result = schema.resolve("test", Some(&dir));
+                        fs::remove_dir_all(&dir).unwrap();
+                        match result {
+                                Err(VPluginError::InvalidConfig { setting, .. }) => assert!(contents.starts_with(&setting)),
+                                other => panic!("{}: {:?}", contents, other),
+                        }
+                }
+        }
+}
@@ -15,6 +15,8 @@
 */
 
 extern crate thiserror;
+use std::io;
+use std::path::PathBuf;
 use thiserror::Error;
 
 /// ## **Generic error code enum**
@@ -26,9 +28,12 @@ use thiserror::Error;
 /// ## Error Handling
 /// If a function from VPlugin returned an `Err` with this enum, then you are
 /// advised to see what the error is (There is a `#derive(Debug)` also used there).
-/// If an `InternalError` is returned, then take a look at the `String` parameter instead.
+/// Most variants name the plugin or file involved, and errors caused by another one
+/// (Like an `io::Error`) make it available through [`source()`](std::error::Error::source).
+/// [report](VPluginError::report) formats the whole chain on a single line.
+///
+/// For FFI, [code](VPluginError::code) returns a number that stays the same across releases.
 #[derive(Error, Debug)]
-#[repr(C)]
 pub enum VPluginError {
         /// Invalid parameters passed to the function,
         /// only useful for FFI calls.
@@ -36,24 +41,240 @@ pub enum VPluginError {
         ParametersError,
         /// The plugin given is not valid
         /// for this operation.
-        #[error("Invalid plugin requested")]
-        InvalidPlugin,
+        #[error("Invalid plugin '{plugin}': {reason}")]
+        InvalidPlugin {plugin: String, reason: String},
+        /// The plugin's archive couldn't be read.
+        #[error("'{}' is not a valid plugin archive", path.display())]
+        InvalidArchive {path: PathBuf, #[source] source: zip::result::ZipError},
+        /// The plugin's `metadata.toml` couldn't be parsed.
+        #[error("Invalid metadata in '{}'", path.display())]
+        InvalidMetadata {path: PathBuf, #[source] source: toml::de::Error},
+        /// The plugin's object file couldn't be loaded.
+        #[error("Couldn't load the object file of plugin '{plugin}'")]
+        LoadFailed {plugin: String, #[source] source: libloading::Error},
+        /// A library bundled with the plugin couldn't be loaded.
+        #[error("Couldn't load library '{library}' bundled with plugin '{plugin}'")]
+        LibraryLoadFailed {plugin: String, library: String, #[source] source: libloading::Error},
+        /// The plugin's object file (Or a library bundled with it) couldn't
+        /// be loaded into its own namespace.
+        #[error("Couldn't load '{object}' of plugin '{plugin}' into its own namespace: {reason}")]
+        NamespaceFailed {plugin: String, object: String, reason: String},
         /// The file requested is not available.
-        #[error("File requested couldn't be accessed")]
-        NoSuchFile,
+        #[error("File '{}' couldn't be accessed", path.display())]
+        NoSuchFile {path: PathBuf, #[source] source: io::Error},
         /// You do not have permission to access something
         /// on the host system.
-        #[error("Access denied on requested permissions")]
-        PermissionDenied,
+        #[error("Access denied to '{}'", path.display())]
+        PermissionDenied {path: PathBuf, #[source] source: io::Error},
+        /// The host runs as root, which VPlugin refuses (Plugins would run with its privileges).
+        #[error("VPlugin may not be run with superuser privileges")]
+        RunningAsSuperuser,
+        /// Any other I/O error.
+        #[error("I/O error on '{}'", path.display())]
+        Io {path: PathBuf, #[source] source: io::Error},
+        /// The file VPlugin keeps the state of plugins in couldn't be parsed.
+        #[error("Invalid plugin state in '{}'", path.display())]
+        InvalidState {path: PathBuf, #[source] source: toml::de::Error},
         /// The symbol requested is not present in the raw
         /// object file.
-        #[error("Symbol requested is not present in the plugin")]
-        MissingSymbol,
-        /// The plugin failed to initialize.
-        #[error("Plugin failed to initialize")]
-        FailedToInitialize,
+        #[error("Symbol '{symbol}' is not present in plugin '{plugin}'")]
+        MissingSymbol {plugin: String, symbol: String},
+        /// The signature a plugin declared for a hook does not
+        /// match the one expected by the host.
+        #[error("Hook '{hook}' of plugin '{plugin}' is declared as '{declared}', but the host expects '{expected}'")]
+        SignatureMismatch {plugin: String, hook: String, declared: String, expected: String},
+        /// The plugin's entry point returned `code` instead of `0`, along with
+        /// the message the plugin reported, if any (See [VPluginHost](crate::VPluginHost)).
+        #[error("Plugin '{plugin}' failed to initialize (code {code}){}", reason(.message))]
+        FailedToInitialize {plugin: String, code: i32, message: Option<String>},
+        /// The plugin has already been started.
+        #[error("Plugin '{plugin}' has already been started")]
+        AlreadyStarted {plugin: String},
+        /// A hook reported a failure while being dispatched
+        /// through a [HookRegistry](crate::HookRegistry).
+        #[error("Hook '{hook}' failed in plugin '{plugin}' (code {code}){}", reason(.message))]
+        HookFailed {plugin: String, hook: String, code: i64, message: Option<String>},
+        /// The plugin was built for an ABI version the host
+        /// doesn't support (And no compatibility shim accepted it).
+        #[error("Plugin '{plugin}' targets ABI version {found} ({build}), but the host uses version {expected}")]
+        IncompatibleAbi {plugin: String, found: u32, expected: u32, build: String},
+        /// A user setting doesn't match the plugin's configuration schema.
+        #[error("Invalid value for setting '{setting}' of plugin '{plugin}': {reason}")]
+        InvalidConfig {plugin: String, setting: String, reason: String},
+        /// Object files can't be loaded from the directory, because
+        /// its filesystem is mounted `noexec`.
+        #[error("Plugins can't be loaded from '{}', which is on a filesystem mounted noexec", path.display())]
+        NotExecutable {path: PathBuf},
+        /// An object file of the plugin was built for another architecture than the host's.
+        #[error("'{object}' of plugin '{plugin}' was built for {found}, but the host needs {expected}")]
+        WrongArchitecture {plugin: String, object: String, found: String, expected: String},
+        /// An object file of the plugin is not a shared object (eg. an executable).
+        #[error("'{object}' of plugin '{plugin}' is not a shared object ({kind})")]
+        NotSharedObject {plugin: String, object: String, kind: String},
+        /// An object file of the plugin needs a library that is
+        /// neither bundled with it nor installed on the system.
+        #[error("'{object}' of plugin '{plugin}' needs library '{library}', which is neither bundled nor installed")]
+        MissingLibrary {plugin: String, object: String, library: String},
+        /// The plugin implements an interface with another layout than the host's
+        /// (See [plugin_interface!](crate::plugin_interface)).
+        #[error("Plugin '{plugin}' implements another version of interface '{interface}' than the host")]
+        InterfaceMismatch {plugin: String, interface: String},
+        /// A data symbol of the plugin can't be read as the type requested
+        /// (See [get_static](crate::Plugin::get_static)).
+        #[error("Symbol '{symbol}' of plugin '{plugin}' can't be read as the type requested: {reason}")]
+        StaticMismatch {plugin: String, symbol: String, reason: String},
         /// Internal error: See the `String` parameter
         /// to determine what the error is.
         #[error("Internal error: {err:?}")]
         InternalError {err: String},
-}
\ No newline at end of file
+}
+
+/// Formats the message a plugin reported along with a failure, if any.
+fn reason(message: &Option<String>) -> String {
+        match message {
+                Some(m) => format!(": {}", m),
+                None    => String::new(),
+        }
+}
+
+impl VPluginError {
+        /// Returns a stable numeric code for the error, as used by the C API.
+        /// `0` is never returned, as it represents success.
+        pub fn code(&self) -> i32 {
+                match self {
+                        Self::ParametersError           => 1,
+                        Self::InvalidPlugin { .. }
+                        | Self::InvalidArchive { .. }
+                        | Self::InvalidMetadata { .. }
+                        | Self::LoadFailed { .. }
+                        | Self::LibraryLoadFailed { .. }
+                        | Self::NamespaceFailed { .. }  => 2,
+                        Self::NoSuchFile { .. }         => 3,
+                        Self::PermissionDenied { .. }
+                        | Self::RunningAsSuperuser      => 4,
+                        Self::MissingSymbol { .. }      => 5,
+                        Self::FailedToInitialize { .. }
+                        | Self::AlreadyStarted { .. }   => 6,
+                        Self::InternalError { .. }
+                        | Self::Io { .. }
+                        | Self::InvalidState { .. }     => 7,
+                        Self::SignatureMismatch { .. }  => 8,
+                        Self::HookFailed { .. }         => 9,
+                        Self::IncompatibleAbi { .. }    => 10,
+                        Self::InvalidConfig { .. }      => 11,
+                        Self::NotExecutable { .. }      => 12,
+                        Self::WrongArchitecture { .. }
+                        | Self::NotSharedObject { .. }  => 13,
+                        Self::MissingLibrary { .. }     => 14,
+                        Self::InterfaceMismatch { .. }  => 15,
+                        Self::StaticMismatch { .. }     => 16,
+                }
+        }
+
+        /// Formats the error along with every error that caused it,
+        /// eg. `File 'x.vpl' couldn't be accessed: No such file or directory (os error 2)`.
+        pub fn report(&self) -> String {
+                let mut report = self.to_string();
+                let mut source = std::error::Error::source(self);
+                while let Some(e) = source {
+                        report.push_str(": ");
+                        report.push_str(&e.to_string());
+                        source = e.source();
+                }
+                report
+        }
+
+        /// Wraps an I/O error on `path`, keeping the common cases distinguishable.
+        pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+                let path = path.into();
+                match source.kind() {
+                        io::ErrorKind::NotFound         => Self::NoSuchFile { path, source },
+                        io::ErrorKind::PermissionDenied => Self::PermissionDenied { path, source },
+                        _                               => Self::Io { path, source },
+                }
+        }
+
+        pub(crate) fn invalid_plugin(plugin: impl Into<String>, reason: impl Into<String>) -> Self {
+                Self::InvalidPlugin { plugin: plugin.into(), reason: reason.into() }
+        }
+
+        pub(crate) fn missing_symbol(plugin: impl Into<String>, symbol: impl Into<String>) -> Self {
+                Self::MissingSymbol { plugin: plugin.into(), symbol: symbol.into() }
+        }
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        fn errors() -> Vec<VPluginError> {
+                let (plugin, path) = (String::from("test"), PathBuf::from("test.vpl"));
+                let io = |kind| VPluginError::io(&path, io::Error::from(kind));
+                vec![
+                        VPluginError::ParametersError,
+                        VPluginError::invalid_plugin("test", "reason"),
+                        VPluginError::InvalidArchive { path: path.clone(), source: zip::result::ZipError::FileNotFound },
+                        VPluginError::InvalidMetadata { path: path.clone(), source: toml::from_str::<toml::Value>("=").unwrap_err() },
+                        VPluginError::LoadFailed { plugin: plugin.clone(), source: libloading::Error::DlOpenUnknown },
+                        io(io::ErrorKind::NotFound),
+                        io(io::ErrorKind::PermissionDenied),
+                        VPluginError::RunningAsSuperuser,
+                        VPluginError::missing_symbol("test", "symbol"),
+                        VPluginError::FailedToInitialize { plugin: plugin.clone(), code: 1, message: None },
+                        io(io::ErrorKind::Other),
+                        VPluginError::InvalidState { path: path.clone(), source: toml::from_str::<toml::Value>("=").unwrap_err() },
+                        VPluginError::SignatureMismatch {
+                                plugin  : plugin.clone(),
+                                hook    : "hook".into(),
+                                declared: "fn()".into(),
+                                expected: "fn(i32)".into()
+                        },
+                        VPluginError::HookFailed { plugin: plugin.clone(), hook: "hook".into(), code: 1, message: None },
+                        VPluginError::IncompatibleAbi { plugin: plugin.clone(), found: 0, expected: 1, build: "unknown".into() },
+                        VPluginError::InvalidConfig { plugin: plugin.clone(), setting: "setting".into(), reason: "reason".into() },
+                        VPluginError::NotExecutable { path: path.clone() },
+                        VPluginError::NotSharedObject { plugin: plugin.clone(), object: "test.so".into(), kind: "executable".into() },
+                        VPluginError::MissingLibrary { plugin: plugin.clone(), object: "test.so".into(), library: "libm.so".into() },
+                        VPluginError::InterfaceMismatch { plugin: plugin.clone(), interface: "interface".into() },
+                        VPluginError::StaticMismatch { plugin, symbol: "symbol".into(), reason: "reason".into() },
+                ]
+        }
+
+        #[test]
+        fn codes_are_stable() {
+                let codes: Vec<i32> = errors().iter().map(VPluginError::code).collect();
+                assert_eq!(codes, [1, 2, 2, 2, 2, 3, 4, 4, 5, 6, 7, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+        }
+
+        #[test]
+        #[cfg(feature = "capi")]
+        fn codes_match_the_c_api() {
+                use crate::capi::*;
+
+                let codes: Vec<i32> = errors().iter().map(VPluginError::code).collect();
+                assert_eq!(codes, [
+                        VPLUGIN_ERR_PARAMETERS,
+                        VPLUGIN_ERR_INVALID_PLUGIN,
+                        VPLUGIN_ERR_INVALID_PLUGIN,
+                        VPLUGIN_ERR_INVALID_PLUGIN,
+                        VPLUGIN_ERR_INVALID_PLUGIN,
+                        VPLUGIN_ERR_NO_SUCH_FILE,
+                        VPLUGIN_ERR_PERMISSION_DENIED,
+                        VPLUGIN_ERR_PERMISSION_DENIED,
+                        VPLUGIN_ERR_MISSING_SYMBOL,
+                        VPLUGIN_ERR_FAILED_TO_INITIALIZE,
+                        VPLUGIN_ERR_INTERNAL,
+                        VPLUGIN_ERR_INTERNAL,
+                        VPLUGIN_ERR_SIGNATURE_MISMATCH,
+                        VPLUGIN_ERR_HOOK_FAILED,
+                        VPLUGIN_ERR_INCOMPATIBLE_ABI,
+                        VPLUGIN_ERR_INVALID_CONFIG,
+                        VPLUGIN_ERR_NOT_EXECUTABLE,
+                        VPLUGIN_ERR_INCOMPATIBLE_OBJECT,
+                        VPLUGIN_ERR_MISSING_LIBRARY,
+                        VPLUGIN_ERR_INTERFACE_MISMATCH,
+                        VPLUGIN_ERR_STATIC_MISMATCH,
+                ]);
+                assert!(!codes.contains(&VPLUGIN_OK));
+        }
+}
@@ -0,0 +1,226 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use crate::host::PluginContext;
+
+/// ## EventCallback
+/// A function provided by a plugin to receive events it subscribed to.
+/// - `event` is the NUL-terminated name of the event,
+/// - `data` and `len` are the serialized (JSON) payload, only valid during the call,
+/// - `user_data` is the pointer the plugin passed when subscribing.
+pub type EventCallback = unsafe extern "C" fn(
+        event    : *const c_char,
+        data     : *const u8,
+        len      : usize,
+        user_data: *mut c_void
+);
+
+#[derive(Debug, Clone)]
+struct Subscription {
+        owner    : u64,
+        plugin   : String,
+        event    : String,
+        callback : EventCallback,
+        user_data: *mut c_void,
+}
+
+/* The user data pointer is owned by the plugin, which is responsible for its thread safety. */
+unsafe impl Send for Subscription {}
+
+/// Keeps track of which plugin subscribed to which event.
+#[derive(Debug, Default)]
+pub(crate) struct EventBus {
+        subscriptions: Vec<Subscription>,
+}
+
+impl EventBus {
+        fn subscribe(&mut self, context: &PluginContext, event: &str, callback: EventCallback, user_data: *mut c_void) {
+                self.subscriptions.push(Subscription {
+                        owner : context.id,
+                        plugin: context.plugin.clone(),
+                        event : event.to_owned(),
+                        callback,
+                        user_data,
+                });
+        }
+
+        fn unsubscribe(&mut self, owner: u64, event: &str, callback: EventCallback) -> bool {
+                let before = self.subscriptions.len();
+                self.subscriptions.retain(|s| {
+                        !(s.owner == owner && s.event == event && std::ptr::fn_addr_eq(s.callback, callback))
+                });
+                before != self.subscriptions.len()
+        }
+
+        /// Drops every subscription made by a plugin.
+        pub(crate) fn unsubscribe_all(&mut self, owner: u64) {
+                self.subscriptions.retain(|s| s.owner != owner);
+        }
+
+        /// Returns the names of the plugins subscribed to `event`.
+        pub(crate) fn subscribers(&self, event: &str) -> Vec<String> {
+                self.subscriptions
+                        .iter()
+                        .filter(|s| s.event == event)
+                        .map(|s| s.plugin.clone())
+                        .collect()
+        }
+
+        /// Returns every callback subscribed to `event`. They are copied out so
+        /// no lock is held while plugins run (They may subscribe from a callback).
+        fn callbacks(&self, event: &str) -> Vec<Subscription> {
+                self.subscriptions
+                        .iter()
+                        .filter(|s| s.event == event)
+                        .cloned()
+                        .collect()
+        }
+}
+
+/// Delivers `data` to every callback subscribed to `event`,
+/// returning how many callbacks were called.
+pub(crate) fn deliver(bus: &std::sync::Mutex<EventBus>, event: &str, data: &[u8]) -> usize {
+        let callbacks = bus.lock().unwrap().callbacks(event);
+        let name = match CString::new(event) {
+                Ok (n) => n,
+                Err(_) => return 0,
+        };
+
+        for subscription in &callbacks {
+                log::trace!("Delivering event '{}' to plugin '{}'.", event, subscription.plugin);
+                unsafe {
+                        (subscription.callback)(name.as_ptr(), data.as_ptr(), data.len(), subscription.user_data);
+                }
+        }
+        callbacks.len()
+}
+
+/// Host callback: subscribes the calling plugin to an event.
+pub(crate) unsafe extern "C" fn subscribe(
+        context  : *mut c_void,
+        event    : *const c_char,
+        callback : EventCallback,
+        user_data: *mut c_void
+) -> c_int {
+        let Some(context) = PluginContext::from_raw(context) else {
+                return -1;
+        };
+        let Some(event) = (!event.is_null()).then(|| CStr::from_ptr(event).to_string_lossy()) else {
+                return -1;
+        };
+
+        log::trace!("Plugin '{}' subscribed to event '{}'.", context.plugin, event);
+        context.state.events.lock().unwrap().subscribe(context, &event, callback, user_data);
+        0
+}
+
+/// Host callback: removes a subscription made by the calling plugin.
+pub(crate) unsafe extern "C" fn unsubscribe(
+        context : *mut c_void,
+        event   : *const c_char,
+        callback: EventCallback
+) -> c_int {
+        let Some(context) = PluginContext::from_raw(context) else {
+                return -1;
+        };
+        if event.is_null() {
+                return -1;
+        }
+
+        let event = CStr::from_ptr(event).to_string_lossy();
+        if context.state.events.lock().unwrap().unsubscribe(context.id, &event, callback) {
+                0
+        } else {
+                -1
+        }
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use std::slice;
+        use std::sync::{Arc, Mutex};
+        use crate::host::{ErrorSlot, HostState};
+
+        /// What a plugin received, as its user data.
+        type Received = Mutex<Vec<(String, Vec<u8>)>>;
+
+        unsafe extern "C" fn record(event: *const c_char, data: *const u8, len: usize, user_data: *mut c_void) {
+                let received = &*(user_data as *const Received);
+                let event    = CStr::from_ptr(event).to_string_lossy().into_owned();
+                received.lock().unwrap().push((event, slice::from_raw_parts(data, len).to_vec()));
+        }
+
+        unsafe extern "C" fn ignore(_: *const c_char, _: *const u8, _: usize, _: *mut c_void) {}
+
+        fn context(plugin: &str, state: &Arc<HostState>) -> Box<PluginContext> {
+                PluginContext::new(plugin, state.clone(), ErrorSlot::default(), None)
+        }
+
+        fn subscribe(context: &PluginContext, event: &CStr, callback: EventCallback, received: &Received) -> c_int {
+                let user_data = received as *const Received as *mut c_void;
+                unsafe { (context.api.subscribe)(context.api.context, event.as_ptr(), callback, user_data) }
+        }
+
+        #[test]
+        fn delivers_to_subscribers() {
+                let state    = Arc::new(HostState::default());
+                let (a, b)   = (context("a", &state), context("b", &state));
+                let received = Received::default();
+
+                assert_eq!(subscribe(&a, c"file_opened", record, &received), 0);
+                assert_eq!(subscribe(&b, c"file_opened", ignore, &received), 0);
+                assert_eq!(subscribe(&b, c"file_closed", record, &received), 0);
+                assert_eq!(state.events.lock().unwrap().subscribers("file_opened"), ["a", "b"]);
+
+                assert_eq!(deliver(&state.events, "file_opened", b"\"x.txt\""), 2);
+                assert_eq!(deliver(&state.events, "file_saved", b"null"), 0);
+                assert_eq!(*received.lock().unwrap(), [("file_opened".into(), b"\"x.txt\"".to_vec())]);
+        }
+
+        #[test]
+        fn unsubscribes_only_the_callers_subscription() {
+                let state    = Arc::new(HostState::default());
+                let (a, b)   = (context("a", &state), context("b", &state));
+                let received = Received::default();
+
+                subscribe(&a, c"file_opened", record, &received);
+                subscribe(&b, c"file_opened", record, &received);
+                let unsubscribe = |c: &PluginContext, callback| unsafe {
+                        (c.api.unsubscribe)(c.api.context, c"file_opened".as_ptr(), callback)
+                };
+                assert_eq!(unsubscribe(&a, ignore), -1);
+                assert_eq!(unsubscribe(&a, record), 0);
+                assert_eq!(unsubscribe(&a, record), -1);
+                assert_eq!(state.events.lock().unwrap().subscribers("file_opened"), ["b"]);
+        }
+
+        #[test]
+        fn detaching_drops_subscriptions() {
+                let state    = Arc::new(HostState::default());
+                let (a, b)   = (context("a", &state), context("b", &state));
+                let received = Received::default();
+
+                subscribe(&a, c"file_opened", record, &received);
+                subscribe(&b, c"file_opened", record, &received);
+                drop(a);
+                assert_eq!(deliver(&state.events, "file_opened", b"{}"), 1);
+                assert_eq!(received.lock().unwrap().len(), 1);
+                drop(b);
+                assert!(state.events.lock().unwrap().subscribers("file_opened").is_empty());
+        }
+}
@@ -0,0 +1,524 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+//! # Stable ABI types
+//! `#[repr(C)]` replacements for the standard types, to pass data between the host and
+//! its plugins (eg. through a [hook](crate::PluginManager::hook) or a
+//! [plugin interface](crate::interface)). The layout of `String`, `Vec`, `Option` and `Result`
+//! may change between compilers, and both sides may not even use the same allocator.
+//!
+//! Owned types ([VString], [VVec] and [VCallback]) carry the function freeing them, from the
+//! side that allocated them, so they can be dropped on either side of the boundary.
+//! Strings coming from the other side aren't trusted to be valid UTF-8, so they're checked
+//! when converted:
+//! ```rust
+//! use vplugin::{VString, VVec};
+//!
+//! let name = VString::from("spell");
+//! let list = VVec::from(vec![1, 2, 3]);
+//! assert_eq!(name.to_str(), Ok("spell"));
+//! assert_eq!(list.into_vec(), [1, 2, 3]);
+//! ```
+
+use std::borrow::Cow;
+use std::ffi::c_void;
+use std::fmt::{self, Debug, Display};
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+use std::{ptr, slice, str};
+
+/// Frees the buffer of a [VVec], along with its first `len` elements.
+type FreeVec<T> = unsafe extern "C" fn(ptr: *mut T, len: usize, capacity: usize);
+
+unsafe extern "C" fn free_vec<T>(ptr: *mut T, len: usize, capacity: usize) {
+        drop(Vec::from_raw_parts(ptr, len, capacity));
+}
+
+/// ## VStr
+/// A borrowed string slice (`&str`), which may not be valid UTF-8 if it comes from a plugin.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct VStr<'a> {
+        ptr    : *const u8,
+        len    : usize,
+        _marker: PhantomData<&'a str>,
+}
+
+impl<'a> VStr<'a> {
+        pub fn as_bytes(&self) -> &'a [u8] {
+                unsafe { slice::from_raw_parts(self.ptr, self.len) }
+        }
+
+        /// Returns the string, or an error if it isn't valid UTF-8.
+        pub fn to_str(&self) -> Result<&'a str, Utf8Error> {
+                str::from_utf8(self.as_bytes())
+        }
+
+        /// Returns the string, with invalid UTF-8 replaced by `U+FFFD`.
+        pub fn to_string_lossy(&self) -> Cow<'a, str> {
+                String::from_utf8_lossy(self.as_bytes())
+        }
+}
+
+impl<'a> From<&'a str> for VStr<'a> {
+        fn from(s: &'a str) -> Self {
+                Self { ptr: s.as_ptr(), len: s.len(), _marker: PhantomData }
+        }
+}
+
+impl PartialEq<str> for VStr<'_> {
+        fn eq(&self, other: &str) -> bool {
+                self.as_bytes() == other.as_bytes()
+        }
+}
+
+impl PartialEq<&str> for VStr<'_> {
+        fn eq(&self, other: &&str) -> bool {
+                self.as_bytes() == other.as_bytes()
+        }
+}
+
+impl Debug for VStr<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Debug::fmt(&self.to_string_lossy(), f)
+        }
+}
+
+impl Display for VStr<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Display::fmt(&self.to_string_lossy(), f)
+        }
+}
+
+unsafe impl Send for VStr<'_> {}
+unsafe impl Sync for VStr<'_> {}
+
+/// ## VSlice
+/// A borrowed slice (`&[T]`).
+#[repr(C)]
+pub struct VSlice<'a, T> {
+        ptr    : *const T,
+        len    : usize,
+        _marker: PhantomData<&'a [T]>,
+}
+
+impl<'a, T> VSlice<'a, T> {
+        pub fn as_slice(&self) -> &'a [T] {
+                unsafe { slice::from_raw_parts(self.ptr, self.len) }
+        }
+}
+
+impl<'a, T> From<&'a [T]> for VSlice<'a, T> {
+        fn from(s: &'a [T]) -> Self {
+                Self { ptr: s.as_ptr(), len: s.len(), _marker: PhantomData }
+        }
+}
+
+impl<T> Deref for VSlice<'_, T> {
+        type Target = [T];
+
+        fn deref(&self) -> &[T] {
+                self.as_slice()
+        }
+}
+
+impl<T> Clone for VSlice<'_, T> {
+        fn clone(&self) -> Self {
+                *self
+        }
+}
+
+impl<T> Copy for VSlice<'_, T> {}
+
+impl<T: Debug> Debug for VSlice<'_, T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Debug::fmt(self.as_slice(), f)
+        }
+}
+
+unsafe impl<T: Sync> Send for VSlice<'_, T> {}
+unsafe impl<T: Sync> Sync for VSlice<'_, T> {}
+
+/// ## VVec
+/// An owned, growable array (`Vec<T>`).
+#[repr(C)]
+pub struct VVec<T> {
+        ptr     : *mut T,
+        len     : usize,
+        capacity: usize,
+        free    : FreeVec<T>,
+}
+
+impl<T> VVec<T> {
+        pub fn new() -> Self {
+                Self::from(Vec::new())
+        }
+
+        pub fn as_slice(&self) -> &[T] {
+                unsafe { slice::from_raw_parts(self.ptr, self.len) }
+        }
+
+        pub fn as_mut_slice(&mut self) -> &mut [T] {
+                unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+        }
+
+        /// Moves the elements into a `Vec` allocated on this side of the boundary.
+        pub fn into_vec(self) -> Vec<T> {
+                let this = ManuallyDrop::new(self);
+                let mut vec = Vec::with_capacity(this.len);
+                unsafe {
+                        ptr::copy_nonoverlapping(this.ptr, vec.as_mut_ptr(), this.len);
+                        vec.set_len(this.len);
+                        /* The elements were moved out, only the buffer is left. */
+                        (this.free)(this.ptr, 0, this.capacity);
+                }
+                vec
+        }
+}
+
+impl<T> From<Vec<T>> for VVec<T> {
+        fn from(vec: Vec<T>) -> Self {
+                let mut vec = ManuallyDrop::new(vec);
+                Self {
+                        ptr     : vec.as_mut_ptr(),
+                        len     : vec.len(),
+                        capacity: vec.capacity(),
+                        free    : free_vec::<T>,
+                }
+        }
+}
+
+impl<T> Default for VVec<T> {
+        fn default() -> Self {
+                Self::new()
+        }
+}
+
+impl<T> Drop for VVec<T> {
+        fn drop(&mut self) {
+                unsafe { (self.free)(self.ptr, self.len, self.capacity) }
+        }
+}
+
+impl<T> Deref for VVec<T> {
+        type Target = [T];
+
+        fn deref(&self) -> &[T] {
+                self.as_slice()
+        }
+}
+
+impl<T> DerefMut for VVec<T> {
+        fn deref_mut(&mut self) -> &mut [T] {
+                self.as_mut_slice()
+        }
+}
+
+impl<T: Clone> Clone for VVec<T> {
+        fn clone(&self) -> Self {
+                Self::from(self.as_slice().to_vec())
+        }
+}
+
+impl<T: Debug> Debug for VVec<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Debug::fmt(self.as_slice(), f)
+        }
+}
+
+impl<T: PartialEq> PartialEq for VVec<T> {
+        fn eq(&self, other: &Self) -> bool {
+                self.as_slice() == other.as_slice()
+        }
+}
+
+impl<T: Eq> Eq for VVec<T> {}
+
+impl<T> FromIterator<T> for VVec<T> {
+        fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+                Self::from(iter.into_iter().collect::<Vec<T>>())
+        }
+}
+
+unsafe impl<T: Send> Send for VVec<T> {}
+unsafe impl<T: Sync> Sync for VVec<T> {}
+
+/// ## VString
+/// An owned string (`String`), which may not be valid UTF-8 if it comes from a plugin.
+#[repr(C)]
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct VString {
+        bytes: VVec<u8>,
+}
+
+impl VString {
+        pub fn new() -> Self {
+                Self::default()
+        }
+
+        pub fn as_bytes(&self) -> &[u8] {
+                &self.bytes
+        }
+
+        /// Returns the string, or an error if it isn't valid UTF-8.
+        pub fn to_str(&self) -> Result<&str, Utf8Error> {
+                str::from_utf8(&self.bytes)
+        }
+
+        /// Returns the string, with invalid UTF-8 replaced by `U+FFFD`.
+        pub fn to_string_lossy(&self) -> Cow<'_, str> {
+                String::from_utf8_lossy(&self.bytes)
+        }
+
+        /// Moves the string into a `String` allocated on this side of the boundary,
+        /// or returns an error (Holding the bytes) if it isn't valid UTF-8.
+        pub fn into_string(self) -> Result<String, FromUtf8Error> {
+                String::from_utf8(self.bytes.into_vec())
+        }
+}
+
+impl From<String> for VString {
+        fn from(s: String) -> Self {
+                Self { bytes: VVec::from(s.into_bytes()) }
+        }
+}
+
+impl From<&str> for VString {
+        fn from(s: &str) -> Self {
+                Self::from(s.to_owned())
+        }
+}
+
+impl PartialEq<str> for VString {
+        fn eq(&self, other: &str) -> bool {
+                self.as_bytes() == other.as_bytes()
+        }
+}
+
+impl PartialEq<&str> for VString {
+        fn eq(&self, other: &&str) -> bool {
+                self.as_bytes() == other.as_bytes()
+        }
+}
+
+impl Debug for VString {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Debug::fmt(&self.to_string_lossy(), f)
+        }
+}
+
+impl Display for VString {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Display::fmt(&self.to_string_lossy(), f)
+        }
+}
+
+/// ## VOption
+/// An optional value (`Option<T>`).
+#[repr(C, u8)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VOption<T> {
+        #[default]
+        None,
+        Some(T),
+}
+
+impl<T> VOption<T> {
+        pub fn is_some(&self) -> bool {
+                matches!(self, Self::Some(_))
+        }
+
+        pub fn as_ref(&self) -> Option<&T> {
+                match self {
+                        Self::Some(v) => Some(v),
+                        Self::None    => None,
+                }
+        }
+
+        pub fn into_option(self) -> Option<T> {
+                match self {
+                        Self::Some(v) => Some(v),
+                        Self::None    => None,
+                }
+        }
+}
+
+impl<T> From<Option<T>> for VOption<T> {
+        fn from(o: Option<T>) -> Self {
+                match o {
+                        Some(v) => Self::Some(v),
+                        None    => Self::None,
+                }
+        }
+}
+
+/// ## VResult
+/// The result of an operation that may fail (`Result<T, E>`).
+#[repr(C, u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VResult<T, E> {
+        Ok(T),
+        Err(E),
+}
+
+impl<T, E> VResult<T, E> {
+        pub fn is_ok(&self) -> bool {
+                matches!(self, Self::Ok(_))
+        }
+
+        pub fn into_result(self) -> Result<T, E> {
+                match self {
+                        Self::Ok (v) => Ok(v),
+                        Self::Err(e) => Err(e),
+                }
+        }
+}
+
+impl<T, E> From<Result<T, E>> for VResult<T, E> {
+        fn from(r: Result<T, E>) -> Self {
+                match r {
+                        Ok (v) => Self::Ok(v),
+                        Err(e) => Self::Err(e),
+                }
+        }
+}
+
+/// ## VCallback
+/// A boxed closure taking an `A` and returning an `R` (`Box<dyn FnMut(A) -> R + Send>`).
+/// Several arguments can be passed as a `#[repr(C)]` struct.
+///
+/// A panic inside the closure aborts the process.
+#[repr(C)]
+pub struct VCallback<A, R> {
+        data: *mut c_void,
+        call: unsafe extern "C" fn(data: *mut c_void, arg: A) -> R,
+        free: unsafe extern "C" fn(data: *mut c_void),
+}
+
+impl<A, R> VCallback<A, R> {
+        pub fn new<F: FnMut(A) -> R + Send + 'static>(f: F) -> Self {
+                unsafe extern "C" fn call<F: FnMut(A) -> R, A, R>(data: *mut c_void, arg: A) -> R {
+                        (*(data as *mut F))(arg)
+                }
+                unsafe extern "C" fn free<F>(data: *mut c_void) {
+                        drop(Box::from_raw(data as *mut F));
+                }
+
+                Self {
+                        data: Box::into_raw(Box::new(f)) as *mut c_void,
+                        call: call::<F, A, R>,
+                        free: free::<F>,
+                }
+        }
+
+        pub fn call(&mut self, arg: A) -> R {
+                unsafe { (self.call)(self.data, arg) }
+        }
+}
+
+impl<A, R> Drop for VCallback<A, R> {
+        fn drop(&mut self) {
+                unsafe { (self.free)(self.data) }
+        }
+}
+
+impl<A, R> Debug for VCallback<A, R> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct("VCallback").field("data", &self.data).finish()
+        }
+}
+
+unsafe impl<A, R> Send for VCallback<A, R> {}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use std::sync::Arc;
+
+        #[test]
+        fn vvec_round_trips() {
+                let vec = VVec::from(vec![1, 2, 3]);
+                assert_eq!(vec.len(), 3);
+                assert_eq!(vec, vec.clone());
+                assert_eq!(vec, (1..=3).collect());
+                assert_eq!(format!("{:?}", vec), "[1, 2, 3]");
+                assert_eq!(vec.into_vec(), [1, 2, 3]);
+                assert!(VVec::<u8>::new().is_empty());
+        }
+
+        #[test]
+        fn vvec_drops_its_elements_once() {
+                let value = Arc::new(());
+                let vec   = VVec::from(vec![value.clone(), value.clone()]);
+                let moved = vec.clone().into_vec();
+                assert_eq!(Arc::strong_count(&value), 5);
+                drop(vec);
+                assert_eq!(Arc::strong_count(&value), 3);
+                drop(moved);
+                assert_eq!(Arc::strong_count(&value), 1);
+        }
+
+        #[test]
+        fn vstring_round_trips() {
+                let string = VString::from("spell");
+                assert_eq!(string, "spell");
+                assert_eq!(string.to_str(), Ok("spell"));
+                assert_eq!(string.to_string(), "spell");
+                assert_eq!(string.clone().into_string().unwrap(), "spell");
+                assert_eq!(VStr::from("spell"), "spell");
+                assert_eq!(VStr::from("spell").to_str(), Ok("spell"));
+        }
+
+        #[test]
+        fn vstring_refuses_invalid_utf8() {
+                let string = VString { bytes: VVec::from(vec![b'a', 0xff]) };
+                assert!(string.to_str().is_err());
+                assert_eq!(string.to_string_lossy(), "a\u{fffd}");
+                assert_eq!(string.into_string().unwrap_err().into_bytes(), [b'a', 0xff]);
+
+                let bytes = [b'a', 0xff];
+                let str   = VStr { ptr: bytes.as_ptr(), len: bytes.len(), _marker: PhantomData };
+                assert!(str.to_str().is_err());
+                assert_eq!(str.to_string_lossy(), "a\u{fffd}");
+        }
+
+        #[test]
+        fn voption_and_vresult_round_trip() {
+                assert_eq!(VOption::from(Some(1)).into_option(), Some(1));
+                assert_eq!(VOption::<i32>::default().into_option(), None);
+                assert!(VOption::from(Some(1)).is_some());
+                assert_eq!(VResult::<i32, ()>::from(Ok(1)).into_result(), Ok(1));
+                assert_eq!(VResult::<(), i32>::from(Err(1)).into_result(), Err(1));
+        }
+
+        #[test]
+        fn vcallback_calls_and_drops_its_closure() {
+                let value    = Arc::new(());
+                let captured = value.clone();
+                let mut total = 0;
+                let mut callback = VCallback::new(move |n: i32| {
+                        total += n;
+                        (total, Arc::strong_count(&captured))
+                });
+                assert_eq!(callback.call(2), (2, 2));
+                assert_eq!(callback.call(3), (5, 2));
+                drop(callback);
+                assert_eq!(Arc::strong_count(&value), 1);
+        }
+}
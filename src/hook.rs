@@ -0,0 +1,619 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Weak};
+use crate::error::VPluginError;
+use crate::host::ErrorSlot;
+use crate::loader::LoadedObject;
+use crate::plugin::Plugin;
+
+mod sealed {
+        pub trait Sealed {}
+}
+
+/// ## HookFn
+/// Implemented for `extern "C" fn` and `unsafe extern "C" fn` pointers taking up to 8 parameters.
+/// This trait is sealed, so it cannot be implemented outside of VPlugin.
+///
+/// The parameters are passed to [`call`](HookFn::call) as a tuple, eg. a hook of type
+/// `unsafe extern "C" fn(*const Doc, i32) -> i32` is called with `(doc, 0)`.
+pub trait HookFn: sealed::Sealed + Copy + 'static {
+        /// The parameters of the function, as a tuple.
+        type Args;
+        /// The return type of the function.
+        type Output;
+
+        /// Calls the function.
+        ///
+        /// ## Safety
+        /// The function pointer must point to a function with exactly this signature,
+        /// and the plugin it belongs to must still be loaded.
+        unsafe fn call(self, args: Self::Args) -> Self::Output;
+}
+
+macro_rules! impl_hook_fn {
+        ($($arg:ident),*) => {
+                impl_hook_fn!(@impl unsafe extern "C" fn($($arg),*) -> R; $($arg),*);
+                impl_hook_fn!(@impl extern "C" fn($($arg),*) -> R; $($arg),*);
+        };
+        (@impl $fn:ty; $($arg:ident),*) => {
+                impl<R: 'static, $($arg: 'static),*> sealed::Sealed for $fn {}
+                impl<R: 'static, $($arg: 'static),*> HookFn for $fn {
+                        type Args   = ($($arg,)*);
+                        type Output = R;
+
+                        #[allow(non_snake_case, clippy::unused_unit)]
+                        unsafe fn call(self, args: Self::Args) -> R {
+                                let ($($arg,)*) = args;
+                                (self)($($arg),*)
+                        }
+                }
+        };
+}
+
+impl_hook_fn!();
+impl_hook_fn!(A);
+impl_hook_fn!(A, B);
+impl_hook_fn!(A, B, C);
+impl_hook_fn!(A, B, C, D);
+impl_hook_fn!(A, B, C, D, E);
+impl_hook_fn!(A, B, C, D, E, F);
+impl_hook_fn!(A, B, C, D, E, F, G);
+impl_hook_fn!(A, B, C, D, E, F, G, H);
+
+/// ## Hook
+/// A function of a started plugin, with its actual type, returned by
+/// [Plugin::hook](crate::Plugin::hook) and [PluginManager::hook](crate::PluginManager::hook).
+///
+/// Unlike [VHook](crate::VHook), the function may take any number of parameters:
+/// ```text
+/// type Export = unsafe extern "C" fn(*const u8, usize) -> i32;
+///
+/// let export = manager.hook::<Export>(&plugin, "export")?;
+/// unsafe { export.call((data.as_ptr(), data.len())) };
+/// ```
+///
+/// The plugin's object file stays loaded until every hook of it is dropped, so a hook
+/// never points to unmapped code, even once the plugin is terminated or dropped. Whether
+/// the plugin is still started (And its state still valid) is up to [is_alive](Hook::is_alive).
+#[derive(Clone)]
+pub struct Hook<F: HookFn> {
+        pub(crate) plugin  : String,
+        pub(crate) name    : String,
+        pub(crate) func    : F,
+        pub(crate) _library: Arc<LoadedObject>,
+        /* Dead once the plugin is terminated or dropped. */
+        pub(crate) alive   : Weak<()>,
+}
+
+impl<F: HookFn> Hook<F> {
+        /// Calls the hook, with its parameters as a tuple.
+        ///
+        /// ## Safety
+        /// The plugin must export the hook as `F`. Unless the hook can run without
+        /// its plugin being started, the plugin must still be [alive](Hook::is_alive).
+        pub unsafe fn call(&self, args: F::Args) -> F::Output {
+                self.func.call(args)
+        }
+
+        /// Returns whether the plugin is still started.
+        pub fn is_alive(&self) -> bool {
+                self.alive.strong_count() > 0
+        }
+
+        /// Returns the name of the hook.
+        pub fn name(&self) -> &str {
+                &self.name
+        }
+
+        /// Returns the name of the plugin the hook belongs to.
+        pub fn plugin(&self) -> &str {
+                &self.plugin
+        }
+}
+
+impl<F: HookFn> std::fmt::Debug for Hook<F> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_struct("Hook")
+                        .field("plugin", &self.plugin)
+                        .field("name", &self.name)
+                        .field("type", &std::any::type_name::<F>())
+                        .field("alive", &self.is_alive())
+                        .finish()
+        }
+}
+
+/// ## HookStatus
+/// Return types that can tell whether a hook succeeded, used by
+/// [`HookRegistry::try_dispatch`]. Integers follow the C convention
+/// (`0` means success), `bool` hooks fail by returning `false`
+/// and `()` hooks never fail.
+pub trait HookStatus {
+        /// Returns `Err` with a numeric code if the hook failed.
+        fn status(&self) -> Result<(), i64>;
+}
+
+macro_rules! impl_hook_status {
+        ($($t:ty),*) => {
+                $(
+                        impl HookStatus for $t {
+                                fn status(&self) -> Result<(), i64> {
+                                        match *self {
+                                                0 => Ok(()),
+                                                v => Err(v as i64),
+                                        }
+                                }
+                        }
+                )*
+        };
+}
+
+impl_hook_status!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl HookStatus for bool {
+        fn status(&self) -> Result<(), i64> {
+                if *self { Ok(()) } else { Err(0) }
+        }
+}
+
+impl HookStatus for () {
+        fn status(&self) -> Result<(), i64> {
+                Ok(())
+        }
+}
+
+/// The value returned by a single plugin's hook during a dispatch.
+#[derive(Debug, Clone)]
+pub struct HookResult<T> {
+        /// Name of the plugin that returned the value.
+        pub plugin: String,
+        /// The value returned by the hook.
+        pub value : T,
+}
+
+struct ResolvedHook<F> {
+        plugin  : String,
+        priority: i32,
+        function: F,
+        /* Keeps the object file loaded while the hook is resolved. */
+        _library: Arc<LoadedObject>,
+        /* Dead once the plugin is terminated or dropped. */
+        alive   : Weak<()>,
+        errors  : ErrorSlot,
+}
+
+struct HookPoint<F: HookFn> {
+        signature: Option<String>,
+        resolved : Vec<ResolvedHook<F>>,
+}
+
+impl<F: HookFn> HookPoint<F> {
+        fn add(&mut self, hook: ResolvedHook<F>) {
+                self.resolved.push(hook);
+                /* Higher priorities run first; plugins with equal priorities keep their order. */
+                self.resolved.sort_by_key(|h| std::cmp::Reverse(h.priority));
+        }
+}
+
+/// Type-erased access to a [`HookPoint`], so the registry can
+/// resolve hook points of different types.
+trait ErasedHookPoint {
+        fn clear(&mut self);
+        fn resolve(&mut self, name: &str, plugin: &Plugin, priority: i32) -> Result<bool, VPluginError>;
+        fn signature(&self) -> Option<&str>;
+        fn providers(&self) -> Vec<&str>;
+        fn as_any(&self) -> &dyn Any;
+}
+
+impl<F: HookFn> ErasedHookPoint for HookPoint<F> {
+        fn clear(&mut self) {
+                self.resolved.clear();
+        }
+
+        fn resolve(&mut self, name: &str, plugin: &Plugin, priority: i32) -> Result<bool, VPluginError> {
+                if !plugin.is_function_available(name) {
+                        return Ok(false);
+                }
+                if let Some(signature) = &self.signature {
+                        plugin.check_hook_signature(name, signature)?;
+                }
+
+                self.add(ResolvedHook {
+                        plugin  : plugin.get_metadata().as_ref().unwrap().name.clone(),
+                        priority,
+                        function: plugin.load_symbol::<F>(name)?,
+                        _library: plugin.library()?,
+                        alive   : Arc::downgrade(&plugin.alive),
+                        errors  : plugin.host.as_ref().map(|c| c.errors.clone()).unwrap_or_default(),
+                });
+                Ok(true)
+        }
+
+        fn signature(&self) -> Option<&str> {
+                self.signature.as_deref()
+        }
+
+        fn providers(&self) -> Vec<&str> {
+                self.resolved.iter().map(|h| h.plugin.as_str()).collect()
+        }
+
+        fn as_any(&self) -> &dyn Any {
+                self
+        }
+}
+
+/// ## HookRegistry
+/// A set of typed hook points declared by the host. Once the hook points are
+/// declared, [`PluginManager::resolve_hooks`](crate::PluginManager::resolve_hooks)
+/// looks them up in every started plugin, and the host can call all
+/// implementations of a hook at once with [`dispatch`](HookRegistry::dispatch):
+/// ```rust
+/// type OnSave = unsafe extern "C" fn(*const Doc) -> i32;
+///
+/// let mut registry = HookRegistry::new();
+/// unsafe { registry.declare::<OnSave>("on_save"); }
+///
+/// manager.resolve_hooks(&mut registry);
+/// registry.try_dispatch::<OnSave>("on_save", (&doc as *const Doc,))?;
+/// ```
+///
+/// Hooks of plugins terminated since the registry was resolved are skipped (Their
+/// object files stay loaded until then), so resolve the registry again whenever
+/// plugins are started or terminated.
+#[derive(Default)]
+pub struct HookRegistry {
+        points    : BTreeMap<String, Box<dyn ErasedHookPoint>>,
+        priorities: BTreeMap<String, i32>,
+}
+
+impl HookRegistry {
+        /// Creates a new registry without any hook points.
+        pub fn new() -> Self {
+                Self::default()
+        }
+
+        /// Declares a hook point named `name`, implemented by plugins as a function of type `F`.
+        ///
+        /// ## Safety
+        /// VPlugin cannot verify the actual signature of a function exported by a plugin.
+        /// The caller guarantees that every plugin exporting `name` implements it as `F`.
+        /// Use [declare_with_signature](HookRegistry::declare_with_signature) to also check
+        /// the signatures plugins declare in their `metadata.toml`.
+        pub unsafe fn declare<F: HookFn>(&mut self, name: &str) {
+                self.points.insert(name.to_owned(), Box::new(HookPoint::<F> {
+                        signature: None,
+                        resolved : Vec::new(),
+                }));
+        }
+
+        /// Same as [declare](HookRegistry::declare), but plugins declaring the hook
+        /// with a different signature descriptor in their manifest are not resolved.
+        ///
+        /// ## Safety
+        /// See [declare](HookRegistry::declare).
+        pub unsafe fn declare_with_signature<F: HookFn>(&mut self, name: &str, signature: &str) {
+                self.points.insert(name.to_owned(), Box::new(HookPoint::<F> {
+                        signature: Some(signature.to_owned()),
+                        resolved : Vec::new(),
+                }));
+        }
+
+        /// Returns whether a hook point named `name` has been declared.
+        pub fn is_declared(&self, name: &str) -> bool {
+                self.points.contains_key(name)
+        }
+
+        /// Returns the names of the plugins implementing `name`, in dispatch order.
+        pub fn providers(&self, name: &str) -> Vec<&str> {
+                self.points
+                        .get(name)
+                        .map(|p| p.providers())
+                        .unwrap_or_default()
+        }
+
+        /// Overrides the priority of a plugin, which otherwise comes from the
+        /// `priority` field of its metadata. Hooks of plugins with higher
+        /// priorities are called first. Takes effect on the next resolution.
+        pub fn set_priority(&mut self, plugin: &str, priority: i32) {
+                self.priorities.insert(plugin.to_owned(), priority);
+        }
+
+        /// Forgets every resolved hook, keeping the hook points declared.
+        pub fn clear(&mut self) {
+                for point in self.points.values_mut() {
+                        point.clear();
+                }
+        }
+
+        /// Resolves every hook point in `plugin`. Plugins whose declared
+        /// signatures don't match are skipped.
+        pub(crate) fn resolve(&mut self, plugin: &Plugin) {
+                let metadata = plugin.get_metadata().as_ref().unwrap();
+                let priority = self.priorities
+                        .get(&metadata.name)
+                        .copied()
+                        .unwrap_or(metadata.priority);
+
+                for (name, point) in self.points.iter_mut() {
+                        if let Err(e) = point.resolve(name, plugin, priority) {
+                                log::warn!(
+                                        "Not using hook '{}' of plugin '{}' (Expected signature '{}'): {}",
+                                        name,
+                                        metadata.name,
+                                        point.signature().unwrap_or_default(),
+                                        e
+                                );
+                        }
+                }
+        }
+
+        fn point<F: HookFn>(&self, name: &str) -> Result<&HookPoint<F>, VPluginError> {
+                let point = match self.points.get(name) {
+                        Some(p) => p,
+                        None    => {
+                                log::error!("Hook point '{}' has not been declared.", name);
+                                return Err(VPluginError::ParametersError);
+                        }
+                };
+                match point.as_any().downcast_ref::<HookPoint<F>>() {
+                        Some(p) => Ok(p),
+                        None    => {
+                                log::error!("Hook point '{}' was declared with a different function type.", name);
+                                Err(VPluginError::ParametersError)
+                        }
+                }
+        }
+
+        /// Calls every implementation of the hook `name`, in priority order, and
+        /// returns the values returned by each plugin. Plugins terminated since
+        /// the registry was resolved are skipped.
+        ///
+        /// ## `Err` returned:
+        /// [`VPluginError::ParametersError`] if the hook point is not declared,
+        /// or was declared with a type other than `F`.
+        pub fn dispatch<F: HookFn>(&self, name: &str, args: F::Args) -> Result<Vec<HookResult<F::Output>>, VPluginError>
+        where
+                F::Args: Clone
+        {
+                self.dispatch_until::<F>(name, args, |_| false)
+        }
+
+        /// Same as [dispatch](HookRegistry::dispatch), but stops calling further
+        /// plugins once `stop` returns `true` for a result. The result that
+        /// stopped the dispatch is the last one returned.
+        pub fn dispatch_until<F: HookFn>(
+                &self,
+                name: &str,
+                args: F::Args,
+                mut stop: impl FnMut(&HookResult<F::Output>) -> bool
+        ) -> Result<Vec<HookResult<F::Output>>, VPluginError>
+        where
+                F::Args: Clone
+        {
+                self.call_until::<F>(name, args, |r, _| stop(r))
+        }
+
+        /// Calls the hooks like [dispatch_until](HookRegistry::dispatch_until), also
+        /// handing `stop` the errors reported by the call.
+        fn call_until<F: HookFn>(
+                &self,
+                name: &str,
+                args: F::Args,
+                mut stop: impl FnMut(&HookResult<F::Output>, &ErrorSlot) -> bool
+        ) -> Result<Vec<HookResult<F::Output>>, VPluginError>
+        where
+                F::Args: Clone
+        {
+                let point = self.point::<F>(name)?;
+                let mut results = Vec::with_capacity(point.resolved.len());
+
+                for hook in &point.resolved {
+                        if hook.alive.strong_count() == 0 {
+                                log::warn!("Skipping hook '{}' of plugin '{}', which was terminated since it was resolved.", name, hook.plugin);
+                                continue;
+                        }
+                        hook.errors.clear();
+                        let result = HookResult {
+                                plugin: hook.plugin.clone(),
+                                /* Safety: The type was guaranteed by the caller of `declare`. */
+                                value : unsafe { hook.function.call(args.clone()) },
+                        };
+                        let done = stop(&result, &hook.errors);
+                        results.push(result);
+                        if done {
+                                break;
+                        }
+                }
+
+                Ok(results)
+        }
+
+        /// Same as [dispatch](HookRegistry::dispatch), but stops at the first hook
+        /// reporting a failure (See [`HookStatus`]).
+        ///
+        /// ## `Err` returned:
+        /// [`VPluginError::HookFailed`], naming the plugin whose hook failed.
+        pub fn try_dispatch<F: HookFn>(&self, name: &str, args: F::Args) -> Result<Vec<HookResult<F::Output>>, VPluginError>
+        where
+                F::Args  : Clone,
+                F::Output: HookStatus
+        {
+                /* The message is read right away, from the hook that failed. */
+                let mut message = None;
+                let mut results = self.call_until::<F>(name, args, |r, errors| {
+                        let failed = r.value.status().is_err();
+                        if failed {
+                                message = errors.message();
+                        }
+                        failed
+                })?;
+
+                if let Some(Err(code)) = results.last().map(|r| r.value.status()) {
+                        let failed = results.pop().unwrap();
+                        log::error!(
+                                "Hook '{}' failed in plugin '{}' (Code {}): {}",
+                                name,
+                                failed.plugin,
+                                code,
+                                message.as_deref().unwrap_or("No error message reported")
+                        );
+                        return Err(VPluginError::HookFailed {
+                                plugin : failed.plugin,
+                                hook   : name.to_owned(),
+                                code,
+                                message,
+                        });
+                }
+
+                Ok(results)
+        }
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use std::ffi::c_char;
+
+        type Status = extern "C" fn() -> i32;
+
+        extern "C" fn succeed() -> i32 {
+                0
+        }
+
+        extern "C" fn fail() -> i32 {
+                7
+        }
+
+        extern "C" fn add(a: i32, b: i32) -> i32 {
+                a + b
+        }
+
+        extern "C" fn first_error() -> *const c_char {
+                c"first".as_ptr()
+        }
+
+        extern "C" fn second_error() -> *const c_char {
+                c"second".as_ptr()
+        }
+
+        fn library() -> Arc<LoadedObject> {
+                #[cfg(unix)]
+                let library = libloading::os::unix::Library::this().into();
+                #[cfg(windows)]
+                let library = libloading::os::windows::Library::this().unwrap().into();
+                Arc::new(LoadedObject::new(library, Vec::new()))
+        }
+
+        /// Declares `name` with a hook for each of `hooks` (Their plugin, priority, function and
+        /// error slot), returning the registry along with what keeps each plugin alive.
+        fn declare<F: HookFn>(name: &str, hooks: Vec<(&str, i32, F, ErrorSlot)>) -> (HookRegistry, Vec<Arc<()>>) {
+                let mut point = HookPoint::<F> { signature: None, resolved: Vec::new() };
+                let mut alive = Vec::new();
+                for (plugin, priority, function, errors) in hooks {
+                        alive.push(Arc::new(()));
+                        point.add(ResolvedHook {
+                                plugin  : plugin.to_owned(),
+                                priority,
+                                function,
+                                _library: library(),
+                                alive   : Arc::downgrade(alive.last().unwrap()),
+                                errors,
+                        });
+                }
+                let mut registry = HookRegistry::new();
+                registry.points.insert(name.to_owned(), Box::new(point));
+                (registry, alive)
+        }
+
+        #[test]
+        fn dispatches_in_priority_order() {
+                type Add = extern "C" fn(i32, i32) -> i32;
+                let (registry, _alive) = declare::<Add>("add", vec![
+                        ("low", -1, add, ErrorSlot::default()),
+                        ("first", 0, add, ErrorSlot::default()),
+                        ("high", 10, add, ErrorSlot::default()),
+                        ("second", 0, add, ErrorSlot::default()),
+                ]);
+                assert_eq!(registry.providers("add"), ["high", "first", "second", "low"]);
+
+                let results = registry.dispatch::<Add>("add", (2, 3)).unwrap();
+                assert_eq!(results.iter().map(|r| r.plugin.as_str()).collect::<Vec<_>>(), ["high", "first", "second", "low"]);
+                assert!(results.iter().all(|r| r.value == 5));
+        }
+
+        #[test]
+        fn dispatch_until_stops_after_the_result() {
+                let (registry, _alive) = declare::<Status>("status", vec![
+                        ("a", 0, succeed, ErrorSlot::default()),
+                        ("b", 0, fail, ErrorSlot::default()),
+                        ("c", 0, succeed, ErrorSlot::default()),
+                ]);
+                let results = registry.dispatch_until::<Status>("status", (), |r| r.value != 0).unwrap();
+                assert_eq!(results.iter().map(|r| (r.plugin.as_str(), r.value)).collect::<Vec<_>>(), [("a", 0), ("b", 7)]);
+        }
+
+        #[test]
+        fn try_dispatch_reports_the_hook_that_failed() {
+                /* Two loads of the same plugin, each reporting its own errors. */
+                let (registry, _alive) = declare::<Status>("status", vec![
+                        ("same", 0, succeed, ErrorSlot::new(Some(first_error))),
+                        ("same", 0, fail, ErrorSlot::new(Some(second_error))),
+                        ("other", 0, succeed, ErrorSlot::default()),
+                ]);
+                match registry.try_dispatch::<Status>("status", ()) {
+                        Err(VPluginError::HookFailed { plugin, hook, code, message }) => {
+                                assert_eq!((plugin.as_str(), hook.as_str(), code), ("same", "status", 7));
+                                assert_eq!(message.as_deref(), Some("second"));
+                        }
+                        other => panic!("expected HookFailed, got {:?}", other.map(|r| r.len())),
+                }
+
+                let (registry, _alive) = declare::<Status>("status", vec![("a", 0, succeed, ErrorSlot::default())]);
+                assert_eq!(registry.try_dispatch::<Status>("status", ()).unwrap().len(), 1);
+        }
+
+        #[test]
+        fn skips_hooks_of_terminated_plugins() {
+                let (registry, mut alive) = declare::<Status>("status", vec![
+                        ("dead", 0, fail, ErrorSlot::default()),
+                        ("alive", 0, succeed, ErrorSlot::default()),
+                ]);
+                alive.remove(0);
+                let results = registry.try_dispatch::<Status>("status", ()).unwrap();
+                assert_eq!(results.iter().map(|r| r.plugin.as_str()).collect::<Vec<_>>(), ["alive"]);
+        }
+
+        #[test]
+        fn refuses_undeclared_hook_points() {
+                let (registry, _alive) = declare::<Status>("status", vec![]);
+                assert!(matches!(registry.dispatch::<Status>("other", ()), Err(VPluginError::ParametersError)));
+                assert!(matches!(
+                        registry.dispatch::<extern "C" fn() -> u64>("status", ()),
+                        Err(VPluginError::ParametersError)
+                ));
+        }
+
+        #[test]
+        fn statuses_follow_the_c_convention() {
+                assert_eq!(0u64.status(), Ok(()));
+                assert_eq!(u64::MAX.status(), Err(-1));
+                assert_eq!(3usize.status(), Err(3));
+                assert_eq!(false.status(), Err(0));
+                assert_eq!(().status(), Ok(()));
+        }
+}
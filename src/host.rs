@@ -0,0 +1,245 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::config;
+use crate::events::{self, EventBus, EventCallback};
+use crate::services::{self, LostService, ServiceRegistry};
+use crate::storage::{self, DirNames, PluginDirs};
+
+/// The version of the [`VPluginHost`] layout. New fields are only
+/// ever appended, so plugins can also check `size` before using them.
+pub const HOST_API_VERSION: u32 = 1;
+
+/// ## VPluginHost
+/// The callbacks VPlugin provides to a plugin when it's started.
+///
+/// If a plugin exports a function named `vplugin_attach`, taking a
+/// `*const VPluginHost`, it will be called right before its entry point.
+/// The pointer stays valid until the plugin is terminated, and every callback
+/// must be passed the `context` field as its first argument:
+/// ```c
+/// static const VPluginHost *host;
+///
+/// void vplugin_attach(const VPluginHost *h) {
+///         host = h;
+///         host->subscribe(host->context, "file_opened", on_file_opened, NULL);
+/// }
+/// ```
+#[derive(Debug)]
+#[repr(C)]
+pub struct VPluginHost {
+        /// The version of this struct's layout ([`HOST_API_VERSION`]).
+        pub version         : u32,
+        /// The size of this struct in bytes.
+        pub size            : usize,
+        /// Opaque pointer identifying the plugin to VPlugin.
+        pub context         : *mut c_void,
+        /// Subscribes to the event named `event`. Returns `0` on success.
+        pub subscribe       : unsafe extern "C" fn(
+                context  : *mut c_void,
+                event    : *const c_char,
+                callback : EventCallback,
+                user_data: *mut c_void
+        ) -> c_int,
+        /// Removes a subscription made with `subscribe`. Returns `0` if it existed.
+        pub unsubscribe     : unsafe extern "C" fn(
+                context : *mut c_void,
+                event   : *const c_char,
+                callback: EventCallback
+        ) -> c_int,
+        /// Publishes a `#[repr(C)]` interface (Usually a table of function pointers)
+        /// under `name` for other plugins to use. Returns `0` on success.
+        pub publish_service : unsafe extern "C" fn(
+                context  : *mut c_void,
+                name     : *const c_char,
+                major    : u32,
+                minor    : u32,
+                interface: *const c_void
+        ) -> c_int,
+        /// Withdraws a service published by the plugin, notifying its consumers.
+        pub withdraw_service: unsafe extern "C" fn(
+                context: *mut c_void,
+                name   : *const c_char
+        ) -> c_int,
+        /// Returns the interface of the service `name`, if it's published with the same
+        /// `major` and at least `minor` version, or NULL. `on_lost` (Which may be NULL)
+        /// is called with `user_data` if the service is withdrawn.
+        pub request_service : unsafe extern "C" fn(
+                context  : *mut c_void,
+                name     : *const c_char,
+                major    : u32,
+                minor    : u32,
+                /* Spelled out so cbindgen knows it's a nullable function pointer. */
+                on_lost  : Option<unsafe extern "C" fn(service: *const c_char, user_data: *mut c_void)>,
+                user_data: *mut c_void
+        ) -> *const c_void,
+        /// Tells VPlugin the plugin doesn't use a service anymore.
+        pub release_service : unsafe extern "C" fn(
+                context: *mut c_void,
+                name   : *const c_char
+        ) -> c_int,
+        /// Explains why the entry point or a hook is about to fail. The message is reported
+        /// to the host along with the returned code, and cleared before every call.
+        pub set_error       : unsafe extern "C" fn(
+                context: *mut c_void,
+                message: *const c_char
+        ) -> c_int,
+        /// Returns the directory the plugin keeps its data in (Never deleted by VPlugin),
+        /// or NULL if the plugin was not loaded through a manager.
+        pub data_dir        : unsafe extern "C" fn(context: *mut c_void) -> *const c_char,
+        /// Returns the directory the plugin keeps its settings in, or NULL.
+        pub config_dir      : unsafe extern "C" fn(context: *mut c_void) -> *const c_char,
+        /// Returns the directory the plugin keeps its caches in, or NULL.
+        pub cache_dir       : unsafe extern "C" fn(context: *mut c_void) -> *const c_char,
+        /// Returns the plugin's settings (Its defaults merged with the user's `config.toml`)
+        /// as a TOML document, or NULL if it doesn't declare any.
+        pub get_config      : unsafe extern "C" fn(context: *mut c_void) -> *const c_char,
+}
+
+/// An exported `const char *vplugin_last_error(void)`, for plugins that report their
+/// errors without the host callbacks.
+type LastErrorFn = unsafe extern "C" fn() -> *const c_char;
+
+/// The last error message reported by a plugin, either through `set_error`
+/// or its exported `vplugin_last_error` function.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ErrorSlot {
+        reported: Arc<Mutex<Option<String>>>,
+        exported: Option<LastErrorFn>,
+}
+
+impl ErrorSlot {
+        pub(crate) fn new(exported: Option<LastErrorFn>) -> Self {
+                Self { reported: Arc::default(), exported }
+        }
+
+        /// Forgets the message of a previous call.
+        pub(crate) fn clear(&self) {
+                *self.reported.lock().unwrap() = None;
+        }
+
+        /// Returns the message reported since the last `clear`, falling back
+        /// to the one returned by `vplugin_last_error`.
+        pub(crate) fn message(&self) -> Option<String> {
+                if let Some(message) = self.reported.lock().unwrap().clone() {
+                        return Some(message);
+                }
+                let exported = self.exported?;
+                unsafe {
+                        let message = exported();
+                        (!message.is_null()).then(|| CStr::from_ptr(message).to_string_lossy().into_owned())
+                }
+        }
+}
+
+/// State shared between a [PluginManager](crate::PluginManager) and
+/// the plugins it started.
+#[derive(Debug, Default)]
+pub(crate) struct HostState {
+        pub(crate) events  : Mutex<EventBus>,
+        pub(crate) services: Mutex<ServiceRegistry>,
+}
+
+/// Everything VPlugin knows about a started plugin on the host side.
+/// Its address is handed to the plugin as `VPluginHost::context`,
+/// so it's always kept boxed.
+#[derive(Debug)]
+pub(crate) struct PluginContext {
+        pub(crate) id    : u64,
+        pub(crate) plugin: String,
+        pub(crate) state : Arc<HostState>,
+        pub(crate) errors: ErrorSlot,
+        pub(crate) dirs  : Option<DirNames>,
+        pub(crate) config: Option<CString>,
+        pub(crate) api   : VPluginHost,
+}
+
+static NEXT_CONTEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+impl PluginContext {
+        pub(crate) fn new(plugin: &str, state: Arc<HostState>, errors: ErrorSlot, dirs: Option<&PluginDirs>) -> Box<Self> {
+                let mut context = Box::new(Self {
+                        id    : NEXT_CONTEXT_ID.fetch_add(1, Ordering::Relaxed),
+                        plugin: plugin.to_owned(),
+                        state,
+                        errors,
+                        dirs  : dirs.and_then(DirNames::new),
+                        config: None,
+                        api   : VPluginHost {
+                                version         : HOST_API_VERSION,
+                                size            : std::mem::size_of::<VPluginHost>(),
+                                context         : std::ptr::null_mut(),
+                                subscribe       : events::subscribe,
+                                unsubscribe     : events::unsubscribe,
+                                publish_service : services::publish_service,
+                                withdraw_service: services::withdraw_service,
+                                request_service : services::request_service,
+                                release_service : services::release_service,
+                                set_error,
+                                data_dir        : storage::data_dir,
+                                config_dir      : storage::config_dir,
+                                cache_dir       : storage::cache_dir,
+                                get_config      : config::get_config,
+                        },
+                });
+                context.api.context = &mut *context as *mut Self as *mut c_void;
+                context
+        }
+
+        /// Turns the `context` pointer passed to a host callback back into the context.
+        ///
+        /// ## Safety
+        /// The pointer must come from `VPluginHost::context` of a plugin that is still attached.
+        pub(crate) unsafe fn from_raw<'a>(context: *mut c_void) -> Option<&'a Self> {
+                (context as *const Self).as_ref()
+        }
+
+        /// Drops everything the plugin registered through the host callbacks,
+        /// notifying the consumers of the services it provided.
+        pub(crate) fn detach(&self) {
+                self.state.events.lock().unwrap().unsubscribe_all(self.id);
+
+                let lost = self.state.services.lock().unwrap().detach(self.id);
+                if !lost.is_empty() {
+                        log::warn!(
+                                "Plugin '{}' was terminated while {} consumer(s) still used its services.",
+                                self.plugin,
+                                lost.len()
+                        );
+                }
+                lost.into_iter().for_each(LostService::notify);
+        }
+}
+
+/// Host callback: records the error message of the calling plugin (NULL clears it).
+unsafe extern "C" fn set_error(context: *mut c_void, message: *const c_char) -> c_int {
+        let Some(context) = PluginContext::from_raw(context) else {
+                return -1;
+        };
+
+        let message = (!message.is_null()).then(|| CStr::from_ptr(message).to_string_lossy().into_owned());
+        *context.errors.reported.lock().unwrap() = message;
+        0
+}
+
+impl Drop for PluginContext {
+        fn drop(&mut self) {
+                self.detach();
+        }
+}
@@ -0,0 +1,185 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::ffi::{c_int, c_void};
+use std::sync::{Arc, Weak};
+use crate::error::VPluginError;
+use crate::loader::LoadedObject;
+use crate::plugin::Plugin;
+
+/// Exported by plugins supporting instances as `vplugin_create_instance`.
+type CreateInstance  = unsafe extern "C" fn() -> *mut c_void;
+/// Exported by plugins supporting instances as `vplugin_destroy_instance`.
+type DestroyInstance = unsafe extern "C" fn(*mut c_void);
+
+/// ## VInstanceHook
+/// A hook called on a plugin instance: Like a [VHook](crate::VHook), but taking
+/// the instance pointer before the data, eg. `int on_save(void *instance, void *doc)`.
+/// The same safety concerns apply.
+pub type VInstanceHook = unsafe extern "C" fn(*mut c_void, *mut c_void) -> c_int;
+
+/// ## PluginInstance
+/// An instance of a started plugin, created with [instantiate](crate::PluginManager::instantiate).
+///
+/// Plugins keeping their state in globals can only be started once per process. Those exporting
+/// `void *vplugin_create_instance(void)` and `void vplugin_destroy_instance(void *)` can instead
+/// keep it behind the pointer they return, and the host can create as many independent
+/// instances as it needs. Their hooks receive that pointer (See [VInstanceHook]).
+///
+/// The instance is destroyed when dropped, unless the plugin was terminated meanwhile
+/// (In which case the plugin was responsible for it).
+#[derive(Debug)]
+pub struct PluginInstance {
+        plugin : String,
+        ptr    : *mut c_void,
+        destroy: Option<DestroyInstance>,
+        /* Dead once the plugin is terminated or unloaded. */
+        alive  : Weak<()>,
+}
+
+impl PluginInstance {
+        /// Asks the started `plugin` for a new instance.
+        pub(crate) fn create(plugin: &Plugin) -> Result<Self, VPluginError> {
+                let create: CreateInstance = plugin.load_symbol("vplugin_create_instance")?;
+                let ptr = unsafe { create() };
+                if ptr.is_null() {
+                        let message = plugin.last_error();
+                        log::error!(
+                                "Plugin '{}' couldn't create an instance: {}",
+                                plugin.display_name(),
+                                message.as_deref().unwrap_or("No reason given")
+                        );
+                        return Err(VPluginError::FailedToInitialize { plugin: plugin.display_name().to_owned(), code: -1, message });
+                }
+
+                let destroy = plugin.load_symbol_unchecked::<DestroyInstance>("vplugin_destroy_instance");
+                if destroy.is_none() {
+                        log::warn!("Plugin '{}' can't destroy its instances, they will be leaked.", plugin.display_name());
+                }
+                Ok(Self {
+                        plugin: plugin.display_name().to_owned(),
+                        ptr,
+                        destroy,
+                        alive : Arc::downgrade(&plugin.alive),
+                })
+        }
+
+        /// Returns the name of the plugin the instance belongs to.
+        pub fn plugin(&self) -> &str {
+                &self.plugin
+        }
+
+        /// Returns the pointer the plugin returned for this instance.
+        pub fn as_ptr(&self) -> *mut c_void {
+                self.ptr
+        }
+
+        /// Returns whether the plugin is still started, so the instance can be used.
+        pub fn is_alive(&self) -> bool {
+                self.alive.strong_count() > 0
+        }
+}
+
+impl Drop for PluginInstance {
+        fn drop(&mut self) {
+                match self.destroy {
+                        Some(destroy) if self.is_alive() => unsafe { destroy(self.ptr) },
+                        _ => (),
+                }
+        }
+}
+
+/// ## InstanceHook
+/// A hook of a plugin, bound to one of its instances by
+/// [get_instance_hook](crate::PluginManager::get_instance_hook).
+/// Like a [Hook](crate::Hook), it keeps the plugin's object file loaded.
+#[derive(Debug, Clone)]
+pub struct InstanceHook {
+        pub(crate) plugin  : String,
+        pub(crate) hook    : VInstanceHook,
+        pub(crate) instance: *mut c_void,
+        pub(crate) _library: Arc<LoadedObject>,
+        /* Dead once the plugin is terminated or dropped. */
+        pub(crate) alive   : Weak<()>,
+}
+
+impl InstanceHook {
+        /// Calls the hook with the instance pointer and `data`.
+        ///
+        /// # Safety
+        /// The instance must still be alive, and `data` must be what the hook expects.
+        pub unsafe fn call(&self, data: *mut c_void) -> c_int {
+                (self.hook)(self.instance, data)
+        }
+
+        /// Returns the name of the plugin the hook belongs to.
+        pub fn plugin(&self) -> &str {
+                &self.plugin
+        }
+
+        /// Returns whether the plugin is still started.
+        pub fn is_alive(&self) -> bool {
+                self.alive.strong_count() > 0
+        }
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /* Each instance points to the number of times it was destroyed. */
+        unsafe extern "C" fn destroy(instance: *mut c_void) {
+                (*(instance as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn instance(destroyed: &AtomicUsize, destroy: Option<DestroyInstance>, alive: &Arc<()>) -> PluginInstance {
+                PluginInstance {
+                        plugin : "test".into(),
+                        ptr    : destroyed as *const AtomicUsize as *mut c_void,
+                        destroy,
+                        alive  : Arc::downgrade(alive),
+                }
+        }
+
+        #[test]
+        fn destroys_instances_when_dropped() {
+                let (destroyed, alive) = (AtomicUsize::new(0), Arc::new(()));
+                let instance = instance(&destroyed, Some(destroy), &alive);
+                assert!(instance.is_alive());
+                assert_eq!(instance.plugin(), "test");
+                drop(instance);
+                assert_eq!(destroyed.load(Ordering::SeqCst), 1);
+        }
+
+        #[test]
+        fn leaves_instances_of_terminated_plugins_alone() {
+                let (destroyed, alive) = (AtomicUsize::new(0), Arc::new(()));
+                let instance = instance(&destroyed, Some(destroy), &alive);
+                /* What terminating the plugin does. */
+                drop(alive);
+                assert!(!instance.is_alive());
+                drop(instance);
+                assert_eq!(destroyed.load(Ordering::SeqCst), 0);
+        }
+
+        #[test]
+        fn leaks_instances_plugins_cannot_destroy() {
+                let (destroyed, alive) = (AtomicUsize::new(0), Arc::new(()));
+                drop(instance(&destroyed, None, &alive));
+                assert_eq!(destroyed.load(Ordering::SeqCst), 0);
+        }
+}
@@ -0,0 +1,309 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+//! # Plugin interfaces
+//! Traits the host and its plugins share, called across the FFI boundary through a vtable.
+//!
+//! Both sides declare the trait with [`plugin_interface!`](crate::plugin_interface), usually
+//! in a crate they both depend on:
+//! ```rust
+//! use std::ffi::c_int;
+//!
+//! vplugin::plugin_interface! {
+//!     pub trait Exporter {
+//!         fn extension(&self) -> u32;
+//!         fn export(&self, data: *const u8, len: usize) -> c_int;
+//!     }
+//! }
+//! ```
+//! The plugin implements it and exports its implementation with [`export_interface!`](crate::export_interface):
+//! ```rust
+//! # use std::ffi::c_int;
+//! # vplugin::plugin_interface! {
+//! #     pub trait Exporter {
+//! #         fn extension(&self) -> u32;
+//! #         fn export(&self, data: *const u8, len: usize) -> c_int;
+//! #     }
+//! # }
+//! struct Png;
+//!
+//! impl Exporter for Png {
+//!     fn extension(&self) -> u32 { 0x706e67 }
+//!     fn export(&self, data: *const u8, len: usize) -> c_int { 0 }
+//! }
+//!
+//! static PNG: Png = Png;
+//! vplugin::export_interface!(Exporter: PNG);
+//! ```
+//! And the host gets it from the started plugin with [`Plugin::interface`](crate::Plugin::interface):
+//! ```text
+//! let exporter = plugin.interface::<dyn Exporter>()?;
+//! exporter.export(data.as_ptr(), data.len());
+//! ```
+//!
+//! Methods take `&self` and FFI-safe arguments (The implementation must be `Sync`).
+//! A panic inside the plugin's implementation aborts the process.
+
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use crate::error::VPluginError;
+
+/// What a plugin exports for an interface, as `RawInterface vplugin_interface_<Trait>(void)`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RawInterface {
+        /// The layout hash of the interface the plugin was built with.
+        pub layout: u64,
+        /// The implementation, passed to every function of the vtable.
+        pub this  : *const c_void,
+        /// The functions implementing the trait's methods, in order.
+        pub vtable: *const c_void,
+}
+
+/// ## Interface
+/// Implemented by [`plugin_interface!`](crate::plugin_interface) for `dyn Trait`.
+///
+/// # Safety
+/// `wrap` must only call the functions of a vtable with the layout `LAYOUT` describes.
+pub unsafe trait Interface {
+        /// The name of the trait, used to look the interface up.
+        const NAME  : &'static str;
+        /// A hash of the trait's name and method signatures, which must match the plugin's.
+        const LAYOUT: u64;
+
+        /// Returns a trait object calling the functions of `raw`.
+        #[doc(hidden)]
+        fn wrap(raw: RawInterface) -> Box<Self>;
+}
+
+/// ## PluginInterface
+/// An interface of a plugin, returned by [`Plugin::interface`](crate::Plugin::interface).
+/// Dereferences to the trait object, and can't outlive the plugin.
+pub struct PluginInterface<'a, I: ?Sized> {
+        pub(crate) inner  : Box<I>,
+        pub(crate) _plugin: PhantomData<&'a ()>,
+}
+
+impl<I: Interface + ?Sized> PluginInterface<'_, I> {
+        /// Wraps what `plugin` exported for the interface, refusing
+        /// implementations of another version of it.
+        pub(crate) fn new(plugin: &str, raw: RawInterface) -> Result<Self, VPluginError> {
+                if raw.layout != I::LAYOUT || raw.vtable.is_null() {
+                        log::error!(
+                                "Plugin '{}' implements another version of interface '{}' (Layout {:#x}, expected {:#x}).",
+                                plugin,
+                                I::NAME,
+                                raw.layout,
+                                I::LAYOUT
+                        );
+                        return Err(VPluginError::InterfaceMismatch { plugin: plugin.to_owned(), interface: I::NAME.to_owned() });
+                }
+                Ok(Self { inner: I::wrap(raw), _plugin: PhantomData })
+        }
+}
+
+impl<I: ?Sized> Deref for PluginInterface<'_, I> {
+        type Target = I;
+
+        fn deref(&self) -> &I {
+                &self.inner
+        }
+}
+
+/// Hashes the signature of an interface (FNV-1a), at compile time.
+#[doc(hidden)]
+pub const fn layout_hash(signature: &str) -> u64 {
+        let bytes = signature.as_bytes();
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut i = 0;
+        while i < bytes.len() {
+                hash ^= bytes[i] as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+                i += 1;
+        }
+        hash
+}
+
+/// ## plugin_interface!
+/// Declares a trait usable across the FFI boundary (See the [module documentation](crate::interface)).
+///
+/// Along with the trait, it generates a `#[repr(C)]` vtable with one `extern "C"` function per method
+/// (Taking the implementation first), an implementation of the trait calling them for the host,
+/// and the functions filling it from any implementation for the plugin. The layout hash covers the
+/// trait's name and the methods' names and signatures as written, so both sides should use the
+/// same declaration.
+#[macro_export]
+macro_rules! plugin_interface {
+        (
+                $(#[$attr:meta])*
+                $vis:vis trait $name:ident {
+                        $(
+                                $(#[$mattr:meta])*
+                                fn $method:ident(&self $(, $arg:ident : $ty:ty)* $(,)?) $(-> $ret:ty)?;
+                        )*
+                }
+        ) => {
+                $(#[$attr])*
+                $vis trait $name {
+                        $(
+                                $(#[$mattr])*
+                                fn $method(&self $(, $arg: $ty)*) $(-> $ret)?;
+                        )*
+                }
+
+                const _: () = {
+                        #[repr(C)]
+                        pub struct VTable {
+                                $($method: unsafe extern "C" fn(*const ::std::ffi::c_void $(, $ty)*) $(-> $ret)?,)*
+                        }
+
+                        /* The plugin's side: Calls into its implementation. */
+                        struct Shims<T>(::std::marker::PhantomData<T>);
+
+                        #[allow(non_snake_case)]
+                        impl<T: $name> Shims<T> {
+                                const VTABLE: VTable = VTable {
+                                        $($method: {
+                                                unsafe extern "C" fn $method<T: $name>(this: *const ::std::ffi::c_void $(, $arg: $ty)*) $(-> $ret)? {
+                                                        (*(this as *const T)).$method($($arg),*)
+                                                }
+                                                $method::<T>
+                                        },)*
+                                };
+                        }
+
+                        impl dyn $name {
+                                #[doc(hidden)]
+                                pub fn __vplugin_export<T: $name + Sync>(value: &'static T) -> $crate::interface::RawInterface {
+                                        $crate::interface::RawInterface {
+                                                layout: <dyn $name as $crate::interface::Interface>::LAYOUT,
+                                                this  : value as *const T as *const ::std::ffi::c_void,
+                                                vtable: &Shims::<T>::VTABLE as *const VTable as *const ::std::ffi::c_void,
+                                        }
+                                }
+                        }
+
+                        /* The host's side: Calls through the plugin's vtable. */
+                        struct Proxy($crate::interface::RawInterface);
+
+                        impl $name for Proxy {
+                                $(
+                                        fn $method(&self $(, $arg: $ty)*) $(-> $ret)? {
+                                                unsafe { ((*(self.0.vtable as *const VTable)).$method)(self.0.this $(, $arg)*) }
+                                        }
+                                )*
+                        }
+
+                        unsafe impl $crate::interface::Interface for dyn $name {
+                                const NAME  : &'static str = stringify!($name);
+                                const LAYOUT: u64 = $crate::interface::layout_hash(concat!(
+                                        stringify!($name)
+                                        $(, ";", stringify!($method), "(", $(stringify!($ty), ",",)* ")" $(, "->", stringify!($ret))?)*
+                                ));
+
+                                fn wrap(raw: $crate::interface::RawInterface) -> Box<Self> {
+                                        Box::new(Proxy(raw))
+                                }
+                        }
+                };
+        };
+}
+
+/// ## export_interface!
+/// Exports a static implementation of an interface declared with
+/// [`plugin_interface!`](crate::plugin_interface) from a plugin, as `vplugin_interface_<Trait>`.
+#[macro_export]
+macro_rules! export_interface {
+        ($name:ident : $value:path) => {
+                const _: () = {
+                        #[export_name = concat!("vplugin_interface_", stringify!($name))]
+                        extern "C" fn __vplugin_interface() -> $crate::interface::RawInterface {
+                                <dyn $name>::__vplugin_export(&$value)
+                        }
+                };
+        };
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use std::ffi::c_int;
+
+        crate::plugin_interface! {
+                pub trait Exporter {
+                        fn extension(&self) -> u32;
+                        fn export(&self, data: *const u8, len: usize) -> c_int;
+                }
+        }
+
+        /* The same interface, once a method changed (Only used to check its layout). */
+        #[allow(dead_code)]
+        mod changed {
+                crate::plugin_interface! {
+                        pub trait Exporter {
+                                fn extension(&self) -> u64;
+                                fn export(&self, data: *const u8, len: usize) -> std::ffi::c_int;
+                        }
+                }
+        }
+
+        struct Png {
+                extension: u32,
+        }
+
+        impl Exporter for Png {
+                fn extension(&self) -> u32 {
+                        self.extension
+                }
+
+                fn export(&self, data: *const u8, len: usize) -> c_int {
+                        let data = unsafe { std::slice::from_raw_parts(data, len) };
+                        data.iter().map(|&b| b as c_int).sum()
+                }
+        }
+
+        static PNG: Png = Png { extension: 0x706e67 };
+        crate::export_interface!(Exporter: PNG);
+
+        extern "C" {
+                fn vplugin_interface_Exporter() -> RawInterface;
+        }
+
+        #[test]
+        fn calls_the_exported_implementation() {
+                let raw = unsafe { vplugin_interface_Exporter() };
+                assert_eq!(raw.this, &PNG as *const Png as *const c_void);
+
+                let exporter = PluginInterface::<dyn Exporter>::new("test", raw).unwrap();
+                assert_eq!(exporter.extension(), 0x706e67);
+                assert_eq!(exporter.export([1, 2, 3].as_ptr(), 3), 6);
+        }
+
+        #[test]
+        fn refuses_other_layouts() {
+                assert_eq!(<dyn Exporter as Interface>::NAME, <dyn changed::Exporter as Interface>::NAME);
+                assert_ne!(<dyn Exporter as Interface>::LAYOUT, <dyn changed::Exporter as Interface>::LAYOUT);
+
+                let raw = unsafe { vplugin_interface_Exporter() };
+                assert!(matches!(
+                        PluginInterface::<dyn changed::Exporter>::new("test", raw),
+                        Err(VPluginError::InterfaceMismatch { plugin, interface }) if plugin == "test" && interface == "Exporter"
+                ));
+                let null = RawInterface { vtable: std::ptr::null(), ..raw };
+                assert!(PluginInterface::<dyn Exporter>::new("test", null).is_err());
+        }
+}
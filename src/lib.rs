@@ -34,16 +34,12 @@
 //! const FILENAME: &str = "plugin/example.vpl";
 //! 
 //! fn main() {
-//!     let manager    = PluginManager::new();
-//!     let mut plugin = manager.load_plugin(FILENAME).expect("Couldn't load plugin");
-//! 
+//!     let mut manager = PluginManager::new();
 //!     manager.set_entry_point("app_entry");
-//! 
-//!     manager.begin_plugin().expect("Couldn't begin plugin");
-//!     if plugin.terminate().is_err() {
-//!             unsafe { plugin.force_terminate(); }
-//!             plugin_manager.shutdown();
-//!     };
+//!
+//!     let mut plugin = manager.load_plugin(FILENAME).expect("Couldn't load plugin");
+//!     manager.begin_plugin(&mut plugin).expect("Couldn't begin plugin");
+//!     plugin.terminate().expect("Couldn't terminate plugin");
 //! }
 //! ```
 //! Then, create a new plugin with [vplugin-init](https://github.com/VPlugin/vplugin-init/):
@@ -57,19 +53,24 @@
 //! 
 //! $ cd plugin/
 //! ```
-//! Afterwards, create an entry point and a destructor for the plugin:
+//! Afterwards, create an entry point and a destructor for the plugin
+//! (Or let the `sdk` module generate them with the `plugin-sdk` feature):
 //! `plugin.rs`:
 //! ```rust
+//! /* The ABI version the plugin was built for, checked before it is loaded. */
+//! #[no_mangle]
+//! pub static VPLUGIN_ABI_VERSION: u32 = vplugin::ABI_VERSION;
+//!
 //! /* Entry point */
 //! #[no_mangle]
-//! fn app_entry()-> i32 {
+//! extern "C" fn app_entry() -> i32 {
 //!     println!("Hello plugin!");
 //!     0
 //! }
 //! 
 //! /* Destructor */
 //! #[no_mangle]
-//! fn vplugin_exit() {
+//! extern "C" fn vplugin_exit() {
 //!     println!("Goodbye plugin!");
 //! }
 //! ```
@@ -86,7 +87,7 @@
 //! Goodbye plugin!
 //! ```
 
-/* I am still working on the C/C++ part. */
+/* C and C++ hosts can use the C API, available with the `capi` feature. */
 #![allow(improper_ctypes_definitions)]
 
 extern crate zip;
@@ -96,7 +97,39 @@ extern crate toml;
 mod plugin;
 mod plugin_manager;
 mod error;
+mod abi;
+mod cache;
+mod loader;
+mod symbols;
+mod hook;
+mod instance;
+mod host;
+mod events;
+mod services;
+mod storage;
+mod config;
+mod state;
+pub mod interface;
+pub mod ffi;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "plugin-sdk")]
+pub mod sdk;
 
 /// Reexports of VPlugin's types.
 pub use plugin_manager::*;
 pub use plugin::*;
+pub use error::VPluginError;
+pub use abi::{AbiShim, ABI_VERSION, BUILD_INFO};
+pub use symbols::{ExportedSymbol, ExportedSymbolKind};
+pub use hook::{Hook, HookFn, HookRegistry, HookResult, HookStatus};
+pub use instance::{InstanceHook, PluginInstance, VInstanceHook};
+pub use interface::{Interface, PluginInterface, RawInterface};
+pub use ffi::{VCallback, VOption, VResult, VSlice, VStr, VString, VVec};
+pub use host::{VPluginHost, HOST_API_VERSION};
+pub use events::EventCallback;
+pub use services::{ServiceInfo, ServiceLostCallback};
+pub use storage::PluginDirs;
+pub use loader::{LoadFlagOverrides, LoadFlags};
+pub use config::{Config, ConfigSchema, ConfigSetting, ConfigType, ConfigValue, CONFIG_FILE};
+pub use state::{PluginState, STATE_FILE};
@@ -0,0 +1,429 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+//! Platform specific parts of loading object files.
+
+#[cfg(target_os = "linux")]
+extern crate libc;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use libloading::Library;
+use serde_derive::Deserialize;
+use crate::error::VPluginError;
+use crate::symbols::{self, DynamicInfo, ObjectInfo};
+
+/// ## LoadFlags
+/// How the dynamic linker loads plugins' object files (And their bundled libraries).
+/// The defaults match `Library::new`: Symbols are resolved lazily and kept local to the plugin.
+///
+/// Set for every plugin with [set_load_flags](crate::PluginManager::set_load_flags), and
+/// overridden by a plugin in the `[loader]` table of its `metadata.toml`:
+/// ```toml
+/// [loader]
+/// now       = true
+/// namespace = true
+/// ```
+/// Flags the platform doesn't support are ignored, with a warning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadFlags {
+        /// Resolve every symbol when loading (`RTLD_NOW`), so unresolved
+        /// symbols fail the load instead of the first call using them.
+        pub now      : bool,
+        /// Make the plugin's symbols available to object files loaded after it (`RTLD_GLOBAL`),
+        /// for plugins providing symbols to others.
+        pub global   : bool,
+        /// Prefer the plugin's own symbols over already loaded ones with the same name
+        /// (`RTLD_DEEPBIND`, Linux with glibc only).
+        pub deepbind : bool,
+        /// Load the plugin into its own link map namespace (`dlmopen`, Linux with glibc only),
+        /// so it can bundle a version of a library conflicting with the one of another
+        /// plugin or the host. The plugin gets its own copy of every library it needs,
+        /// including the C library (And its `stdout` buffer, not flushed when the host exits).
+        pub namespace: bool,
+}
+
+/// The `[loader]` table of a plugin's `metadata.toml`: Each flag set
+/// there replaces the corresponding default of the PluginManager.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoadFlagOverrides {
+        pub now      : Option<bool>,
+        pub global   : Option<bool>,
+        pub deepbind : Option<bool>,
+        pub namespace: Option<bool>,
+}
+
+impl LoadFlagOverrides {
+        /// Returns `defaults` with the flags set by the plugin replaced.
+        pub fn apply(&self, defaults: LoadFlags) -> LoadFlags {
+                LoadFlags {
+                        now      : self.now.unwrap_or(defaults.now),
+                        global   : self.global.unwrap_or(defaults.global),
+                        deepbind : self.deepbind.unwrap_or(defaults.deepbind),
+                        namespace: self.namespace.unwrap_or(defaults.namespace),
+                }
+        }
+}
+
+/// Why an object file couldn't be opened.
+pub(crate) enum OpenError {
+        /// Reported by libloading.
+        Library(libloading::Error),
+        /// Loading into a new namespace failed, with the dynamic linker's message.
+        Namespace(String),
+}
+
+/// The link map namespace a plugin is loaded into, once its first object file is.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub(crate) type Namespace = Option<libc::Lmid_t>;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub(crate) type Namespace = Option<()>;
+
+/// Refuses the flags this platform doesn't support, so the plugin is
+/// still loaded (The way it would be without them).
+pub(crate) fn supported(plugin: &str, mut flags: LoadFlags) -> LoadFlags {
+        let unsupported = |flag: &str| log::warn!("Plugin '{}': '{}' is not supported on this platform, ignoring it.", plugin, flag);
+        if cfg!(not(unix)) && (flags.now || flags.global) {
+                unsupported(if flags.now { "now" } else { "global" });
+                (flags.now, flags.global) = (false, false);
+        }
+        if cfg!(not(all(target_os = "linux", target_env = "gnu"))) && (flags.deepbind || flags.namespace) {
+                unsupported(if flags.deepbind { "deepbind" } else { "namespace" });
+                (flags.deepbind, flags.namespace) = (false, false);
+        }
+        if flags.namespace && flags.global {
+                /* dlmopen refuses RTLD_GLOBAL for new namespaces, and nothing would see the symbols anyways. */
+                log::warn!("Plugin '{}' is loaded into its own namespace, 'global' has no effect.", plugin);
+                flags.global = false;
+        }
+        flags
+}
+
+/// Opens the object file at `path` with `flags` (Which must be [supported]). Object files of
+/// a plugin loaded into its own namespace must share `namespace`, set by the first one.
+pub(crate) fn open(path: &Path, flags: LoadFlags, namespace: &mut Namespace) -> Result<Library, OpenError> {
+        #[cfg(unix)]
+        {
+                use libloading::os::unix;
+
+                #[allow(unused_mut)]
+                let mut mode = if flags.now { unix::RTLD_NOW } else { unix::RTLD_LAZY };
+                mode |= if flags.global { unix::RTLD_GLOBAL } else { unix::RTLD_LOCAL };
+
+                #[cfg(all(target_os = "linux", target_env = "gnu"))]
+                {
+                        if flags.deepbind {
+                                mode |= libc::RTLD_DEEPBIND;
+                        }
+                        if flags.namespace {
+                                return open_in_namespace(path, mode, namespace);
+                        }
+                }
+                let _ = namespace;
+                unsafe { unix::Library::open(Some(path), mode) }.map(Library::from).map_err(OpenError::Library)
+        }
+        #[cfg(not(unix))]
+        {
+                let _ = (flags, namespace);
+                unsafe { Library::new(path) }.map_err(OpenError::Library)
+        }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn open_in_namespace(path: &Path, mode: i32, namespace: &mut Namespace) -> Result<Library, OpenError> {
+        use std::ffi::{CStr, CString};
+        use std::os::unix::ffi::OsStrExt;
+
+        let filename = CString::new(path.as_os_str().as_bytes()).map_err(|e| OpenError::Namespace(e.to_string()))?;
+        unsafe {
+                let handle = libc::dlmopen(namespace.unwrap_or(libc::LM_ID_NEWLM), filename.as_ptr(), mode);
+                if handle.is_null() {
+                        let error = libc::dlerror();
+                        return Err(OpenError::Namespace(match error.is_null() {
+                                true  => String::from("unknown error"),
+                                false => CStr::from_ptr(error).to_string_lossy().into_owned(),
+                        }));
+                }
+
+                if namespace.is_none() {
+                        let mut lmid: libc::Lmid_t = 0;
+                        if libc::dlinfo(handle, libc::RTLD_DI_LMID, &mut lmid as *mut _ as *mut std::ffi::c_void) == 0 {
+                                *namespace = Some(lmid);
+                        }
+                }
+                Ok(Library::from(libloading::os::unix::Library::from_raw(handle)))
+        }
+}
+
+/// The directory plugins are extracted to, unless the host sets another one.
+pub(crate) fn default_work_dir() -> PathBuf {
+        env::temp_dir().join("vplugin")
+}
+
+/// Whether object files can be loaded from memory on this platform.
+pub(crate) const MEMFD_SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Copies `contents` into an anonymous in-memory file, returning it along with
+/// a path it can be opened from (`/proc/self/fd/N`) as long as it's kept open.
+#[cfg(target_os = "linux")]
+pub(crate) fn memfd(name: &str, contents: &mut impl Read) -> io::Result<(File, PathBuf)> {
+        use std::ffi::CString;
+        use std::os::fd::{AsRawFd, FromRawFd};
+
+        let name = CString::new(format!("vplugin:{}", name)).unwrap_or_default();
+        let fd   = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+                return Err(io::Error::last_os_error());
+        }
+
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        io::copy(contents, &mut file)?;
+        let path = PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd()));
+        Ok((file, path))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn memfd(_name: &str, _contents: &mut impl Read) -> io::Result<(File, PathBuf)> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "object files can only be loaded from memory on Linux"))
+}
+
+/// ## LoadedObject
+/// A plugin's object file, along with the in-memory files it and its bundled libraries were
+/// loaded from. Those stay open as long as the object file is loaded: The next in-memory file
+/// would otherwise get the same path, and the dynamic linker would hand out this object file again.
+#[derive(Debug)]
+pub(crate) struct LoadedObject {
+        /* Unloaded before the files are closed. */
+        library: Library,
+        _memfds: Vec<File>,
+}
+
+impl LoadedObject {
+        pub(crate) fn new(library: Library, memfds: Vec<File>) -> Self {
+                Self { library, _memfds: memfds }
+        }
+}
+
+impl Deref for LoadedObject {
+        type Target = Library;
+
+        fn deref(&self) -> &Library {
+                &self.library
+        }
+}
+
+/// Refuses to load object files from `dir` if it's on a filesystem mounted `noexec`,
+/// where loading would fail with an unhelpful error from the dynamic linker.
+#[cfg(target_os = "linux")]
+pub(crate) fn check_executable(dir: &Path) -> Result<(), VPluginError> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+                return Ok(());
+        };
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } == 0 && stat.f_flag & libc::ST_NOEXEC != 0 {
+                log::error!(
+                        "'{}' is on a filesystem mounted noexec, set another work directory or load plugins from memory.",
+                        dir.display()
+                );
+                return Err(VPluginError::NotExecutable { path: dir.to_owned() });
+        }
+        Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn check_executable(_dir: &Path) -> Result<(), VPluginError> {
+        Ok(())
+}
+
+/// Checks every object file of `plugin` before any of them is loaded (See [symbols::check_loadable]),
+/// and that the `objfile` exports the `required` functions. Each file is only read once, what was
+/// read from the libraries (In the same order) and the `objfile` is returned.
+pub(crate) fn check_objects(
+        plugin   : &str,
+        libraries: &[(String, PathBuf)],
+        objfile  : (&str, &Path),
+        required : &[&str]
+) -> Result<(Vec<ObjectInfo>, ObjectInfo), VPluginError> {
+        let infos = libraries
+                .iter()
+                .map(|(library, path)| ObjectInfo::read(plugin, library, path))
+                .collect::<Result<Vec<_>, _>>()?;
+        let info  = ObjectInfo::read(plugin, objfile.0, objfile.1)?;
+
+        let mut bundled = Vec::new();
+        for ((_, path), library) in libraries.iter().zip(&infos) {
+                bundled.extend(path.file_name().map(|n| n.to_string_lossy().into_owned()));
+                bundled.extend(library.dynamic.soname.clone());
+        }
+        for ((library, path), object) in libraries.iter().zip(&infos) {
+                symbols::check_loadable(plugin, library, path, object, &bundled)?;
+        }
+        symbols::check_loadable(plugin, objfile.0, objfile.1, &info, &bundled)?;
+
+        for symbol in required {
+                if !info.exports.iter().any(|s| s.name == *symbol && s.is_function()) {
+                        log::error!("Plugin '{}' does not export '{}' from its object file.", plugin, symbol);
+                        return Err(VPluginError::missing_symbol(plugin, *symbol));
+                }
+        }
+        Ok((infos, info))
+}
+
+/// Returns whether the dynamic linker would find `library` (A `DT_NEEDED` entry of an object file
+/// in `origin`, searching `runpath` first), without loading it: Either it's already loaded, or it's
+/// in one of the directories searched or the linker's cache.
+#[cfg(target_os = "linux")]
+pub(crate) fn library_installed(library: &str, origin: &Path, runpath: &[String]) -> bool {
+        use std::ffi::CString;
+
+        if library.contains('/') {
+                return Path::new(library).is_file();
+        }
+        if let Ok(name) = CString::new(library) {
+                let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_LAZY | libc::RTLD_NOLOAD) };
+                if !handle.is_null() {
+                        unsafe { libc::dlclose(handle) };
+                        return true;
+                }
+        }
+
+        let origin = origin.to_string_lossy();
+        let mut dirs: Vec<PathBuf> = runpath
+                .iter()
+                .map(|d| PathBuf::from(d.replace("${ORIGIN}", &origin).replace("$ORIGIN", &origin)))
+                .collect();
+        if let Some(path) = env::var_os("LD_LIBRARY_PATH") {
+                dirs.extend(env::split_paths(&path));
+        }
+        if dirs.iter().any(|d| d.join(library).is_file()) {
+                return true;
+        }
+
+        /* The cache holds the name of every library it knows, NUL terminated. */
+        let key = format!("\0{}\0", library);
+        if std::fs::read("/etc/ld.so.cache").is_ok_and(|c| c.windows(key.len()).any(|w| w == key.as_bytes())) {
+                return true;
+        }
+        let multiarch = format!("{}-linux-gnu", env::consts::ARCH);
+        ["/lib", "/usr/lib", "/lib64", "/usr/lib64", "/usr/local/lib"]
+                .into_iter()
+                .flat_map(|d| [PathBuf::from(d), Path::new(d).join(&multiarch)])
+                .any(|d| d.join(library).is_file())
+}
+
+/// Only Linux is checked, other platforms assume the library is there.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn library_installed(_library: &str, _origin: &Path, _runpath: &[String]) -> bool {
+        true
+}
+
+/// Sorts the libraries bundled with `plugin` (Their name in the archive and path, along with the
+/// `dynamic` information read from each of them) so that every library comes after the bundled
+/// libraries it needs, keeping their order otherwise.
+pub(crate) fn dependency_order(
+        plugin   : &str,
+        libraries: Vec<(String, PathBuf)>,
+        dynamic  : &[DynamicInfo]
+) -> Result<Vec<(String, PathBuf)>, VPluginError> {
+        let mut names = Vec::with_capacity(libraries.len());
+        let mut needs = Vec::with_capacity(libraries.len());
+        for ((_, path), info) in libraries.iter().zip(dynamic) {
+                let mut known: Vec<String> = path.file_name().map(|n| n.to_string_lossy().into_owned()).into_iter().collect();
+                known.extend(info.soname.clone());
+                names.push(known);
+                needs.push(&info.needed);
+        }
+        let depends = |i: usize, j: usize| i != j && needs[i].iter().any(|n| names[j].contains(n));
+
+        let mut remaining: Vec<usize> = (0..libraries.len()).collect();
+        let mut order = Vec::with_capacity(libraries.len());
+        while !remaining.is_empty() {
+                let Some(next) = remaining.iter().position(|&i| !remaining.iter().any(|&j| depends(i, j))) else {
+                        let cycle: Vec<&str> = remaining.iter().map(|&i| libraries[i].0.as_str()).collect();
+                        log::error!("Libraries bundled with plugin '{}' depend on each other: {}", plugin, cycle.join(", "));
+                        return Err(VPluginError::invalid_plugin(
+                                plugin,
+                                format!("bundled libraries depend on each other: {}", cycle.join(", "))
+                        ));
+                };
+                order.push(remaining.remove(next));
+        }
+
+        let mut libraries: Vec<Option<(String, PathBuf)>> = libraries.into_iter().map(Some).collect();
+        Ok(order.into_iter().filter_map(|i| libraries[i].take()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        /// A bundled library named `name`, needing the `needed` ones.
+        fn library(name: &str, soname: Option<&str>, needed: &[&str]) -> ((String, PathBuf), DynamicInfo) {
+                let info = DynamicInfo {
+                        soname: soname.map(String::from),
+                        needed: needed.iter().map(|n| n.to_string()).collect(),
+                        ..Default::default()
+                };
+                ((format!("libs/{}", name), PathBuf::from("/tmp/libs").join(name)), info)
+        }
+
+        fn order(libraries: Vec<((String, PathBuf), DynamicInfo)>) -> Result<Vec<String>, VPluginError> {
+                let (libraries, dynamic): (Vec<_>, Vec<_>) = libraries.into_iter().unzip();
+                Ok(dependency_order("test", libraries, &dynamic)?.into_iter().map(|(name, _)| name).collect())
+        }
+
+        #[test]
+        fn loads_dependencies_first() {
+                let order = order(vec![
+                        library("liba.so", None, &["libb.so.1", "libc.so.6"]),
+                        library("libb.so", Some("libb.so.1"), &["libc.so"]),
+                        library("libc.so", None, &[]),
+                ]);
+                assert_eq!(order.unwrap(), ["libs/libc.so", "libs/libb.so", "libs/liba.so"]);
+        }
+
+        #[test]
+        fn keeps_independent_libraries_in_order() {
+                let order = order(vec![
+                        library("libz.so", None, &["libc.so.6"]),
+                        library("liba.so", None, &[]),
+                ]);
+                assert_eq!(order.unwrap(), ["libs/libz.so", "libs/liba.so"]);
+        }
+
+        #[test]
+        fn refuses_cycles() {
+                let result = order(vec![
+                        library("liba.so", None, &["libb.so"]),
+                        library("libb.so", None, &["libc.so"]),
+                        library("libc.so", None, &["liba.so"]),
+                        library("libd.so", None, &[]),
+                ]);
+                match result {
+                        Err(VPluginError::InvalidPlugin { reason, .. }) => {
+                                assert!(reason.contains("libs/liba.so, libs/libb.so, libs/libc.so"), "{}", reason);
+                        }
+                        other => panic!("{:?}", other),
+                }
+        }
+}
@@ -19,13 +19,16 @@
 extern crate libloading;
 extern crate log;
 
-use std::env::{self};
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::fs::{
         self,
         File
 };
-use serde::Deserialize;
 use serde_derive::Deserialize;
 use libloading::{
         Library,
@@ -34,7 +37,17 @@ use libloading::{
 use zip::ZipArchive;
 use crate::VHook;
 use crate::error::VPluginError;
-use std::io::ErrorKind::{*, self};
+use crate::abi::{self, AbiShim};
+use crate::cache::{self, ExtractionCache};
+use crate::loader::{self, LoadFlagOverrides, LoadFlags, LoadedObject, OpenError};
+use crate::symbols::{self, ExportedSymbol};
+use crate::host::PluginContext;
+use crate::interface::{Interface, PluginInterface, RawInterface};
+use crate::storage::PluginDirs;
+use crate::ffi::{VOption, VString, VVec};
+use crate::hook::{Hook, HookFn};
+use crate::config::{self, Config, ConfigSchema, RawSetting, SchemaFile};
+use std::io::{ErrorKind, Seek};
 
 /* Personally I believe it looks much better like this */
 type LaterInitialized<T> = Option<T>;
@@ -52,7 +65,13 @@ macro_rules! init_now {
 /// This is purely for deserialization.
 #[derive(Deserialize)]
 struct Data {
-        metadata: Metadata
+        metadata: Metadata,
+        #[serde(default)]
+        hooks   : BTreeMap<String, String>,
+        #[serde(default)]
+        config  : BTreeMap<String, RawSetting>,
+        #[serde(default)]
+        loader  : LoadFlagOverrides
 }
 
 #[derive(Deserialize)]
@@ -60,7 +79,10 @@ struct Metadata {
         description: Option<String>,
         version    : String,
         name       : String,
-        objfile    : String
+        objfile    : String,
+        priority   : Option<i32>,
+        #[serde(default)]
+        libraries  : Vec<String>
 }
 /// A struct that represents metadata about
 /// a single plugin, like its version and name.
@@ -68,21 +90,66 @@ struct Metadata {
 /// This struct should only be returned by `PluginMetadata::load()`.
 /// Otherwise, undefined values will be returned, resulting in undefined
 /// behavior.
+///
+/// Its layout is not stable: Use [to_ffi](PluginMetadata::to_ffi) to pass it across the FFI boundary.
 #[derive(Debug)]
-#[repr(C)]
 pub struct PluginMetadata {
         pub description: Option<String>,
         pub version    : String,
         pub name       : String,
         pub filename   : String,
-        pub objfile    : String
+        pub objfile    : String,
+        /// The order in which hooks of this plugin are called relative
+        /// to other plugins (Higher first). Defaults to `0`.
+        pub priority   : i32,
+        /// Hooks declared in the `[hooks]` table, mapped to their
+        /// signature descriptor (eg. `fn(*const Doc) -> i32`).
+        pub hooks      : BTreeMap<String, String>,
+        /// The settings declared in the `[config]` table, or in `config.schema.toml`.
+        pub config     : ConfigSchema,
+        /// Shared libraries bundled in the archive (Files or directories), loaded
+        /// before the object file.
+        pub libraries  : Vec<String>,
+        /// The load flags set in the `[loader]` table.
+        pub loader     : LoadFlagOverrides
+}
+
+/// ## VPluginMetadata
+/// The metadata of a plugin, in a layout that can be passed across the FFI boundary.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct VPluginMetadata {
+        pub name       : VString,
+        pub version    : VString,
+        pub description: VOption<VString>,
+        pub objfile    : VString,
+        pub priority   : i32,
+        pub hooks      : VVec<VHookDeclaration>,
+        pub libraries  : VVec<VString>,
+}
+
+/// A hook declared in the `[hooks]` table, and its signature descriptor.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct VHookDeclaration {
+        pub name     : VString,
+        pub signature: VString,
+}
+
+static NO_HOOKS: BTreeMap<String, String> = BTreeMap::new();
+static NO_CONFIG: ConfigSchema = ConfigSchema::EMPTY;
+static NEXT_LOAD: AtomicU64 = AtomicU64::new(0);
+
+/// Signatures are compared ignoring whitespace, so `fn(i32)->i32`
+/// and `fn(i32) -> i32` are considered equal.
+fn normalize_signature(signature: &str) -> String {
+        signature.split_whitespace().collect()
 }
 
 /// The plugin type. This is used to identify a single plugin
 /// from VPlugin. New plugins should be loaded with `Plugin::load()`,
 /// and not be reused explicitly.
 #[derive(Debug)]
-#[repr(C)]
 pub struct Plugin {
         // Metadata about the plugin, will be None if the plugin
         // has not loaded its metadata yet.
@@ -90,80 +157,163 @@ pub struct Plugin {
         pub(crate) filename: String,
         pub(crate) is_valid: bool,
         pub(crate) started : bool,
-        pub(crate) raw     : LaterInitialized<Library>,
+        // Shared with the hooks handed out, so it stays mapped until the last of them is dropped.
+        pub(crate) raw     : LaterInitialized<Arc<LoadedObject>>,
+        // Bundled libraries, loaded before (And unloaded after) the object file.
+        pub(crate) libs    : Vec<Library>,
+        pub(crate) objpath : LaterInitialized<PathBuf>,
         pub(crate) archive : ZipArchive<File>,
+        // What the plugin exports as VPLUGIN_ABI_VERSION and VPLUGIN_BUILD_INFO.
+        pub(crate) abi     : Option<u32>,
+        pub(crate) build   : Option<String>,
+        // Assigned by the PluginManager loading the plugin.
+        pub(crate) dirs    : Option<PluginDirs>,
+        // The directory the plugin was extracted to (Along with other plugins).
+        pub(crate) workdir : PathBuf,
+        // The cache entry the plugin was extracted to, instead of the work directory.
+        pub(crate) cached  : Option<PathBuf>,
+        // Whether another loaded plugin uses the same cache entry, so object files are copied.
+        pub(crate) shared  : bool,
+        // Tells this load apart from others of the same plugin, which get their own directory.
+        pub(crate) id      : u64,
+        // The directory of this load, removed when the plugin is dropped (Even once terminated).
+        pub(crate) loaddir : Option<PathBuf>,
+        // How to load object files: The PluginManager's flags, replaced by the
+        // effective ones once the metadata is loaded.
+        pub(crate) flags   : LoadFlags,
+        // The entry point the object file must export, if known.
+        pub(crate) entry   : Option<String>,
+        // The PluginManager's shims, for plugins built against an older ABI version.
+        pub(crate) shims   : BTreeMap<u32, AbiShim>,
+        // Whether to load object files from memory, and the in-memory files they were
+        // loaded from (Until the object file is, which keeps them open from then on).
+        pub(crate) from_mem: bool,
+        pub(crate) memfds  : Vec<File>,
+        // The settings handed to the plugin when it was started.
+        pub(crate) config  : Option<Config>,
+        // Host callbacks handed to the plugin, only
+        // available while the plugin is started.
+        pub(crate) host    : LaterInitialized<Box<PluginContext>>,
+        // Replaced once the plugin is terminated, so its instances know they're gone.
+        pub(crate) alive   : Arc<()>,
+
+}
+
+/// How a [PluginManager](crate::PluginManager) loads its plugins.
+#[derive(Debug, Default)]
+pub(crate) struct LoadOptions {
+        /// Shims for plugins built against an older ABI version.
+        pub(crate) shims   : BTreeMap<u32, AbiShim>,
+        /// Where archives are kept extracted across runs, if enabled.
+        pub(crate) cache   : Option<ExtractionCache>,
+        /// Where archives are extracted to, `<temp>/vplugin` by default.
+        pub(crate) work_dir: Option<PathBuf>,
+        /// Load object files from memory instead of a directory (Linux only).
+        pub(crate) memfd   : bool,
+        /// Flags plugins are loaded with, unless they override them.
+        pub(crate) flags   : LoadFlags,
+        /// The entry point plugins must export, if the host set one before loading them.
+        pub(crate) entry   : Option<String>,
+}
 
+impl LoadOptions {
+        pub(crate) fn work_dir(&self) -> PathBuf {
+                self.work_dir.clone().unwrap_or_else(loader::default_work_dir)
+        }
 }
 
 impl PluginMetadata {
+        /// Copies the metadata into a [VPluginMetadata].
+        pub fn to_ffi(&self) -> VPluginMetadata {
+                VPluginMetadata {
+                        name       : self.name.as_str().into(),
+                        version    : self.version.as_str().into(),
+                        description: self.description.as_deref().map(VString::from).into(),
+                        objfile    : self.objfile.as_str().into(),
+                        priority   : self.priority,
+                        hooks      : self.hooks
+                                .iter()
+                                .map(|(name, signature)| VHookDeclaration {
+                                        name     : name.as_str().into(),
+                                        signature: signature.as_str().into(),
+                                })
+                                .collect(),
+                        libraries  : self.libraries.iter().map(|l| VString::from(l.as_str())).collect(),
+                }
+        }
+
         /// Reads a metadata.toml file or returns an error. This is useful
         /// for libraries that wish to make use of VPlugin's internals.
-        pub fn read_from_str<T: for<'a> Deserialize<'a>>(string: &str) -> Result<T, VPluginError> {
+        pub fn read_from_str<T: for<'a> serde::Deserialize<'a>>(string: &str) -> Result<T, VPluginError> {
                 let data: T = match toml::from_str(string) {
                         Ok (t) => t,
                         Err(e) => {
-                                log::error!("Couldn't read metadata file: {}", e.to_string());
-                                return Err(VPluginError::ParametersError)
+                                log::error!("Couldn't read metadata file: {}", e);
+                                return Err(VPluginError::InvalidMetadata { path: "metadata.toml".into(), source: e })
                         }
                 };
 
                 Ok(data)
 
         }
-        
-        fn load(plugin: &Plugin) -> Result<Self, VPluginError> {
+
+        /// Reads the name and version of the plugin archived at `path`,
+        /// without extracting or loading it.
+        pub(crate) fn peek(path: &Path) -> Result<(String, String), VPluginError> {
+                let file = File::open(path).map_err(|e| VPluginError::io(path, e))?;
+                let invalid_archive = |source| VPluginError::InvalidArchive { path: path.to_owned(), source };
+                let mut archive = ZipArchive::new(file).map_err(invalid_archive)?;
+                let metadata = archive.by_name("metadata.toml").map_err(invalid_archive)?;
+                let contents = std::io::read_to_string(metadata).map_err(|e| VPluginError::io(path, e))?;
+                let data: Data = toml::from_str(&contents)
+                        .map_err(|e| VPluginError::InvalidMetadata { path: path.to_owned(), source: e })?;
+
+                Ok((data.metadata.name, data.metadata.version))
+        }
+
+        fn load(plugin: &mut Plugin) -> Result<Self, VPluginError> {
                 let mut plugin_metadata = Self {
                      description: None,
                      version    : String::new(),
                      name       : String::new(),
                      filename   : plugin.filename.clone(),
                      objfile    : String::new(),
+                     priority   : 0,
+                     hooks      : BTreeMap::new(),
+                     config     : ConfigSchema::default(),
+                     libraries  : Vec::new(),
+                     loader     : LoadFlagOverrides::default(),
                 };
 
-                let f = match File::open("metadata.toml") {
+                let path = plugin.extracted_dir().join("metadata.toml");
+                let f = match File::open(&path) {
                         Ok(val) => val,
-                        Err(e) => {
-                                match e.kind() {
-                                        PermissionDenied => return Err(VPluginError::PermissionDenied),
-                                        Unsupported      => return Err(VPluginError::InternalError { err: "Unsupported file".into() }),
-                                        NotFound         => return Err(VPluginError::NoSuchFile),
-                                        Interrupted      => return Err(VPluginError::InvalidPlugin),
-                                        UnexpectedEof    => return Err(VPluginError::InvalidPlugin),
-                                        OutOfMemory      => return Err(VPluginError::InternalError { err: "Host is out of memory".into() }),
-                                        Other            => return Err(VPluginError::InternalError { err: "Unknown error.".into() }),
-                                        _ => panic!()
-                                }
-                        }
+                        Err(e) => return Err(VPluginError::io(path, e)),
                 };
 
                 let contents = match std::io::read_to_string(f) {
                         Ok(contents) => contents,
                         Err(e)        => {
-                                log::error!("Error reading metadata string: {}.", e.to_string());
-                                return Err(VPluginError::ParametersError);
+                                log::error!("Error reading metadata string: {}.", e);
+                                return Err(VPluginError::io(path, e));
                         }
                 };
                 let buffer = String::from(contents.as_str());
 
                 let data_raw: Data = match toml::from_str(&buffer) {
                         Ok(ok) => ok,
-                        Err(_) => {
-                                return Err(VPluginError::ParametersError)
+                        Err(e) => {
+                                return Err(VPluginError::InvalidMetadata { path, source: e })
                         }
                 };
 
                 if data_raw.metadata.name.is_empty()
                 || data_raw.metadata.name.contains(' ') {
-                        /*
-                         * Here we panic as without a name, it's impossible to identify the plugin
-                         * for future errors.
-                         */
-                        panic!(
-                                "
-                                Attempted to use a plugin that has an empty name in its metadata or contains an
-                                invalid character in the field.
-                                "
-                        )
+                        /* Without a name, it's impossible to identify the plugin for future errors. */
+                        return Err(VPluginError::invalid_plugin(
+                                plugin.filename.as_str(),
+                                "the name in metadata.toml is empty or contains an invalid character"
+                        ));
                 }
 
                 if data_raw.metadata.version.is_empty()
@@ -176,90 +326,169 @@ impl PluginMetadata {
                         );
                 }
 
-                plugin_metadata.filename = "metadata.toml".to_owned();
+                plugin_metadata.filename    = "metadata.toml".to_owned();
+                plugin_metadata.description = data_raw.metadata.description;
                 plugin_metadata.version  = data_raw.metadata.version;
                 plugin_metadata.name     = data_raw.metadata.name;
                 plugin_metadata.objfile  = data_raw.metadata.objfile;
+                plugin_metadata.priority = data_raw.metadata.priority.unwrap_or(0);
+                plugin_metadata.hooks    = data_raw.hooks;
+                plugin_metadata.libraries = data_raw.metadata.libraries;
+                plugin_metadata.loader    = data_raw.loader;
+
+                /* The schema is read from the archive, so files left by other plugins never get in the way. */
+                let mut settings = data_raw.config;
+                if let Ok(file) = plugin.archive.by_name(config::SCHEMA_FILE) {
+                        if !settings.is_empty() {
+                                return Err(VPluginError::invalid_plugin(
+                                        plugin_metadata.name,
+                                        "settings are declared in both metadata.toml and config.schema.toml"
+                                ));
+                        }
+                        let contents = std::io::read_to_string(file).map_err(|e| VPluginError::io(config::SCHEMA_FILE, e))?;
+                        let schema: SchemaFile = toml::from_str(&contents)
+                                .map_err(|e| VPluginError::InvalidMetadata { path: config::SCHEMA_FILE.into(), source: e })?;
+                        settings = schema.config;
+                }
+                plugin_metadata.config = ConfigSchema::parse(&plugin_metadata.name, settings)?;
 
                 Ok(plugin_metadata)
         }
 }
 
 impl Plugin {
-        fn load_archive<S: Copy + Into<String> + AsRef<OsStr>>(filename: S) -> Result<Self, VPluginError> {
+        fn load_archive<S: Copy + Into<String> + AsRef<OsStr>>(
+                filename: S,
+                options : &LoadOptions
+        ) -> Result<Self, VPluginError> {
                 log::trace!("Loading plugin: {}.", &filename.into());
                 let tmp = filename.into();
                 let fname = std::path::Path::new(&tmp);
-                let file = match fs::File::open(fname) {
+                let mut file = match fs::File::open(fname) {
                         Ok(val) => val,
                         Err(e) => {
                                 log::error!(
                                         "Couldn't load {}: {} (error {})",
                                         filename.into(),
-                                        e.to_string(),
+                                        e,
                                         e.raw_os_error().unwrap_or(0)
                                 );
-                                match e.kind() {
-                                        PermissionDenied => return Err(VPluginError::PermissionDenied),
-                                        Unsupported      => return Err(VPluginError::InternalError { err: "Unsupported file".into() }),
-                                        NotFound         => return Err(VPluginError::NoSuchFile),
-                                        Interrupted      => return Err(VPluginError::InvalidPlugin),
-                                        UnexpectedEof    => return Err(VPluginError::InvalidPlugin),
-                                        OutOfMemory      => return Err(VPluginError::InternalError { err: "Host is out of memory".into() }),
-                                        Other            => return Err(VPluginError::InternalError { err: "Unknown error.".into() }),
-                                        _ => panic!()
-                                }
+                                return Err(VPluginError::io(fname, e));
                         }
                 };
+
+                if let Some(cache) = &options.cache {
+                        let invalid_archive = |source| VPluginError::InvalidArchive { path: fname.to_owned(), source };
+                        let mut archive = zip::ZipArchive::new(file.try_clone().map_err(|e| VPluginError::io(fname, e))?)
+                                .map_err(invalid_archive)?;
+                        match cache.extract(fname, &mut file, &mut archive) {
+                                Ok (entry) => return Ok(Self::new(filename.into(), archive, Some(entry), options)),
+                                Err(e)     => log::warn!(
+                                        "Couldn't use the extraction cache for {}, extracting it normally: {}",
+                                        filename.into(),
+                                        e.report()
+                                ),
+                        }
+                        file.rewind().map_err(|e| VPluginError::io(fname, e))?;
+                }
                 
-                match std::fs::create_dir(env::temp_dir().join("vplugin")) {
-                        Err(e) => match e.kind() {
-                                ErrorKind::AlreadyExists => (),
-                                _ => log::info!("Couldn't create VPlugin directory: {}", e.to_string()),
+                if let Err(e) = std::fs::create_dir_all(options.work_dir()) {
+                        if e.kind() != ErrorKind::AlreadyExists {
+                                log::info!("Couldn't create VPlugin directory: {}", e);
                         }
-                        Ok(_) => env::set_current_dir(env::temp_dir().join("vplugin")).unwrap()
                 }
+                /* Extract inside VPlugin's directory, without changing the host's working directory. */
+                let workdir = options.work_dir();
 
                 /* Uncompressing the archive. */
                 log::trace!("Uncompressing plugin {}", filename.into());
-                let mut archive = zip::ZipArchive::new(file).unwrap();
+                let invalid_archive = |source| VPluginError::InvalidArchive { path: fname.to_owned(), source };
+                let mut archive = zip::ZipArchive::new(file).map_err(invalid_archive)?;
                 for i in 0..archive.len() {
-                        let mut file = archive.by_index(i).unwrap();
+                        let mut file = archive.by_index(i).map_err(invalid_archive)?;
                         let outpath = match file.enclosed_name() {
-                            Some(path) => path.to_owned(),
+                            Some(path) => workdir.join(path),
                             None => continue,
                         };
 
                         if (*file.name()).ends_with('/') {
-                                fs::create_dir_all(&outpath).unwrap();
+                                fs::create_dir_all(&outpath).map_err(|e| VPluginError::io(&outpath, e))?;
                         } else {
                                 if let Some(p) = outpath.parent() {
                                         if !p.exists() {
-                                            fs::create_dir_all(p).unwrap();
+                                            fs::create_dir_all(p).map_err(|e| VPluginError::io(p, e))?;
                                         }
                                 }
                                 
-                                let mut outfile = fs::File::create(&outpath).unwrap();
-                                std::io::copy(&mut file, &mut outfile).unwrap();
+                                let mut outfile = fs::File::create(&outpath).map_err(|e| VPluginError::io(&outpath, e))?;
+                                std::io::copy(&mut file, &mut outfile).map_err(|e| VPluginError::io(&outpath, e))?;
                         }
                 }
 
-                let plugin = Self {
+                Ok(Self::new(filename.into(), archive, None, options))
+        }
+
+        fn new(filename: String, archive: ZipArchive<File>, cached: Option<PathBuf>, options: &LoadOptions) -> Self {
+                Self {
                         metadata: initialize_later!(),
                         raw     : initialize_later!(),
-                        filename: filename.into(),
+                        libs    : Vec::new(),
+                        objpath : initialize_later!(),
+                        host    : initialize_later!(),
+                        alive   : Arc::new(()),
+                        abi     : None,
+                        build   : None,
+                        dirs    : None,
+                        workdir : options.work_dir(),
+                        shared  : cached.as_deref().is_some_and(|entry| !cache::acquire(entry)),
+                        id      : NEXT_LOAD.fetch_add(1, Ordering::Relaxed),
+                        loaddir : None,
+                        cached,
+                        flags   : options.flags,
+                        entry   : options.entry.clone(),
+                        shims   : options.shims.clone(),
+                        from_mem: options.memfd,
+                        memfds  : Vec::new(),
+                        config  : None,
+                        filename,
                         is_valid: false,
                         started : false,
                         archive,
-                };
+                }
+        }
 
-                Ok(plugin)
+        /// Creates the directory the plugin's object files are copied to, and remembers it so it's removed later.
+        fn create_load_dir(&mut self, plugin: &str) -> Result<PathBuf, VPluginError> {
+                let dir = self.workdir.join(format!("{}-{}", plugin, self.id));
+                fs::create_dir_all(&dir).map_err(|e| VPluginError::io(&dir, e))?;
+                self.loaddir = Some(dir.clone());
+                Ok(dir)
+        }
+
+        /// The directory the plugin's archive was extracted to.
+        fn extracted_dir(&self) -> PathBuf {
+                match &self.cached {
+                        Some(entry) => entry.clone(),
+                        None        => self.workdir.clone(),
+                }
         }
         /// Loads a plugin into memory and returns it.
         /// After 0.2.0, metadata is also loaded in this call so avoid calling it
         /// again (For your convenience, it has been marked as deprecated).
+        ///
+        /// Plugins built for another ABI version than [ABI_VERSION](crate::ABI_VERSION) (Or not exporting
+        /// one) are refused before their object file is loaded, see
+        /// [register_abi_shim](crate::PluginManager::register_abi_shim) to accept older ones.
         pub fn load<S: Copy + Into<String> + AsRef<OsStr>>(filename: S) -> Result<Plugin, VPluginError> {
-                let mut plugin = match Self::load_archive(filename) {
+                Self::load_with(filename, &LoadOptions::default())
+        }
+
+        /// Loads a plugin the way a PluginManager configured it to.
+        pub(crate) fn load_with<S: Copy + Into<String> + AsRef<OsStr>>(
+                filename: S,
+                options : &LoadOptions
+        ) -> Result<Plugin, VPluginError> {
+                let mut plugin = match Self::load_archive(filename, options) {
                         Err(e) => {
                                 log::error!("Couldn't load archive, stopping here.");
                                 return Err(e);
@@ -274,23 +503,22 @@ impl Plugin {
                                 log::error!("Couldn't load metadata, stopping here.");
                                 return Err(e);
                         }
+                        Ok(_) if plugin.cached.is_some() => (),
                         Ok(_) => {
-                                fs::create_dir_all(
-                                        env::temp_dir()
-                                        .join("vplugin")
-                                        .join(&plugin.metadata.as_ref().unwrap().name)
-                                ).expect("Cannot create plugin directory!");
+                                let name = plugin.display_name().to_owned();
+                                plugin.create_load_dir(&name)?;
                         }
                 }
+
                 Ok(plugin)
         }
 
         /// Returns a VHook (Generic function pointer) that can be used to exchange data between
-        /// your application and the plugin.
+        /// your application and the plugin. It must not be called once the plugin is gone.
         pub(super) fn load_vhook(&self, fn_name: &str) -> Result<VHook, VPluginError> {
                 if !self.started || !self.is_valid || self.raw.is_none() {
                         log::error!("Attempted to load plugin function that isn't started or isn't valid");
-                        return Err(VPluginError::InvalidPlugin);
+                        return Err(VPluginError::invalid_plugin(self.display_name(), "not started"));
                 }
                 let hook: Symbol<VHook>;
                 unsafe {
@@ -300,7 +528,7 @@ impl Plugin {
                                 .get(format!("{}\0", fn_name).as_bytes())
                         {
                             Ok (v) => v,
-                            Err(_) => return Err(VPluginError::MissingSymbol),
+                            Err(_) => return Err(VPluginError::missing_symbol(self.display_name(), fn_name)),
                         };
                 }
                 Ok(*hook)
@@ -310,16 +538,70 @@ impl Plugin {
                 Self::load_vhook(self, fn_name)
         }
 
+        /// Returns the function `name` exported by the started plugin, as an `F`
+        /// (eg. `unsafe extern "C" fn(*const u8, usize) -> i32`).
+        ///
+        /// VPlugin can't check that the plugin exports it with this type: Compare
+        /// the signature the plugin declares first (See [check_hook_signature](Plugin::check_hook_signature)).
+        ///
+        /// The hook keeps the object file loaded, even once the plugin is terminated or dropped.
+        pub fn hook<F: HookFn>(&self, name: &str) -> Result<Hook<F>, VPluginError> {
+                Ok(Hook {
+                        plugin  : self.display_name().to_owned(),
+                        name    : name.to_owned(),
+                        func    : self.load_symbol::<F>(name)?,
+                        _library: self.library()?,
+                        alive   : Arc::downgrade(&self.alive),
+                })
+        }
+
+        /// Returns the object file, to keep it loaded while something of the plugin is in use.
+        pub(crate) fn library(&self) -> Result<Arc<LoadedObject>, VPluginError> {
+                match &self.raw {
+                        Some(raw) => Ok(Arc::clone(raw)),
+                        None      => Err(VPluginError::invalid_plugin(self.display_name(), "no object file is loaded")),
+                }
+        }
+
+        /// Loads a symbol of any (Copy) type, usually a function pointer.
+        pub(crate) fn load_symbol<F: Copy>(&self, name: &str) -> Result<F, VPluginError> {
+                if !self.started || !self.is_valid || self.raw.is_none() {
+                        log::error!("Cannot load symbol '{}' from non-started or invalid plugin.", name);
+                        return Err(VPluginError::invalid_plugin(self.display_name(), "not started"));
+                }
+                unsafe {
+                        match self.raw
+                                .as_ref()
+                                .unwrap_unchecked()
+                                .get::<F>(format!("{}\0", name).as_bytes())
+                        {
+                            Ok (v) => Ok(*v),
+                            Err(_) => Err(VPluginError::missing_symbol(self.display_name(), name)),
+                        }
+                }
+        }
+
+        /// Loads an optional symbol, even before the plugin is started.
+        pub(crate) fn load_symbol_unchecked<F: Copy>(&self, name: &str) -> Option<F> {
+                unsafe {
+                        self.raw
+                                .as_ref()?
+                                .get::<F>(format!("{}\0", name).as_bytes())
+                                .ok()
+                                .map(|v| *v)
+                }
+        }
+
         /// Implemented as public in [PluginManager](crate::plugin_manager::PluginManager).
         pub(crate) fn get_custom_hook<P, T>(
                 &self,
                 fn_name: &str
-        ) -> Result<unsafe extern fn(P) -> T, VPluginError> {
+        ) -> Result<unsafe extern "C" fn(P) -> T, VPluginError> {
                 if !self.started || !self.is_valid || self.raw.is_none() {
                         log::error!("Cannot load custom hook from non-started or invalid plugin.");
-                        return Err(VPluginError::InvalidPlugin);
+                        return Err(VPluginError::invalid_plugin(self.display_name(), "not started"));
                 }
-                let hook: Symbol<unsafe extern fn(P) -> T>;
+                let hook: Symbol<unsafe extern "C" fn(P) -> T>;
                 unsafe {
                         hook = match self.raw
                                 .as_ref()
@@ -327,7 +609,7 @@ impl Plugin {
                                 .get(format!("{}\0", fn_name).as_bytes())
                         {
                             Ok (v) => v,
-                            Err(_) => return Err(VPluginError::MissingSymbol),
+                            Err(_) => return Err(VPluginError::missing_symbol(self.display_name(), fn_name)),
                         };
                 }
                 Ok(*hook)
@@ -341,28 +623,196 @@ impl Plugin {
         pub fn load_metadata(&mut self) -> Result<(), VPluginError> {
                 match PluginMetadata::load(self) {
                         Ok (v) => {
-                                let plugin_dir_name = env::temp_dir()
-                                        .join("vplugin")
-                                        .join(&v.name);
+                                let mut libraries = Vec::new();
+                                for library in self.bundled_libraries(&v)? {
+                                        let path = self.stage(&v.name, &library)?;
+                                        libraries.push((library, path));
+                                }
+                                let objpath = self.stage(&v.name, &v.objfile)?;
 
-                                fs::create_dir_all(&plugin_dir_name).unwrap();
-                                fs::copy(&v.objfile, plugin_dir_name.join(&v.objfile)).unwrap();
+                                /* Nothing is loaded before the object files are known to be loadable, and
+                                 * to export what the plugin claims to implement. */
+                                let required: Vec<&str> = self.entry
+                                        .iter()
+                                        .chain(v.hooks.keys())
+                                        .map(String::as_str)
+                                        .collect();
+                                let (infos, info) = loader::check_objects(&v.name, &libraries, (&v.objfile, &objpath), &required)?;
+                                (self.abi, self.build) = (info.abi, info.build);
+                                let shim = abi::check(&v.name, self.abi, self.build.as_deref(), &self.shims)?;
+                                self.flags  = loader::supported(&v.name, v.loader.apply(self.flags));
+                                let mut namespace = None;
 
-                                self.raw       = unsafe {
-                                        init_now!(Library::new(plugin_dir_name.join(&v.objfile)).unwrap())
+                                /* Loaded libraries satisfy the DT_NEEDED entries naming their soname. */
+                                let dynamic: Vec<_> = infos.into_iter().map(|i| i.dynamic).collect();
+                                for (library, path) in loader::dependency_order(&v.name, libraries, &dynamic)? {
+                                        if !self.from_mem {
+                                                loader::check_executable(path.parent().unwrap_or(Path::new(".")))?;
+                                        }
+                                        match loader::open(&path, self.flags, &mut namespace) {
+                                                Ok (lib) => self.libs.push(lib),
+                                                Err(OpenError::Library(e)) => {
+                                                        log::error!("Couldn't load library '{}' bundled with plugin '{}': {}", library, v.name, e);
+                                                        return Err(VPluginError::LibraryLoadFailed { plugin: v.name, library, source: e });
+                                                }
+                                                Err(OpenError::Namespace(reason)) => {
+                                                        log::error!("Couldn't load library '{}' bundled with plugin '{}': {}", library, v.name, reason);
+                                                        return Err(VPluginError::NamespaceFailed { plugin: v.name, object: library, reason });
+                                                }
+                                        }
+                                }
+
+                                if !self.from_mem {
+                                        loader::check_executable(objpath.parent().unwrap_or(Path::new(".")))?;
+                                }
+                                self.raw       = match loader::open(&objpath, self.flags, &mut namespace) {
+                                        Ok (library) => init_now!(Arc::new(LoadedObject::new(library, mem::take(&mut self.memfds)))),
+                                        Err(OpenError::Library(e)) => {
+                                                log::error!("Couldn't load object file of plugin '{}': {}", v.name, e);
+                                                return Err(VPluginError::LoadFailed { plugin: v.name, source: e });
+                                        }
+                                        Err(OpenError::Namespace(reason)) => {
+                                                log::error!("Couldn't load object file of plugin '{}': {}", v.name, reason);
+                                                return Err(VPluginError::NamespaceFailed { plugin: v.name, object: v.objfile, reason });
+                                        }
                                 };
-                                self.is_valid = true;
+                                self.objpath  = init_now!(objpath);
                                 self.metadata = init_now!(v);
 
-                                Ok(())
+                                self.is_valid = true;
+                                match shim {
+                                        Some(shim) => shim(self),
+                                        None       => Ok(()),
+                                }
                         },
                         Err(e) => {
-                                log::error!("Couldn't load metadata ({}): {}", self.filename, e.to_string());
+                                log::error!("Couldn't load metadata ({}): {}", self.filename, e);
                                 Err(e)
                         }
                 }
         }
 
+        /// Returns the files of the archive matching the `libraries` listed in the
+        /// metadata: Either a file, or every file inside a directory.
+        fn bundled_libraries(&self, metadata: &PluginMetadata) -> Result<Vec<String>, VPluginError> {
+                let mut libraries = Vec::new();
+                for entry in &metadata.libraries {
+                        let prefix = format!("{}/", entry.trim_end_matches('/'));
+                        let mut files: Vec<String> = self.archive
+                                .file_names()
+                                .filter(|f| f == entry || (f.starts_with(&prefix) && !f.ends_with('/')))
+                                .map(String::from)
+                                .collect();
+                        if files.is_empty() {
+                                log::error!("Plugin '{}' bundles library '{}', which is not in its archive.", metadata.name, entry);
+                                return Err(VPluginError::invalid_plugin(
+                                        &metadata.name,
+                                        format!("bundled library '{}' is not in the archive", entry)
+                                ));
+                        }
+                        files.sort();
+                        for file in files {
+                                if !libraries.contains(&file) {
+                                        libraries.push(file);
+                                }
+                        }
+                }
+                Ok(libraries)
+        }
+
+        /// Makes `file` of the archive available to the dynamic linker and returns its path:
+        /// In memory, in the cache entry, or copied to the plugin's own directory.
+        fn stage(&mut self, plugin: &str, file: &str) -> Result<PathBuf, VPluginError> {
+                if self.from_mem {
+                        /* Straight from the archive, so it doesn't matter where it was extracted. */
+                        let mut contents = self.archive
+                                .by_name(file)
+                                .map_err(|source| VPluginError::InvalidArchive { path: self.filename.clone().into(), source })?;
+                        let (memfd, path) = loader::memfd(file, &mut contents).map_err(|e| VPluginError::io(file, e))?;
+                        self.memfds.push(memfd);
+                        return Ok(path);
+                }
+
+                let extracted = self.extracted_dir().join(file);
+                if self.cached.is_some() && !self.shared {
+                        /* Cache entries are never deleted while in use. */
+                        return Ok(extracted);
+                }
+                /* A path of its own, or the dynamic linker would hand out the handle of another load. */
+                let path = self.create_load_dir(plugin)?.join(file);
+                if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir).map_err(|e| VPluginError::io(dir, e))?;
+                }
+                fs::copy(&extracted, &path).map_err(|e| VPluginError::io(&extracted, e))?;
+                Ok(path)
+        }
+
+        /// Returns the flags the plugin's object file was loaded with: The PluginManager's,
+        /// replaced by those set in the plugin's metadata (And without unsupported ones).
+        pub fn load_flags(&self) -> LoadFlags {
+                self.flags
+        }
+
+        /// Returns the ABI version the plugin was built for, if it exports one.
+        pub fn abi_version(&self) -> Option<u32> {
+                self.abi
+        }
+
+        /// Returns the VPlugin and compiler versions the plugin was built with, if it reports them.
+        pub fn build_info(&self) -> Option<&str> {
+                self.build.as_deref()
+        }
+
+        /// Returns the last error message the plugin reported, through the `set_error`
+        /// host callback or its exported `vplugin_last_error` function, if it is started.
+        /// Useful after a [hook](crate::PluginManager::hook) failed.
+        pub fn last_error(&self) -> Option<String> {
+                self.host.as_ref()?.errors.message()
+        }
+
+        /// Returns the directories of the plugin, if it was loaded through a [PluginManager](crate::PluginManager)
+        /// that knows where to keep them. They are created again if they were deleted since.
+        pub fn dirs(&self) -> Option<&PluginDirs> {
+                let dirs = self.dirs.as_ref()?;
+                dirs.create(self.display_name()).ok()?;
+                Some(dirs)
+        }
+
+        /// Returns the directory the plugin keeps persistent data in, if it
+        /// was loaded through a [PluginManager](crate::PluginManager).
+        /// It may have been deleted since (See [dirs](Plugin::dirs)).
+        pub fn data_dir(&self) -> Option<&Path> {
+                self.dirs.as_ref().map(|d| d.data.as_path())
+        }
+
+        /// Returns the directory the plugin keeps its settings in, if it
+        /// was loaded through a [PluginManager](crate::PluginManager).
+        /// It may have been deleted since (See [dirs](Plugin::dirs)).
+        pub fn config_dir(&self) -> Option<&Path> {
+                self.dirs.as_ref().map(|d| d.config.as_path())
+        }
+
+        /// Returns the directory the plugin keeps its caches in, if it
+        /// was loaded through a [PluginManager](crate::PluginManager).
+        /// It may have been deleted since (See [dirs](Plugin::dirs)).
+        pub fn cache_dir(&self) -> Option<&Path> {
+                self.dirs.as_ref().map(|d| d.cache.as_path())
+        }
+
+        /// Returns the settings the plugin declares, which may be empty.
+        pub fn config_schema(&self) -> &ConfigSchema {
+                match &self.metadata {
+                        Some(m) => &m.config,
+                        None    => &NO_CONFIG,
+                }
+        }
+
+        /// Returns the settings handed to the plugin when it was started,
+        /// if it declares any.
+        pub fn config(&self) -> Option<&Config> {
+                self.config.as_ref()
+        }
+
         /// Returns a reference to the plugin metadata, if loaded.
         /// Otherwise, `None` is returned.
         pub fn get_metadata(&self) -> &Option<PluginMetadata> {
@@ -381,12 +831,12 @@ impl Plugin {
         /// to force the plugin to be removed, risking safety and undefined behavior.
         pub fn terminate(&mut self) -> Result<(), VPluginError> {
                 if self.raw.is_none() {
-                        return Err(VPluginError::InvalidPlugin);
+                        return Err(VPluginError::invalid_plugin(self.display_name(), "no object file is loaded"));
                 }
 
                 if !self.started {
                         log::error!("Cannot terminate a plugin that wasn't started in the first place.");
-                        return Err(VPluginError::InvalidPlugin);
+                        return Err(VPluginError::invalid_plugin(self.display_name(), "not started"));
                 }
 
                 let destructor: Symbol<unsafe extern "C" fn() -> ()>;
@@ -403,23 +853,79 @@ impl Plugin {
                                         "Plugin {} does not have a destructor. Force terminate if needed.",
                                         self.get_metadata().as_ref().unwrap().name
                                 );
-                                return Err(VPluginError::InvalidPlugin)
+                                /* Event subscriptions are dropped anyways, so no dangling callbacks remain. */
+                                self.host = None;
+                                return Err(VPluginError::missing_symbol(self.display_name(), "vplugin_exit"))
                             },
                         };
 
                         destructor();
                 }
 
+                /* Dropping the context removes everything the plugin registered through it. */
+                self.host     = None;
                 self.started  = false;
+                self.alive    = Arc::new(());
                 if cfg!(feature = "non_reusable_plugins") {
                         self.is_valid = false;
+                        /* Hooks still in use keep the object file loaded until they are dropped. */
                         self.raw      = None;
+                        self.objpath  = None;
+                        self.libs     = Vec::new();
                         self.filename = String::new();
                         self.metadata = None;
                 }
                 Ok(())
         }
 
+        /// Returns the plugin's implementation of an interface declared with
+        /// [plugin_interface!](crate::plugin_interface), eg. `plugin.interface::<dyn Exporter>()`.
+        /// The plugin must be started, and built with the same declaration of the interface.
+        pub fn interface<I: Interface + ?Sized>(&self) -> Result<PluginInterface<'_, I>, VPluginError> {
+                /* The implementation may rely on the plugin's initialization, and is gone once it's terminated. */
+                if !self.started || !self.is_valid {
+                        log::error!("Cannot get interface '{}' from non-started or invalid plugin '{}'.", I::NAME, self.display_name());
+                        return Err(VPluginError::invalid_plugin(self.display_name(), "not started"));
+                }
+                let export: unsafe extern "C" fn() -> RawInterface = self.load_symbol(&format!("vplugin_interface_{}", I::NAME))?;
+                PluginInterface::new(self.display_name(), unsafe { export() })
+        }
+
+        /// Returns the data symbol `name` exported by the plugin (eg. a table of capabilities),
+        /// without calling any of its code. The plugin doesn't need to be started.
+        ///
+        /// If the plugin also exports `<name>_SIZE` (A `size_t`), it must match the size of `T`.
+        /// The symbol must be aligned for `T` as well.
+        ///
+        /// ## Safety
+        /// VPlugin can't check anything else about the symbol: It must be a valid `T` (Usually a
+        /// `#[repr(C)]` type matching the plugin's declaration), which the plugin doesn't modify
+        /// while the reference is in use.
+        pub unsafe fn get_static<T>(&self, name: &str) -> Result<&T, VPluginError> {
+                let Some(raw) = self.raw.as_ref() else {
+                        log::error!("Cannot load symbol '{}' from a plugin without an object file.", name);
+                        return Err(VPluginError::invalid_plugin(self.display_name(), "no object file is loaded"));
+                };
+                let ptr = match raw.get::<*const T>(format!("{}\0", name).as_bytes()) {
+                        Ok (v) if !v.is_null() => *v,
+                        _ => return Err(VPluginError::missing_symbol(self.display_name(), name)),
+                };
+
+                let mismatch = |reason: String| {
+                        log::error!("Symbol '{}' of plugin '{}' can't be read as {}: {}", name, self.display_name(), std::any::type_name::<T>(), reason);
+                        Err(VPluginError::StaticMismatch { plugin: self.display_name().to_owned(), symbol: name.to_owned(), reason })
+                };
+                if let Ok(size) = raw.get::<*const usize>(format!("{}_SIZE\0", name).as_bytes()) {
+                        if !size.is_null() && **size != std::mem::size_of::<T>() {
+                                return mismatch(format!("its size is {} bytes, but the host expects {}", **size, std::mem::size_of::<T>()));
+                        }
+                }
+                if !ptr.is_aligned() {
+                        return mismatch(format!("it isn't aligned to {} bytes", std::mem::align_of::<T>()));
+                }
+                Ok(&*ptr)
+        }
+
         /// Returns whether the function specified is available on the plugin.
         pub fn is_function_available(&self, name: &str) -> bool {
                 if self.raw.is_none() {
@@ -427,7 +933,84 @@ impl Plugin {
                         return false;
                 }
                 unsafe {
-                        self.raw.as_ref().unwrap().get::<unsafe extern "C" fn()>(name.as_bytes()).is_ok()
+                        self.raw
+                                .as_ref()
+                                .unwrap()
+                                .get::<unsafe extern "C" fn()>(format!("{}\0", name).as_bytes())
+                                .is_ok()
+                }
+        }
+
+        /// Returns every symbol (Functions and data) exported by the plugin's
+        /// object file, sorted by name.
+        ///
+        /// The symbols are read from the object file's dynamic symbol table
+        /// (Or export table, depending on the platform), so hosts can discover
+        /// hooks without probing for each name.
+        pub fn exported_symbols(&self) -> Result<Vec<ExportedSymbol>, VPluginError> {
+                match &self.objpath {
+                        Some(path) => symbols::read_exported_symbols(path),
+                        None => {
+                                log::error!("Cannot read symbols of a plugin without an object file.");
+                                Err(VPluginError::invalid_plugin(self.display_name(), "no object file is loaded"))
+                        }
+                }
+        }
+
+        /// Returns the names of all exported functions starting with `prefix`,
+        /// eg. every `myapp_on_*` hook a plugin implements.
+        pub fn functions_with_prefix(&self, prefix: &str) -> Result<Vec<String>, VPluginError> {
+                Ok(self
+                        .exported_symbols()?
+                        .into_iter()
+                        .filter(|s| s.is_function() && s.name.starts_with(prefix))
+                        .map(|s| s.name)
+                        .collect())
+        }
+
+        /// Returns the hooks declared by the plugin in its `metadata.toml`,
+        /// mapped to their signature descriptors. All of them are guaranteed
+        /// to be exported by the plugin's object file.
+        pub fn declared_hooks(&self) -> &BTreeMap<String, String> {
+                match &self.metadata {
+                        Some(m) => &m.hooks,
+                        None    => &NO_HOOKS,
+                }
+        }
+
+        /// Checks the signature the plugin declared for `hook` against the one
+        /// the host expects. Plugins that do not declare the hook at all pass
+        /// the check, as the manifest is optional.
+        ///
+        /// ## `Err` returned:
+        /// [`VPluginError::SignatureMismatch`] if the declared signature differs.
+        pub fn check_hook_signature(&self, hook: &str, expected: &str) -> Result<(), VPluginError> {
+                match self.declared_hooks().get(hook) {
+                        Some(declared) if normalize_signature(declared) != normalize_signature(expected) => {
+                                let e = VPluginError::SignatureMismatch {
+                                        plugin  : self.display_name().to_owned(),
+                                        hook    : hook.to_owned(),
+                                        declared: declared.to_owned(),
+                                        expected: expected.to_owned(),
+                                };
+                                log::error!("{}.", e);
+                                Err(e)
+                        }
+                        _ => Ok(())
+                }
+        }
+
+        /// Returns the names of all files packaged inside the plugin's archive.
+        pub fn files(&self) -> Vec<String> {
+                self.archive.file_names().map(String::from).collect()
+        }
+
+        /// The name used to refer to the plugin in errors: Its name, or
+        /// the archive it was loaded from if its metadata isn't loaded.
+        pub(crate) fn display_name(&self) -> &str {
+                match &self.metadata {
+                        Some(m) => &m.name,
+                        None    => &self.filename,
                 }
         }
 
@@ -442,20 +1025,21 @@ impl Plugin {
 
 impl Drop for Plugin {
         fn drop(&mut self) {
-                let plugin_dir_name = env::temp_dir()
-                        .join("vplugin")
-                        .join(&self.metadata.as_ref().unwrap().name);
+                if let Some(entry) = self.cached.as_deref().filter(|_| !self.shared) {
+                        cache::release(entry);
+                }
+                /* Plugins loaded from the cache (Or that failed early) never got a directory. */
+                let Some(plugin_dir_name) = self.loaddir.take() else {
+                        return;
+                };
 
-                match std::fs::remove_dir_all(&plugin_dir_name) {
-                        Err(e) => {
-                                log::warn!(
-                                        "Couldn't remove directory '{}' corresponding to plugin '{}': {}",
-                                        plugin_dir_name.display(),
-                                        self.metadata.as_ref().unwrap().name,
-                                        e.to_string()
-                                )
-                        },
-                        Ok(_) => ()
+                if let Err(e) = std::fs::remove_dir_all(&plugin_dir_name) {
+                        log::warn!(
+                                "Couldn't remove directory '{}' corresponding to plugin '{}': {}",
+                                plugin_dir_name.display(),
+                                self.display_name(),
+                                e
+                        )
                 }
         }
 }
@@ -15,11 +15,24 @@
 */
 
 extern crate libloading;
-use std::{ffi::{c_void, c_int}, env};
+use std::{ffi::{c_void, c_int, CString}, collections::BTreeMap, path::Path, sync::Arc};
 use libloading::Symbol;
+use serde::Serialize;
 use crate::error::VPluginError;
+use crate::abi::{AbiShim, ABI_VERSION};
+use crate::hook::{Hook, HookFn, HookRegistry};
+use crate::instance::{InstanceHook, PluginInstance, VInstanceHook};
+use crate::host::{ErrorSlot, HostState, PluginContext, VPluginHost};
+use crate::events;
+use crate::services::{self, ServiceInfo};
+use crate::storage::{PluginDirs, StorageRoots};
+use crate::config::{self, Config};
+use crate::state::{PluginState, StateFile};
 
-use super::plugin::Plugin;
+use crate::cache::ExtractionCache;
+use crate::loader::{self, LoadFlags};
+
+use super::plugin::{LoadOptions, Plugin, PluginMetadata};
 
 /// ## PluginManager
 /// The plugin manager is responsible for managing all loaded plugins,
@@ -30,12 +43,15 @@ use super::plugin::Plugin;
 /// The PluginManager is responsible for all tasks not involving plugins
 /// (Yes, even unloading plugins from memory) and should be a core part
 /// of your application.
-#[repr(C)]
 pub struct PluginManager {
         plugin : Vec<Plugin>,
         entry  : String,
-        running: bool,
-        errcode: u32
+        hooks  : BTreeMap<String, String>,
+        loader : LoadOptions,
+        storage: StorageRoots,
+        states : Option<StateFile>,
+        safe   : bool,
+        state  : Arc<HostState>
 }
 
 /// ## VHook
@@ -55,25 +71,343 @@ pub type VHook = unsafe extern "C" fn(*mut c_void) -> c_int;
 
 impl PluginManager {
         /// Creates a new, empty PluginManager and returns it.
+        ///
+        /// ## Panics
+        /// If the application runs as root, see [try_new](PluginManager::try_new).
         pub fn new() -> Self {
+                match Self::try_new() {
+                        Ok (manager) => manager,
+                        Err(e)       => panic!("{}", e),
+                }
+        }
+
+        /// Same as [new](PluginManager::new), but returns
+        /// [RunningAsSuperuser](VPluginError::RunningAsSuperuser) instead of
+        /// panicking if the application runs as root.
+        pub fn try_new() -> Result<Self, VPluginError> {
                 #[cfg(unix)] /* Windows applications often need admin rights. */
                 if is_superuser::is_superuser() {
-                        panic!("VPlugin may not be run")
+                        log::error!("VPlugin may not be run with superuser privileges.");
+                        return Err(VPluginError::RunningAsSuperuser);
                 }
-                Self {
+                Ok(Self {
                         plugin : Vec::new(),
                         entry  : String::from("vplugin_init"),
-                        running: false, /* No plugins running */
-                        errcode: 0
-                }
+                        hooks  : BTreeMap::new(),
+                        loader : LoadOptions::default(),
+                        storage: StorageRoots::default(),
+                        states : None,
+                        safe   : false,
+                        state  : Arc::new(HostState::default())
+                })
         }
 
         /// Loads a plugin through PluginManager. This function calls Plugin::load(filename)
-        /// under the hood, so you can also use it.
+        /// under the hood, so you can also use it (Although it won't know about ABI shims
+        /// or the extraction cache).
         /// 
         /// See also: [register_plugin](PluginManager::register_plugin).
+        ///
+        /// The plugin gets data, config and cache directories as well, created along with it
+        /// (See [set_storage_root](PluginManager::set_storage_root)). If they can't be, the
+        /// plugin is still loaded, without directories of its own.
         pub fn load_plugin(&mut self, filename: &str) -> Result<Plugin, VPluginError> {
-                Plugin::load(filename)
+                let mut plugin = Plugin::load_with(filename, &self.loader)?;
+                let name = plugin.display_name();
+                let dirs = self.storage.dirs_for(name).and_then(|dirs| dirs.create(name).map(|_| dirs));
+                plugin.dirs = match dirs {
+                        Ok (dirs) => Some(dirs),
+                        Err(e)    => {
+                                log::warn!("Plugin '{}' won't have directories of its own: {}", plugin.display_name(), e);
+                                None
+                        }
+                };
+                Ok(plugin)
+        }
+
+        /// Sets the name of the directory plugins' directories are kept in, inside the
+        /// user's data, config and cache directories. Defaults to `vplugin`, so you
+        /// probably want to set this to your application's name.
+        pub fn set_application_name(&mut self, name: &str) {
+                self.storage.application = name.to_owned();
+                self.states = None;
+        }
+
+        /// Keeps the plugins' directories in `<root>/data/<plugin>`, `<root>/config/<plugin>`
+        /// and `<root>/cache/<plugin>` instead of the user's directories
+        /// (eg. `~/.local/share/<app>/plugins/<plugin>` on Linux).
+        pub fn set_storage_root<P: AsRef<Path>>(&mut self, root: P) {
+                let root = root.as_ref().to_path_buf();
+                if root.starts_with(self.loader.work_dir()) {
+                        log::warn!("'{}' is deleted when VPlugin shuts down, plugins won't be able to keep files there.", root.display());
+                }
+                self.storage.root = Some(root);
+                self.states = None;
+        }
+
+        /// Sets the directory plugins are extracted to, `<temp>/vplugin` by default.
+        /// Like the default one, it is deleted when the PluginManager is dropped, so it
+        /// shouldn't be used for anything else.
+        ///
+        /// Object files are loaded from there as well (Unless they are loaded from memory or
+        /// from the extraction cache), so its filesystem must not be mounted `noexec`:
+        /// Loading fails with [`VPluginError::NotExecutable`] otherwise.
+        pub fn set_work_dir<P: AsRef<Path>>(&mut self, dir: P) {
+                self.loader.work_dir = Some(dir.as_ref().to_path_buf());
+        }
+
+        /// Loads object files straight from the plugins' archives into anonymous in-memory files
+        /// (`memfd_create`), instead of copying them to the work directory. Useful if the work
+        /// directory can't be on an executable filesystem, eg. when `/tmp` is mounted `noexec`.
+        ///
+        /// Only supported on Linux, elsewhere enabling it is ignored.
+        pub fn set_memfd_loading(&mut self, enabled: bool) {
+                if enabled && !loader::MEMFD_SUPPORTED {
+                        log::warn!("Object files can only be loaded from memory on Linux, ignoring.");
+                        return;
+                }
+                self.loader.memfd = enabled;
+        }
+
+        /// Sets the flags plugins' object files are loaded with, unless a plugin
+        /// overrides them in its `metadata.toml` (See [LoadFlags](crate::LoadFlags)).
+        pub fn set_load_flags(&mut self, flags: LoadFlags) {
+                self.loader.flags = flags;
+        }
+
+        /// Returns the settings of a plugin: The defaults of its schema, overridden by the
+        /// user's `config.toml` in its config directory. The result is what the plugin gets
+        /// when started, so hosts can use it to show the current settings.
+        ///
+        /// ## `Err` returned:
+        /// [`VPluginError::InvalidConfig`] if a user setting doesn't match the schema.
+        pub fn resolve_config(&self, plugin: &Plugin) -> Result<Config, VPluginError> {
+                plugin.config_schema().resolve(plugin.display_name(), plugin.config_dir())
+        }
+
+        /// Returns the directories of the plugin named `name`, whether it's loaded or not.
+        pub fn plugin_dirs(&self, name: &str) -> Result<PluginDirs, VPluginError> {
+                self.storage.dirs_for(name).inspect_err(|_| {
+                        log::error!("Couldn't determine the user's directories, set a storage root instead.");
+                })
+        }
+
+        /// Keeps track of the plugins being started in the state file, so those taking
+        /// the host down are disabled on the next run ("Safe mode"), and records why
+        /// plugins failed to load or start (See [PluginState]).
+        ///
+        /// The state file is shared by every host using the same application name, so
+        /// set it (Or a storage root) first: A plugin crashing one application would
+        /// otherwise be disabled for the others as well.
+        pub fn enable_safe_mode(&mut self) {
+                self.safe   = true;
+                self.states = None;
+        }
+
+        /// Returns the state file, reading it the first time.
+        fn states(&mut self) -> Result<&mut StateFile, VPluginError> {
+                if self.states.is_none() {
+                        self.states = Some(StateFile::open(self.storage.state_file()?, self.safe)?);
+                }
+                Ok(self.states.as_mut().unwrap())
+        }
+
+        /// Returns the state [discover](PluginManager::discover) and [begin_all](PluginManager::begin_all)
+        /// go by. Unless safe mode is enabled, the state file is left alone until the host uses it.
+        fn saved_state(&mut self, name: &str) -> Result<PluginState, VPluginError> {
+                if !self.safe && self.states.is_none() {
+                        return Ok(PluginState::default());
+                }
+                Ok(self.states()?.get(name))
+        }
+
+        /// Same as `states().update()` in safe mode, but only warns if the state file can't be
+        /// written, since failing to keep track of a plugin is no reason to stop it.
+        fn record(&mut self, name: &str, f: impl FnOnce(&mut PluginState)) {
+                if !self.safe {
+                        return;
+                }
+                if let Err(e) = self.states().and_then(|s| s.update(name, f)) {
+                        log::warn!("Couldn't save the state of plugin '{}': {}", name, e);
+                }
+        }
+
+        /// Returns what is remembered about the plugin named `name` across runs
+        /// (See [PluginState]), whether it's loaded or not.
+        ///
+        /// In safe mode, reading the state file for the first time also disables
+        /// the plugins that crashed the host while starting on the previous run.
+        pub fn plugin_state(&mut self, name: &str) -> Result<PluginState, VPluginError> {
+                Ok(self.states()?.get(name))
+        }
+
+        /// Returns the state of every plugin the state file knows about.
+        pub fn plugin_states(&mut self) -> Result<&BTreeMap<String, PluginState>, VPluginError> {
+                Ok(self.states()?.plugins())
+        }
+
+        /// Enables the plugin named `name`, so [discover](PluginManager::discover) and
+        /// [begin_all](PluginManager::begin_all) pick it up again. Plugins disabled after
+        /// crashing the host have to be enabled again this way.
+        pub fn enable(&mut self, name: &str) -> Result<(), VPluginError> {
+                self.states()?.update(name, |s| s.enabled = true)
+        }
+
+        /// Disables the plugin named `name`, starting with the next call to
+        /// [discover](PluginManager::discover) or [begin_all](PluginManager::begin_all).
+        /// Plugins already started keep running.
+        pub fn disable(&mut self, name: &str) -> Result<(), VPluginError> {
+                self.states()?.update(name, |s| s.enabled = false)
+        }
+
+        /// Makes [discover](PluginManager::discover) only load `version` of the plugin named
+        /// `name`, or any version if `None`.
+        pub fn pin_version(&mut self, name: &str, version: Option<&str>) -> Result<(), VPluginError> {
+                self.states()?.update(name, |s| s.pinned_version = version.map(String::from))
+        }
+
+        /// Loads and registers every plugin (`.vpl` archive) in `dir`, skipping plugins that
+        /// are disabled, pinned to another version or already registered. Returns the names of
+        /// the plugins registered. The state file is only read in safe mode, or once the host
+        /// used it (eg. through [plugin_states](PluginManager::plugin_states)).
+        ///
+        /// Plugins failing to load are skipped as well, and in safe mode their error is kept
+        /// as their [last failure](PluginState::last_failure).
+        ///
+        /// ## `Err` returned:
+        /// If `dir` can't be read, or if the state file can't be read.
+        pub fn discover<P: AsRef<Path>>(&mut self, dir: P) -> Result<Vec<String>, VPluginError> {
+                let dir = dir.as_ref();
+                let mut archives: Vec<_> = std::fs::read_dir(dir)
+                        .map_err(|e| VPluginError::io(dir, e))?
+                        .filter_map(|entry| entry.ok().map(|e| e.path()))
+                        .filter(|path| path.extension().is_some_and(|e| e == "vpl"))
+                        .collect();
+                archives.sort();
+
+                let mut registered = Vec::new();
+                for path in archives {
+                        let (name, version) = match PluginMetadata::peek(&path) {
+                                Ok (p) => p,
+                                Err(e) => {
+                                        log::warn!("Skipping {}: {}", path.display(), e.report());
+                                        continue;
+                                }
+                        };
+
+                        let state = self.saved_state(&name)?;
+                        if !state.enabled {
+                                log::info!("Plugin '{}' is disabled, not loading it.", name);
+                                continue;
+                        }
+                        if let Some(pinned) = state.pinned_version.filter(|p| *p != version) {
+                                log::info!("Plugin '{}' is pinned to version {}, not loading version {}.", name, pinned, version);
+                                continue;
+                        }
+                        if self.plugin.iter().any(|p| p.display_name() == name) {
+                                log::warn!("A plugin named '{}' is already registered, skipping {}.", name, path.display());
+                                continue;
+                        }
+
+                        match self.load_plugin(&path.to_string_lossy()) {
+                                Ok (plugin) => {
+                                        self.plugin.push(plugin);
+                                        registered.push(name);
+                                }
+                                Err(e)      => {
+                                        log::warn!("Couldn't load plugin '{}': {}", name, e.report());
+                                        self.record(&name, |s| s.last_failure = Some(e.report()));
+                                }
+                        }
+                }
+                Ok(registered)
+        }
+
+        /// Starts every registered plugin that isn't started or disabled, in the order they
+        /// were registered. Plugins failing to start are left registered, but not started.
+        ///
+        /// Returns the names of the plugins that failed, along with their error.
+        pub fn begin_all(&mut self) -> Vec<(String, VPluginError)> {
+                let mut plugins = std::mem::take(&mut self.plugin);
+                let mut failed  = Vec::new();
+                for plugin in plugins.iter_mut().filter(|p| !p.started) {
+                        let name = plugin.display_name().to_owned();
+                        match self.saved_state(&name) {
+                                Ok (state) if !state.enabled => {
+                                        log::info!("Plugin '{}' is disabled, not starting it.", name);
+                                        continue;
+                                }
+                                Ok (_) => (),
+                                Err(e) => log::warn!("Couldn't read the state of plugin '{}': {}", name, e),
+                        }
+                        if let Err(e) = self.begin_plugin(plugin) {
+                                failed.push((name, e));
+                        }
+                }
+                /* Plugins registered meanwhile (Eg. by a hook) come after. */
+                plugins.append(&mut self.plugin);
+                self.plugin = plugins;
+                failed
+        }
+
+        /// Returns every registered plugin.
+        pub fn plugins(&self) -> &[Plugin] {
+                &self.plugin
+        }
+
+        /// Keeps plugin archives extracted across runs, so unchanged plugins are loaded straight
+        /// from the cache instead of being extracted every time. Archives are identified by
+        /// the SHA-256 hash of their contents, so updated plugins are extracted again.
+        ///
+        /// The cache is kept in `<app>/extracted` inside the user's cache directory, or in
+        /// `<root>/extracted` if a [storage root](PluginManager::set_storage_root) is set
+        /// (Set it, and the [application name](PluginManager::set_application_name), first).
+        /// Files are verified against their hashes before being reused, and extracted again
+        /// if they were modified.
+        pub fn enable_extraction_cache(&mut self) -> Result<(), VPluginError> {
+                let dir = self.storage.extraction_cache()?;
+                self.enable_extraction_cache_in(dir);
+                Ok(())
+        }
+
+        /// Same as [enable_extraction_cache](PluginManager::enable_extraction_cache),
+        /// but keeps the cache in `dir`.
+        pub fn enable_extraction_cache_in<P: AsRef<Path>>(&mut self, dir: P) {
+                let limit = self.loader.cache.as_ref().map(|c| c.limit);
+                let mut cache = ExtractionCache::new(dir.as_ref().to_path_buf());
+                cache.limit = limit.unwrap_or(cache.limit);
+                self.loader.cache = Some(cache);
+        }
+
+        /// Sets how many bytes the extracted plugins may take (512 MiB by default). When
+        /// a plugin is extracted past that, the least recently used ones are removed.
+        pub fn set_extraction_cache_limit(&mut self, bytes: u64) {
+                match &mut self.loader.cache {
+                        Some(cache) => cache.limit = bytes,
+                        None        => log::warn!("The extraction cache is not enabled, ignoring its limit."),
+                }
+        }
+
+        /// Removes every plugin from the extraction cache. Plugins already loaded from it
+        /// keep working on Unix systems, but should be unloaded first elsewhere.
+        pub fn clear_extraction_cache(&mut self) -> Result<(), VPluginError> {
+                match &self.loader.cache {
+                        Some(cache) => cache.clear(),
+                        None        => Ok(()),
+                }
+        }
+
+        /// Accepts plugins built for the older ABI `version`, as long as `shim` does.
+        ///
+        /// Plugins exporting a `VPLUGIN_ABI_VERSION` other than [ABI_VERSION](crate::ABI_VERSION)
+        /// are otherwise refused with [IncompatibleAbi](VPluginError::IncompatibleAbi), before their
+        /// object file is loaded. Plugins not exporting it are loaded with a warning, a shim for
+        /// version 0 is called for them too.
+        pub fn register_abi_shim(&mut self, version: u32, shim: AbiShim) {
+                if version >= ABI_VERSION {
+                        log::warn!("ABI version {} is not older than the host's, its shim will never be used.", version);
+                }
+                self.loader.shims.insert(version, shim);
         }
 
         /// Registers a plugin into the PluginManager.
@@ -89,28 +423,132 @@ impl PluginManager {
         /// Sets the name of a plugin's entry point.
         /// 
         /// You probably want to set this to something unique to your application,
-        /// like `appname_init`.
+        /// like `appname_init`. Plugins loaded afterwards are refused
+        /// if their object file doesn't export it, while those loaded
+        /// before are only checked by [begin_plugin](PluginManager::begin_plugin).
         pub fn set_entry_point(&mut self, entry_point: &str) {
                 let entry_point_with_null = &format!("{}\0", entry_point);
-                self.entry = String::from(entry_point_with_null)
+                self.entry = String::from(entry_point_with_null);
+                self.loader.entry = Some(entry_point.to_owned());
+        }
+
+        /// Declares the signature the host expects for a hook, eg.
+        /// `expect_hook("on_save", "fn(*const Doc) -> i32")`.
+        ///
+        /// Plugins declaring the same hook with a different signature in their
+        /// `metadata.toml` will be refused by [begin_plugin](PluginManager::begin_plugin),
+        /// [get_hook](PluginManager::get_hook) and [hook](PluginManager::hook).
+        pub fn expect_hook(&mut self, hook: &str, signature: &str) {
+                self.hooks.insert(hook.to_owned(), signature.to_owned());
+        }
+
+        /// Checks every hook declared by the plugin against the expected signatures.
+        fn check_declared_hooks(&self, plugin: &Plugin) -> Result<(), VPluginError> {
+                for (hook, signature) in &self.hooks {
+                        plugin.check_hook_signature(hook, signature)?;
+                }
+                Ok(())
+        }
+
+        /// Resolves every hook point declared in `registry` across all registered,
+        /// started plugins, replacing any previously resolved hooks.
+        ///
+        /// Signatures registered with [expect_hook](PluginManager::expect_hook) are
+        /// checked as well: plugins declaring a different signature are skipped.
+        pub fn resolve_hooks(&self, registry: &mut HookRegistry) {
+                registry.clear();
+                for plugin in self.plugin.iter().filter(|p| p.started) {
+                        if let Err(e) = self.check_declared_hooks(plugin) {
+                                log::warn!(
+                                        "Not resolving hooks of plugin '{}': {}",
+                                        plugin.get_metadata().as_ref().unwrap().name,
+                                        e
+                                );
+                                continue;
+                        }
+                        registry.resolve(plugin);
+                }
         }
 
         /// Returns a hook from the plugin specified.
         /// See [VHook](crate::plugin_manager::VHook) for more information.
+        ///
+        /// The function pointer doesn't keep the plugin loaded, so it must not be
+        /// called once the plugin is dropped. [hook](PluginManager::hook) returns
+        /// a handle that does.
+        #[deprecated = "Use `PluginManager::hook::<VHook>`, which keeps the plugin loaded."]
         pub fn get_hook(&mut self, plugin: &Plugin, hook: &str) -> Result<VHook, VPluginError> {
+                if let Some(signature) = self.hooks.get(hook) {
+                        plugin.check_hook_signature(hook, signature)?;
+                }
                 plugin.get_hook(hook)
         }
 
+        /// Creates a new instance of the registered plugin named `name`, which must be started
+        /// and export `vplugin_create_instance` (See [PluginInstance]).
+        pub fn instantiate(&self, name: &str) -> Result<PluginInstance, VPluginError> {
+                PluginInstance::create(self.registered(name)?)
+        }
+
+        /// Returns a hook of the plugin `instance` belongs to, bound to that instance.
+        /// See [VInstanceHook](crate::VInstanceHook) for the signature it must have.
+        pub fn get_instance_hook(&self, instance: &PluginInstance, hook: &str) -> Result<InstanceHook, VPluginError> {
+                if !instance.is_alive() {
+                        log::error!("Plugin '{}' was terminated since the instance was created.", instance.plugin());
+                        return Err(VPluginError::invalid_plugin(instance.plugin(), "terminated since the instance was created"));
+                }
+                let plugin = self.registered(instance.plugin())?;
+                if let Some(signature) = self.hooks.get(hook) {
+                        plugin.check_hook_signature(hook, signature)?;
+                }
+                Ok(InstanceHook {
+                        plugin  : instance.plugin().to_owned(),
+                        hook    : plugin.load_symbol::<VInstanceHook>(hook)?,
+                        instance: instance.as_ptr(),
+                        _library: plugin.library()?,
+                        alive   : Arc::downgrade(&plugin.alive),
+                })
+        }
+
+        /// Returns the registered plugin named `name`.
+        fn registered(&self, name: &str) -> Result<&Plugin, VPluginError> {
+                match self.plugin.iter().find(|p| p.get_metadata().as_ref().map(|m| m.name.as_str()) == Some(name)) {
+                        Some(plugin) => Ok(plugin),
+                        None         => {
+                                log::error!("No plugin named '{}' is registered.", name);
+                                Err(VPluginError::invalid_plugin(name, "not registered"))
+                        }
+                }
+        }
+
+        /// Returns the function `hook` of a started plugin, as an `F`, which may
+        /// be any `extern "C"` function pointer (See [Hook]).
+        ///
+        /// The signature the plugin declares for it is checked against the one
+        /// registered with [expect_hook](PluginManager::expect_hook), if any.
+        pub fn hook<F: HookFn>(&self, plugin: &Plugin, hook: &str) -> Result<Hook<F>, VPluginError> {
+                if let Some(signature) = self.hooks.get(hook) {
+                        plugin.check_hook_signature(hook, signature)?;
+                }
+                plugin.hook(hook)
+        }
+
         /// Returns a hook as specified by the generic parameters
         /// 'T' and 'P':
         /// - `T` is the return type of the function representing the hook,
         /// - `P` is the actual function declaration (Don't add `unsafe extern fn`, it's already specified).
+        ///
         /// The function pointer returned can then be used to exchange data between the server and the plugin.
+        /// It doesn't keep the plugin loaded, so it must not be called once the plugin is dropped.
+        #[deprecated = "Use `PluginManager::hook`, which takes any number of parameters and keeps the plugin loaded."]
         pub fn get_custom_hook<P, T>(
                 &mut self,
                 plugin: &Plugin,
                 hook: &str
-        ) -> Result<unsafe extern fn(P) -> T, VPluginError> {
+        ) -> Result<unsafe extern "C" fn(P) -> T, VPluginError> {
+                if let Some(signature) = self.hooks.get(hook) {
+                        plugin.check_hook_signature(hook, signature)?;
+                }
                 plugin.get_custom_hook(hook)
         }
         
@@ -118,13 +556,16 @@ impl PluginManager {
         /// 
         /// This function is used to execute the entry point of the plugin,
         /// effectively starting the plugin like a normal executable.
+        ///
+        /// In safe mode, a plugin taking the host down while starting is disabled
+        /// on the next run (See [enable_safe_mode](PluginManager::enable_safe_mode)).
         pub fn begin_plugin(&mut self, plugin: &mut Plugin) -> Result<(), VPluginError>{
                 if !plugin.is_valid {
                         log::error!(
                                 "Attempted to start plugin '{}', which is not marked as valid.",
-                                plugin.get_metadata().as_ref().unwrap().name
+                                plugin.display_name()
                         );
-                        return Err(VPluginError::InvalidPlugin);
+                        return Err(VPluginError::invalid_plugin(plugin.display_name(), "not marked as valid"));
                 }
 
                 if plugin.started {
@@ -132,11 +573,41 @@ impl PluginManager {
                                 "Plugin '{}' has already been initialized.",
                                 plugin.get_metadata().as_ref().unwrap().name
                         );
-                        return Err(VPluginError::FailedToInitialize);
+                        return Err(VPluginError::AlreadyStarted { plugin: plugin.display_name().to_owned() });
                 }
 
+                let name = plugin.display_name().to_owned();
+                self.record(&name, |s| s.starting = true);
+                let result = self.start_plugin(plugin);
+                self.record(&name, |s| {
+                        s.starting     = false;
+                        s.last_failure = result.as_ref().err().map(VPluginError::report);
+                });
+                result
+        }
+
+        fn start_plugin(&mut self, plugin: &mut Plugin) -> Result<(), VPluginError> {
+                self.check_declared_hooks(plugin)?;
+
+                /* Plugins not using the host callbacks may still explain their failures. */
+                let errors  = ErrorSlot::new(plugin.load_symbol_unchecked("vplugin_last_error"));
+                let config  = match plugin.config_schema().is_empty() {
+                        true  => None,
+                        false => Some(self.resolve_config(plugin)?),
+                };
+                let mut context = PluginContext::new(plugin.display_name(), self.state.clone(), errors, plugin.dirs.as_ref());
+                context.config  = config.as_ref().and_then(|c| CString::new(config::to_toml(c)).ok());
                 let plugin_entry: Symbol<unsafe extern "C" fn() -> i32>;
                 unsafe {
+                        /* Plugins that want the host callbacks export `vplugin_attach`. */
+                        if let Ok(attach) = plugin.raw
+                                .as_ref()
+                                .unwrap()
+                                .get::<unsafe extern "C" fn(*const VPluginHost)>(b"vplugin_attach\0")
+                        {
+                                attach(&context.api);
+                        }
+
                         plugin_entry = match plugin.raw
                                         .as_ref()
                                         .unwrap()
@@ -146,22 +617,121 @@ impl PluginManager {
                                                 Err(e)  => {
                                                         log::error!(
                                                                 "Couldn't initialize plugin: {}",
-                                                                e.to_string()
+                                                                e
                                                         );
-                                                        return Err(VPluginError::FailedToInitialize)
+                                                        return Err(VPluginError::missing_symbol(
+                                                                plugin.display_name(),
+                                                                self.entry.trim_end_matches('\0')
+                                                        ))
                                                 }
                                         };
 
                         let ___result = plugin_entry();
                         if ___result != 0 {
-                                log::error!("Couldn't start plugin: Entry point '{}' did not return success", self.entry);
-                                return Err(VPluginError::FailedToInitialize);
+                                let message = context.errors.message();
+                                log::error!(
+                                        "Couldn't start plugin: Entry point '{}' did not return success ({})",
+                                        self.entry.trim_end_matches('\0'),
+                                        message.as_deref().unwrap_or("No error message reported")
+                                );
+                                return Err(VPluginError::FailedToInitialize {
+                                        plugin : plugin.display_name().to_owned(),
+                                        code   : ___result,
+                                        message,
+                                });
                         }
                 }
+                plugin.host    = Some(context);
+                plugin.config  = config;
                 plugin.started = true;
                 Ok(())
         }
 
+        /// Emits an event to every plugin subscribed to it, returning the
+        /// number of subscriptions the event was delivered to.
+        ///
+        /// The payload is serialized as JSON before being handed to the plugins'
+        /// callbacks (See [VPluginHost](crate::VPluginHost) for how plugins subscribe).
+        pub fn emit<T: Serialize + ?Sized>(&self, event: &str, payload: &T) -> Result<usize, VPluginError> {
+                match serde_json::to_vec(payload) {
+                        Ok (data) => Ok(self.emit_raw(event, &data)),
+                        Err(e)    => {
+                                log::error!("Couldn't serialize payload of event '{}': {}", event, e);
+                                Err(VPluginError::ParametersError)
+                        }
+                }
+        }
+
+        /// Same as [emit](PluginManager::emit), but hands `data` to the plugins as is.
+        pub fn emit_raw(&self, event: &str, data: &[u8]) -> usize {
+                events::deliver(&self.state.events, event, data)
+        }
+
+        /// Returns the names of the plugins subscribed to `event`.
+        pub fn subscribers(&self, event: &str) -> Vec<String> {
+                self.state.events.lock().unwrap().subscribers(event)
+        }
+
+        /// Returns every service currently published by a plugin,
+        /// along with the plugins using it.
+        pub fn services(&self) -> Vec<ServiceInfo> {
+                self.state.services.lock().unwrap().info()
+        }
+
+        /// Returns the interface published by a plugin under `name`, if its major version
+        /// is `major` and its minor version is at least `minor`.
+        ///
+        /// The pointer is only valid until the providing plugin is terminated.
+        pub fn get_service(&self, name: &str, major: u32, minor: u32) -> Option<*const c_void> {
+                self.state.services.lock().unwrap().interface(name, major, minor)
+        }
+
+        /// Returns the order in which the registered plugins (Indices into `self.plugin`)
+        /// can be terminated, so that consumers of a service are always terminated
+        /// before the plugin providing it.
+        fn termination_order(&self, plugins: Vec<usize>) -> Vec<usize> {
+                let names: Vec<String> = plugins
+                        .iter()
+                        .map(|&i| self.plugin[i].get_metadata().as_ref().map(|m| m.name.clone()).unwrap_or_default())
+                        .collect();
+                services::termination_order(&self.services(), &names)
+                        .into_iter()
+                        .map(|i| plugins[i])
+                        .collect()
+        }
+
+        /// Terminates the registered plugin named `name`. Plugins using services it
+        /// provides are terminated first, so that none of them is left with a
+        /// dangling interface.
+        pub fn terminate_plugin(&mut self, name: &str) -> Result<(), VPluginError> {
+                let Some(index) = self.plugin
+                        .iter()
+                        .position(|p| p.get_metadata().as_ref().map(|m| m.name.as_str()) == Some(name))
+                else {
+                        log::error!("No plugin named '{}' is registered.", name);
+                        return Err(VPluginError::invalid_plugin(name, "not registered"));
+                };
+
+                let affected = services::dependents(&self.services(), name);
+                let dependents: Vec<usize> = (0..self.plugin.len())
+                        .filter(|&i| i != index && self.plugin[i].started)
+                        .filter(|&i| self.plugin[i]
+                                .get_metadata()
+                                .as_ref()
+                                .is_some_and(|m| affected.contains(&m.name)))
+                        .collect();
+                for i in self.termination_order(dependents) {
+                        self.plugin[i].terminate().unwrap_or_else(|e| log::warn!(
+                                "Couldn't terminate plugin '{}', which depends on '{}': {}",
+                                self.plugin[i].get_metadata().as_ref().unwrap().name,
+                                name,
+                                e
+                        ));
+                }
+
+                self.plugin[index].terminate()
+        }
+
         /// ## Shutdown the PluginManager
         /// This function is used to shutdown the plugin manager,
         /// by removing all loaded plugins, neutralizing its state
@@ -178,9 +748,9 @@ impl PluginManager {
         /// ownership of the plugin manager, to ensure that the plugin manager
         /// will not be accidentally reused (Use after free). It does call
         /// `drop` on the plugin manager though automatically.
-        pub extern fn shutdown(mut self) {
-                for plugin in self.plugin.iter_mut() {
-                        plugin.terminate().unwrap_or_else(|_| log::warn!("Error occured while unloading plugin."));
+        pub extern "C" fn shutdown(mut self) {
+                for i in self.termination_order((0..self.plugin.len()).collect()) {
+                        self.plugin[i].terminate().unwrap_or_else(|_| log::warn!("Error occured while unloading plugin."));
                 }
         }
 }
@@ -193,21 +763,20 @@ impl Default for PluginManager {
 
 impl Drop for PluginManager {
         fn drop(&mut self) {
-            let vplugin_dir = env::temp_dir().join("vplugin");
-            for plug in &mut self.plugin {
-                plug
+            let vplugin_dir = self.loader.work_dir();
+            for i in self.termination_order((0..self.plugin.len()).collect()) {
+                self.plugin[i]
                         .terminate()
                         .unwrap_or_else(|e|
-                                log::error!("Couldn't unload plugin (VPlugin Error): {}", e.to_string())
+                                log::error!("Couldn't unload plugin (VPlugin Error): {}", e)
                         );
-                drop(plug);
             }
             match std::fs::remove_dir_all(&vplugin_dir) {
                 Ok(()) => log::trace!("Removed directory: {}", vplugin_dir.display()),
                 Err(e) => {
                         log::warn!(
                                 "Couldn't remove VPlugin: {} (err {}). No cleanup will be performed.",
-                                e.to_string(),
+                                e,
                                 e.raw_os_error().unwrap()
                         )
                 }
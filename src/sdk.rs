@@ -0,0 +1,474 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+//! # Plugin SDK
+//! Helpers for writing plugins in Rust, available with the `plugin-sdk` feature.
+//!
+//! Instead of hand-writing `#[no_mangle] extern "C"` functions, a plugin crate
+//! (Built as a `cdylib`) declares its exports with [`export_plugin!`](crate::export_plugin):
+//! ```rust
+//! use std::ffi::c_void;
+//!
+//! fn init() -> Result<(), String> {
+//!     println!("Hello plugin!");
+//!     Ok(())
+//! }
+//!
+//! fn exit() {
+//!     println!("Goodbye plugin!");
+//! }
+//!
+//! fn on_save(doc: *mut c_void) -> i32 {
+//!     0
+//! }
+//!
+//! vplugin::export_plugin! {
+//!     entry: "app_entry",
+//!     init : init,
+//!     exit : exit,
+//!     hooks: [
+//!         on_save(doc: *mut c_void) -> i32 = on_save,
+//!     ],
+//! }
+//! ```
+//! The plugin's `metadata.toml` is still packaged along with the object file.
+//! Every generated function catches panics, so they never unwind into the host.
+//! Panics and errors returned by `init` are reported to the host with [set_error].
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::PathBuf;
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicPtr, Ordering};
+use crate::ffi::VOption;
+use crate::host::VPluginHost;
+
+static HOST: AtomicPtr<VPluginHost> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Returns the host callbacks, once the host attached them
+/// (Right before the entry point runs).
+pub fn host() -> Option<&'static VPluginHost> {
+        unsafe { HOST.load(Ordering::Acquire).as_ref() }
+}
+
+/// Reports why the entry point or a hook is about to fail, through the `set_error`
+/// host callback. Errors returned by the initialization function and panics are
+/// reported automatically.
+pub fn set_error(message: &str) {
+        let Some(host) = host() else {
+                return;
+        };
+        if let Ok(message) = CString::new(message.replace('\0', "")) {
+                unsafe { (host.set_error)(host.context, message.as_ptr()); }
+        }
+}
+
+fn dir(get: fn(&VPluginHost) -> unsafe extern "C" fn(*mut c_void) -> *const c_char) -> Option<PathBuf> {
+        let host = host()?;
+        unsafe {
+                let dir = get(host)(host.context);
+                (!dir.is_null()).then(|| PathBuf::from(CStr::from_ptr(dir).to_string_lossy().into_owned()))
+        }
+}
+
+/// Returns the directory the plugin can keep persistent data in.
+pub fn data_dir() -> Option<PathBuf> {
+        dir(|h| h.data_dir)
+}
+
+/// Returns the directory the plugin can keep its settings in.
+pub fn config_dir() -> Option<PathBuf> {
+        dir(|h| h.config_dir)
+}
+
+/// Returns the directory the plugin can keep its caches in.
+pub fn cache_dir() -> Option<PathBuf> {
+        dir(|h| h.cache_dir)
+}
+
+/// Returns the plugin's settings, deserialized from the TOML document
+/// the host provides, if the plugin declares any.
+pub fn config<T: serde::de::DeserializeOwned>() -> Option<T> {
+        let host = host()?;
+        unsafe {
+                let config = (host.get_config)(host.context);
+                if config.is_null() {
+                        return None;
+                }
+                match toml::from_str(&CStr::from_ptr(config).to_string_lossy()) {
+                        Ok (c) => Some(c),
+                        Err(e) => {
+                                set_error(&format!("Couldn't read the plugin's settings: {}", e));
+                                None
+                        }
+                }
+        }
+}
+
+/// Implementation detail of [`export_plugin!`](crate::export_plugin).
+#[doc(hidden)]
+pub fn __attach(host: *const VPluginHost) {
+        HOST.store(host as *mut VPluginHost, Ordering::Release);
+}
+
+/// Implementation detail of [`export_plugin!`](crate::export_plugin).
+/// Copies `s` into a NUL-terminated array of `N` bytes at compile time.
+#[doc(hidden)]
+pub const fn __c_string<const N: usize>(s: &str) -> [u8; N] {
+        let bytes = s.as_bytes();
+        let mut array = [0; N];
+        let mut i = 0;
+        while i < bytes.len() && i < N - 1 {
+                array[i] = bytes[i];
+                i += 1;
+        }
+        array
+}
+
+/// Return types accepted from a plugin's initialization function.
+pub trait InitResult {
+        /// Converts the value to the entry point's return code (`0` for success).
+        fn into_code(self) -> c_int;
+}
+
+impl InitResult for () {
+        fn into_code(self) -> c_int {
+                0
+        }
+}
+
+impl InitResult for c_int {
+        fn into_code(self) -> c_int {
+                self
+        }
+}
+
+impl InitResult for bool {
+        fn into_code(self) -> c_int {
+                if self { 0 } else { -1 }
+        }
+}
+
+impl<E: Display> InitResult for Result<(), E> {
+        fn into_code(self) -> c_int {
+                match self {
+                        Ok (_) => 0,
+                        Err(e) => {
+                                set_error(&e.to_string());
+                                -1
+                        }
+                }
+        }
+}
+
+/// Implementation detail of [`export_plugin!`](crate::export_plugin).
+#[doc(hidden)]
+pub fn __run_init<R: InitResult>(f: impl FnOnce() -> R) -> c_int {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok (r) => r.into_code(),
+                Err(_) => {
+                        set_error("The plugin panicked during initialization");
+                        -1
+                }
+        }
+}
+
+/// Return types accepted from a plugin's hooks, which must be able to
+/// report a failure when the hook panics.
+pub trait HookReturn {
+        /// The value returned in place of one the hook couldn't produce. Integers follow
+        /// the C convention, returning `-1` (All bits set when unsigned), `bool` hooks return
+        /// `false` and pointers are null, so the host sees a failure
+        /// (See [HookStatus](crate::HookStatus)).
+        fn failure() -> Self;
+}
+
+impl HookReturn for () {
+        fn failure() -> Self {}
+}
+
+impl HookReturn for bool {
+        fn failure() -> Self {
+                false
+        }
+}
+
+macro_rules! impl_hook_return {
+        ($($t:ty),*) => {
+                $(
+                        impl HookReturn for $t {
+                                fn failure() -> Self {
+                                        !0
+                                }
+                        }
+                )*
+        };
+}
+
+impl_hook_return!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl HookReturn for f32 {
+        fn failure() -> Self {
+                f32::NAN
+        }
+}
+
+impl HookReturn for f64 {
+        fn failure() -> Self {
+                f64::NAN
+        }
+}
+
+impl<T> HookReturn for *const T {
+        fn failure() -> Self {
+                std::ptr::null()
+        }
+}
+
+impl<T> HookReturn for *mut T {
+        fn failure() -> Self {
+                std::ptr::null_mut()
+        }
+}
+
+impl<T> HookReturn for VOption<T> {
+        fn failure() -> Self {
+                VOption::None
+        }
+}
+
+/// Implementation detail of [`export_plugin!`](crate::export_plugin).
+/// Panicking hooks return [`R::failure()`](HookReturn::failure).
+#[doc(hidden)]
+pub fn __run_hook<R: HookReturn>(name: &str, f: impl FnOnce() -> R) -> R {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok (r) => r,
+                Err(_) => {
+                        set_error(&format!("The plugin panicked in '{}'", name));
+                        R::failure()
+                }
+        }
+}
+
+/// Returns the state of the instance a hook was called on, as created by
+/// [`export_plugin!`](crate::export_plugin) for plugins declaring an `instance` type.
+///
+/// # Safety
+/// `instance` must be the pointer the hook received, and `T` the declared type.
+pub unsafe fn instance<'a, T>(instance: *mut c_void) -> &'a mut T {
+        &mut *(instance as *mut T)
+}
+
+/// Implementation detail of [`export_plugin!`](crate::export_plugin).
+#[doc(hidden)]
+pub fn __create_instance<T: Default>() -> *mut c_void {
+        match panic::catch_unwind(T::default) {
+                Ok (state) => Box::into_raw(Box::new(state)) as *mut c_void,
+                Err(_)     => {
+                        set_error("The plugin panicked while creating an instance");
+                        std::ptr::null_mut()
+                }
+        }
+}
+
+/// Implementation detail of [`export_plugin!`](crate::export_plugin).
+#[doc(hidden)]
+pub unsafe fn __destroy_instance<T>(instance: *mut c_void) {
+        if !instance.is_null() {
+                let state = Box::from_raw(instance as *mut T);
+                __run_hook("vplugin_destroy_instance", move || drop(state));
+        }
+}
+
+/// ## export_plugin!
+/// Generates the symbols a plugin exports to VPlugin:
+/// - `entry` (Optional): The name of the entry point the host expects, `vplugin_init` by default.
+/// - `init`: The initialization function. It may return `()`, `bool`, an `i32` code or a `Result`
+///   (Whose error is reported to the host).
+/// - `exit` (Optional): The destructor, exported as `vplugin_exit`.
+/// - `instance` (Optional): A type implementing `Default`, holding the state of each instance
+///   the host creates (See [PluginInstance](crate::PluginInstance)). Instance hooks take the
+///   instance pointer first, which [`instance()`](crate::sdk::instance) turns back into the state.
+/// - `hooks` (Optional): Functions exported as hooks with C linkage, written as
+///   `name(arg: Type, ...) -> Return = implementation`. The return type must implement
+///   [HookReturn], whose failure value is returned if the implementation panics.
+///
+/// The ABI version VPlugin was built with is always exported as `VPLUGIN_ABI_VERSION`
+/// (Along with `VPLUGIN_BUILD_INFO`, see [BUILD_INFO](crate::BUILD_INFO)), and `vplugin_attach` stores the host callbacks, available through [`host()`](crate::sdk::host).
+///
+/// See the [module documentation](crate::sdk) for an example.
+#[macro_export]
+macro_rules! export_plugin {
+        (
+                $(entry: $entry:literal,)?
+                init : $init:path
+                $(, exit : $exit:path)?
+                $(, instance: $instance:ty)?
+                $(, hooks: [ $( $hook:ident ( $($arg:ident : $ty:ty),* $(,)? ) $(-> $ret:ty)? = $imp:path ),* $(,)? ])?
+                $(,)?
+        ) => {
+                #[no_mangle]
+                pub static VPLUGIN_ABI_VERSION: u32 = $crate::ABI_VERSION;
+
+                #[no_mangle]
+                pub static VPLUGIN_BUILD_INFO: [u8; $crate::BUILD_INFO.len() + 1] = $crate::sdk::__c_string($crate::BUILD_INFO);
+
+                #[no_mangle]
+                pub extern "C" fn vplugin_attach(host: *const $crate::VPluginHost) {
+                        $crate::sdk::__attach(host)
+                }
+
+                $crate::__export_entry!($($entry)?; $init);
+
+                /* Each function lives in its own block, so it can share its implementation's name. */
+                $(
+                        const _: () = {
+                                #[no_mangle]
+                                extern "C" fn vplugin_exit() {
+                                        $crate::sdk::__run_hook("vplugin_exit", $exit)
+                                }
+                        };
+                )?
+
+                $(
+                        #[no_mangle]
+                        pub extern "C" fn vplugin_create_instance() -> *mut ::std::ffi::c_void {
+                                $crate::sdk::__create_instance::<$instance>()
+                        }
+
+                        #[no_mangle]
+                        pub unsafe extern "C" fn vplugin_destroy_instance(instance: *mut ::std::ffi::c_void) {
+                                $crate::sdk::__destroy_instance::<$instance>(instance)
+                        }
+                )?
+
+                $($(
+                        const _: () = {
+                                #[export_name = stringify!($hook)]
+                                extern "C" fn __vplugin_hook($($arg: $ty),*) $(-> $ret)? {
+                                        $crate::sdk::__run_hook(stringify!($hook), move || $imp($($arg),*))
+                                }
+                        };
+                )*)?
+        };
+}
+
+/// Implementation detail of [`export_plugin!`](crate::export_plugin).
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __export_entry {
+        (; $init:path) => {
+                $crate::__export_entry!("vplugin_init"; $init);
+        };
+        ($entry:literal; $init:path) => {
+                #[export_name = $entry]
+                pub extern "C" fn __vplugin_entry() -> ::std::ffi::c_int {
+                        $crate::sdk::__run_init($init)
+                }
+        };
+}
+
+#[cfg(test)]
+mod tests {
+        use std::ffi::{c_int, c_void, CStr};
+        use super::{instance, HookReturn, VOption};
+
+        #[derive(Default)]
+        struct Counter {
+                calls: i32,
+        }
+
+        fn init() -> Result<(), String> {
+                Err("not today".into())
+        }
+
+        fn exit() {}
+
+        fn add(a: i32, b: i32) -> i32 {
+                a + b
+        }
+
+        fn fail() -> i32 {
+                panic!("hook failed")
+        }
+
+        fn count(state: *mut c_void) -> i32 {
+                let counter = unsafe { instance::<Counter>(state) };
+                counter.calls += 1;
+                counter.calls
+        }
+
+        crate::export_plugin! {
+                entry: "sdk_test_entry",
+                init : init,
+                exit : exit,
+                instance: Counter,
+                hooks: [
+                        sdk_test_add(a: i32, b: i32) -> i32 = add,
+                        sdk_test_fail() -> i32 = fail,
+                        sdk_test_count(state: *mut c_void) -> i32 = count,
+                ],
+        }
+
+        extern "C" {
+                fn sdk_test_entry() -> c_int;
+                fn sdk_test_add(a: i32, b: i32) -> i32;
+                fn sdk_test_fail() -> i32;
+                fn sdk_test_count(state: *mut c_void) -> i32;
+                fn vplugin_exit();
+        }
+
+        #[test]
+        fn exports_versions() {
+                assert_eq!(VPLUGIN_ABI_VERSION, crate::ABI_VERSION);
+                let build = CStr::from_bytes_until_nul(&VPLUGIN_BUILD_INFO).unwrap();
+                assert_eq!(build.to_str(), Ok(crate::BUILD_INFO));
+        }
+
+        #[test]
+        fn entry_reports_errors() {
+                assert_eq!(unsafe { sdk_test_entry() }, -1);
+                unsafe { vplugin_exit() };
+        }
+
+        #[test]
+        fn hooks_catch_panics() {
+                assert_eq!(unsafe { sdk_test_add(2, 3) }, 5);
+                let code = unsafe { sdk_test_fail() };
+                assert_eq!(code, -1);
+                assert_eq!(crate::HookStatus::status(&code), Err(-1));
+        }
+
+        #[test]
+        fn hook_failures_are_failures() {
+                use crate::HookStatus;
+
+                assert!(<u32 as HookReturn>::failure().status().is_err());
+                assert!(<i64 as HookReturn>::failure().status().is_err());
+                assert!(<bool as HookReturn>::failure().status().is_err());
+                assert!(<*mut c_void as HookReturn>::failure().is_null());
+                assert!(!<VOption<i32> as HookReturn>::failure().is_some());
+        }
+
+        #[test]
+        fn instances_keep_their_state() {
+                let state = vplugin_create_instance();
+                assert!(!state.is_null());
+                assert_eq!(unsafe { sdk_test_count(state) }, 1);
+                assert_eq!(unsafe { sdk_test_count(state) }, 2);
+                unsafe { vplugin_destroy_instance(state) };
+        }
+}
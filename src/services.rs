@@ -0,0 +1,437 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use crate::host::PluginContext;
+
+/// ## ServiceLostCallback
+/// Called when a service a plugin requested is withdrawn, usually because
+/// the plugin providing it was terminated. The interface pointer obtained
+/// from `request_service` must not be used anymore after this call.
+pub type ServiceLostCallback = unsafe extern "C" fn(
+        service  : *const c_char,
+        user_data: *mut c_void
+);
+
+/// Information about a service published by a plugin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInfo {
+        /// The name the service was published under.
+        pub name     : String,
+        /// The major version of the interface. Consumers must request the same one.
+        pub major    : u32,
+        /// The minor version of the interface. Consumers can request any older one.
+        pub minor    : u32,
+        /// The plugin providing the service.
+        pub provider : String,
+        /// The plugins currently using the service.
+        pub consumers: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Consumer {
+        id       : u64,
+        plugin   : String,
+        on_lost  : Option<ServiceLostCallback>,
+        user_data: *mut c_void,
+}
+
+#[derive(Debug)]
+struct Service {
+        name         : String,
+        major        : u32,
+        minor        : u32,
+        interface    : *const c_void,
+        provider     : u64,
+        provider_name: String,
+        consumers    : Vec<Consumer>,
+}
+
+/* The interface and user data pointers are owned by the plugins, which are responsible for their thread safety. */
+unsafe impl Send for Service {}
+
+/// A notification that has to be sent to a consumer once the
+/// registry's lock has been released.
+pub(crate) struct LostService {
+        name     : CString,
+        callback : ServiceLostCallback,
+        user_data: *mut c_void,
+}
+
+impl LostService {
+        pub(crate) fn notify(self) {
+                unsafe { (self.callback)(self.name.as_ptr(), self.user_data) }
+        }
+}
+
+/// Keeps track of the services published by plugins, and of who uses them.
+#[derive(Debug, Default)]
+pub(crate) struct ServiceRegistry {
+        services: Vec<Service>,
+}
+
+impl ServiceRegistry {
+        fn publish(&mut self, context: &PluginContext, name: &str, major: u32, minor: u32, interface: *const c_void) -> bool {
+                if let Some(existing) = self.services.iter().find(|s| s.name == name) {
+                        log::error!(
+                                "Plugin '{}' cannot publish service '{}', which is already provided by '{}'.",
+                                context.plugin,
+                                name,
+                                existing.provider_name
+                        );
+                        return false;
+                }
+
+                log::trace!("Plugin '{}' published service '{}' v{}.{}.", context.plugin, name, major, minor);
+                self.services.push(Service {
+                        name         : name.to_owned(),
+                        major,
+                        minor,
+                        interface,
+                        provider     : context.id,
+                        provider_name: context.plugin.clone(),
+                        consumers    : Vec::new(),
+                });
+                true
+        }
+
+        fn find(&self, name: &str, major: u32, minor: u32) -> Option<usize> {
+                self.services
+                        .iter()
+                        .position(|s| s.name == name && s.major == major && s.minor >= minor)
+        }
+
+        fn request(
+                &mut self,
+                context  : &PluginContext,
+                name     : &str,
+                major    : u32,
+                minor    : u32,
+                on_lost  : Option<ServiceLostCallback>,
+                user_data: *mut c_void
+        ) -> *const c_void {
+                let Some(index) = self.find(name, major, minor) else {
+                        log::warn!(
+                                "Plugin '{}' requested service '{}' v{}.{}, which is not available.",
+                                context.plugin,
+                                name,
+                                major,
+                                minor
+                        );
+                        return std::ptr::null();
+                };
+
+                let service = &mut self.services[index];
+                service.consumers.retain(|c| c.id != context.id);
+                service.consumers.push(Consumer {
+                        id    : context.id,
+                        plugin: context.plugin.clone(),
+                        on_lost,
+                        user_data,
+                });
+                service.interface
+        }
+
+        fn release(&mut self, owner: u64, name: &str) -> bool {
+                let mut released = false;
+                for service in self.services.iter_mut().filter(|s| s.name == name) {
+                        let before = service.consumers.len();
+                        service.consumers.retain(|c| c.id != owner);
+                        released |= before != service.consumers.len();
+                }
+                released
+        }
+
+        /// Removes a service, returning the notifications for its consumers.
+        fn withdraw(&mut self, owner: u64, name: &str) -> Option<Vec<LostService>> {
+                let index   = self.services.iter().position(|s| s.provider == owner && s.name == name)?;
+                let service = self.services.remove(index);
+
+                Some(service.consumers
+                        .into_iter()
+                        .filter_map(|c| Some(LostService {
+                                name     : CString::new(service.name.as_str()).ok()?,
+                                callback : c.on_lost?,
+                                user_data: c.user_data,
+                        }))
+                        .collect())
+        }
+
+        /// Removes every service provided and every request made by a plugin.
+        pub(crate) fn detach(&mut self, owner: u64) -> Vec<LostService> {
+                let provided: Vec<String> = self.services
+                        .iter()
+                        .filter(|s| s.provider == owner)
+                        .map(|s| s.name.clone())
+                        .collect();
+
+                for service in self.services.iter_mut() {
+                        service.consumers.retain(|c| c.id != owner);
+                }
+                provided
+                        .iter()
+                        .filter_map(|name| self.withdraw(owner, name))
+                        .flatten()
+                        .collect()
+        }
+
+        pub(crate) fn interface(&self, name: &str, major: u32, minor: u32) -> Option<*const c_void> {
+                self.find(name, major, minor).map(|i| self.services[i].interface)
+        }
+
+        pub(crate) fn info(&self) -> Vec<ServiceInfo> {
+                self.services
+                        .iter()
+                        .map(|s| ServiceInfo {
+                                name     : s.name.clone(),
+                                major    : s.major,
+                                minor    : s.minor,
+                                provider : s.provider_name.clone(),
+                                consumers: s.consumers.iter().map(|c| c.plugin.clone()).collect(),
+                        })
+                        .collect()
+        }
+}
+
+/// Returns the plugins using services of `plugin`, or services of those plugins in turn.
+pub(crate) fn dependents(services: &[ServiceInfo], plugin: &str) -> Vec<String> {
+        let mut affected = vec![plugin.to_owned()];
+        let mut i = 0;
+        while i < affected.len() {
+                let provider = affected[i].clone();
+                for service in services.iter().filter(|s| s.provider == provider) {
+                        for consumer in &service.consumers {
+                                if !affected.contains(consumer) {
+                                        affected.push(consumer.clone());
+                                }
+                        }
+                }
+                i += 1;
+        }
+        affected.remove(0);
+        affected
+}
+
+/// Returns the order in which `plugins` (Indices into it) can be terminated, so that
+/// consumers of a service are always terminated before the plugin providing it.
+pub(crate) fn termination_order(services: &[ServiceInfo], plugins: &[String]) -> Vec<usize> {
+        let mut remaining: Vec<usize> = (0..plugins.len()).collect();
+        let mut order = Vec::with_capacity(plugins.len());
+
+        while !remaining.is_empty() {
+                let next = remaining
+                        .iter()
+                        .position(|&i| {
+                                let provider = &plugins[i];
+                                !services.iter().any(|s| {
+                                        s.provider == *provider
+                                        && s.consumers.iter().any(|c| c != provider && remaining.iter().any(|&j| plugins[j] == *c))
+                                })
+                        })
+                        /* Plugins using each other's services: Nothing can be done. */
+                        .unwrap_or(0);
+                order.push(remaining.remove(next));
+        }
+        order
+}
+
+unsafe fn service_name<'a>(name: *const c_char) -> Option<std::borrow::Cow<'a, str>> {
+        (!name.is_null()).then(|| CStr::from_ptr(name).to_string_lossy())
+}
+
+/// Host callback: publishes an interface under `name`.
+pub(crate) unsafe extern "C" fn publish_service(
+        context  : *mut c_void,
+        name     : *const c_char,
+        major    : u32,
+        minor    : u32,
+        interface: *const c_void
+) -> c_int {
+        let (Some(context), Some(name)) = (PluginContext::from_raw(context), service_name(name)) else {
+                return -1;
+        };
+        if interface.is_null() {
+                return -1;
+        }
+
+        match context.state.services.lock().unwrap().publish(context, &name, major, minor, interface) {
+                true  => 0,
+                false => -1,
+        }
+}
+
+/// Host callback: withdraws a service published by the calling plugin.
+pub(crate) unsafe extern "C" fn withdraw_service(context: *mut c_void, name: *const c_char) -> c_int {
+        let (Some(context), Some(name)) = (PluginContext::from_raw(context), service_name(name)) else {
+                return -1;
+        };
+
+        let lost = context.state.services.lock().unwrap().withdraw(context.id, &name);
+        match lost {
+                Some(lost) => {
+                        lost.into_iter().for_each(LostService::notify);
+                        0
+                }
+                None => -1,
+        }
+}
+
+/// Host callback: returns the interface of a service compatible with `major.minor`, or NULL.
+pub(crate) unsafe extern "C" fn request_service(
+        context  : *mut c_void,
+        name     : *const c_char,
+        major    : u32,
+        minor    : u32,
+        on_lost  : Option<ServiceLostCallback>,
+        user_data: *mut c_void
+) -> *const c_void {
+        let (Some(context), Some(name)) = (PluginContext::from_raw(context), service_name(name)) else {
+                return std::ptr::null();
+        };
+
+        context.state.services.lock().unwrap().request(context, &name, major, minor, on_lost, user_data)
+}
+
+/// Host callback: tells VPlugin the calling plugin doesn't use a service anymore.
+pub(crate) unsafe extern "C" fn release_service(context: *mut c_void, name: *const c_char) -> c_int {
+        let (Some(context), Some(name)) = (PluginContext::from_raw(context), service_name(name)) else {
+                return -1;
+        };
+
+        match context.state.services.lock().unwrap().release(context.id, &name) {
+                true  => 0,
+                false => -1,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use crate::host::{ErrorSlot, HostState};
+
+        static DICTIONARY: u32 = 42;
+
+        unsafe extern "C" fn lost(service: *const c_char, user_data: *mut c_void) {
+                assert_eq!(CStr::from_ptr(service).to_str(), Ok("dictionary"));
+                (*(user_data as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn context(plugin: &str, state: &Arc<HostState>) -> Box<PluginContext> {
+                PluginContext::new(plugin, state.clone(), ErrorSlot::default(), None)
+        }
+
+        fn publish(context: &PluginContext, major: u32, minor: u32) -> c_int {
+                let interface = &DICTIONARY as *const u32 as *const c_void;
+                unsafe { (context.api.publish_service)(context.api.context, c"dictionary".as_ptr(), major, minor, interface) }
+        }
+
+        fn request(context: &PluginContext, major: u32, minor: u32, counter: &AtomicUsize) -> *const c_void {
+                let counter = counter as *const AtomicUsize as *mut c_void;
+                unsafe { (context.api.request_service)(context.api.context, c"dictionary".as_ptr(), major, minor, Some(lost), counter) }
+        }
+
+        fn info(plugin: &str, consumers: &[&str]) -> ServiceInfo {
+                ServiceInfo {
+                        name     : format!("{}-service", plugin),
+                        major    : 1,
+                        minor    : 0,
+                        provider : plugin.into(),
+                        consumers: consumers.iter().map(|&c| c.into()).collect(),
+                }
+        }
+
+        #[test]
+        fn requests_compatible_versions() {
+                let state    = Arc::new(HostState::default());
+                let provider = context("dict", &state);
+                let consumer = context("spell", &state);
+                let counter  = AtomicUsize::new(0);
+
+                assert_eq!(publish(&provider, 1, 2), 0);
+                assert_eq!(publish(&consumer, 1, 2), -1);
+                assert!(request(&consumer, 1, 3, &counter).is_null());
+                assert!(request(&consumer, 2, 0, &counter).is_null());
+                assert_eq!(request(&consumer, 1, 1, &counter), &DICTIONARY as *const u32 as *const c_void);
+
+                let services = state.services.lock().unwrap().info();
+                assert_eq!(services, [ServiceInfo {
+                        name     : "dictionary".into(),
+                        major    : 1,
+                        minor    : 2,
+                        provider : "dict".into(),
+                        consumers: vec!["spell".into()],
+                }]);
+        }
+
+        #[test]
+        fn withdrawing_notifies_consumers() {
+                let state    = Arc::new(HostState::default());
+                let provider = context("dict", &state);
+                let consumer = context("spell", &state);
+                let released = context("grammar", &state);
+                let counter  = AtomicUsize::new(0);
+
+                publish(&provider, 1, 0);
+                request(&consumer, 1, 0, &counter);
+                request(&released, 1, 0, &counter);
+                assert_eq!(unsafe { (released.api.release_service)(released.api.context, c"dictionary".as_ptr()) }, 0);
+
+                /* Only the provider can withdraw it. */
+                assert_eq!(unsafe { (consumer.api.withdraw_service)(consumer.api.context, c"dictionary".as_ptr()) }, -1);
+                assert_eq!(unsafe { (provider.api.withdraw_service)(provider.api.context, c"dictionary".as_ptr()) }, 0);
+                assert_eq!(counter.load(Ordering::SeqCst), 1);
+                assert!(state.services.lock().unwrap().info().is_empty());
+        }
+
+        #[test]
+        fn detaching_drops_services_and_requests() {
+                let state    = Arc::new(HostState::default());
+                let provider = context("dict", &state);
+                let consumer = context("spell", &state);
+                let counter  = AtomicUsize::new(0);
+
+                publish(&provider, 1, 0);
+                request(&consumer, 1, 0, &counter);
+                drop(consumer);
+                assert!(state.services.lock().unwrap().info()[0].consumers.is_empty());
+
+                let consumer = context("spell", &state);
+                request(&consumer, 1, 0, &counter);
+                drop(provider);
+                assert_eq!(counter.load(Ordering::SeqCst), 1);
+                assert!(state.services.lock().unwrap().interface("dictionary", 1, 0).is_none());
+        }
+
+        #[test]
+        fn finds_dependents_through_services() {
+                let services = [info("dict", &["spell", "grammar"]), info("spell", &["editor"]), info("other", &["dict"])];
+                assert_eq!(dependents(&services, "dict"), ["spell", "grammar", "editor"]);
+                assert_eq!(dependents(&services, "editor"), Vec::<String>::new());
+        }
+
+        #[test]
+        fn terminates_consumers_before_providers() {
+                let services = [info("dict", &["spell", "dict"]), info("spell", &["editor"])];
+                let plugins  = ["dict", "spell", "editor", "unrelated"].map(String::from);
+                assert_eq!(termination_order(&services, &plugins), [2, 1, 0, 3]);
+
+                /* Plugins using each other's services are terminated in the order given. */
+                let services = [info("a", &["b"]), info("b", &["a"])];
+                assert_eq!(termination_order(&services, &["a".into(), "b".into()]), [0, 1]);
+        }
+}
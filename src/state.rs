@@ -0,0 +1,198 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use serde_derive::{Deserialize, Serialize};
+use crate::error::VPluginError;
+
+/// The name of the file a [PluginManager](crate::PluginManager) keeps the state of its plugins in,
+/// next to the `plugins` directory holding their data (Or in the storage root, if set).
+pub const STATE_FILE: &str = "plugins.toml";
+
+fn enabled() -> bool {
+        true
+}
+
+/// ## PluginState
+/// What the host remembers about a plugin across runs:
+/// ```toml
+/// [plugins.spell]
+/// enabled        = false
+/// pinned_version = "1.2.0"
+/// last_failure   = "Plugin 'spell' crashed while starting on the previous run"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginState {
+        /// Disabled plugins are skipped by [discover](crate::PluginManager::discover)
+        /// and [begin_all](crate::PluginManager::begin_all).
+        #[serde(default = "enabled")]
+        pub enabled       : bool,
+        /// If set, [discover](crate::PluginManager::discover) only loads this version of the plugin.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub pinned_version: Option<String>,
+        /// Why the plugin last failed to load or start. Cleared once it starts successfully.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_failure  : Option<String>,
+        /* Set while the entry point runs: If it's still there on the next run, the plugin took the host down. */
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pub(crate) starting: bool,
+}
+
+impl Default for PluginState {
+        fn default() -> Self {
+                Self {
+                        enabled       : true,
+                        pinned_version: None,
+                        last_failure  : None,
+                        starting      : false,
+                }
+        }
+}
+
+/// This is purely for (de)serialization.
+#[derive(Default, Serialize, Deserialize)]
+struct Contents {
+        #[serde(default)]
+        plugins: BTreeMap<String, PluginState>,
+}
+
+/// The state file of a PluginManager, saved after every change.
+#[derive(Debug)]
+pub(crate) struct StateFile {
+        path   : PathBuf,
+        plugins: BTreeMap<String, PluginState>,
+}
+
+impl StateFile {
+        /// Reads the state file at `path` (Which may not exist yet).
+        ///
+        /// In `safe_mode`, plugins that were still starting when the previous run ended crashed
+        /// the host, so they are disabled until the user enables them again.
+        pub(crate) fn open(path: PathBuf, safe_mode: bool) -> Result<Self, VPluginError> {
+                let contents = match fs::read_to_string(&path) {
+                        Ok (c) => toml::from_str(&c).map_err(|e| {
+                                log::error!("Couldn't read plugin state from {}: {}", path.display(), e);
+                                VPluginError::InvalidState { path: path.clone(), source: e }
+                        })?,
+                        Err(e) if e.kind() == ErrorKind::NotFound => Contents::default(),
+                        Err(e) => return Err(VPluginError::io(path, e)),
+                };
+
+                let mut file = Self { path, plugins: contents.plugins };
+                let mut crashed = false;
+                for (name, state) in file.plugins.iter_mut().filter(|(_, s)| safe_mode && s.starting) {
+                        log::warn!("Plugin '{}' crashed while starting on the previous run, disabling it.", name);
+                        state.starting     = false;
+                        state.enabled      = false;
+                        state.last_failure = Some(format!("Plugin '{}' crashed while starting on the previous run", name));
+                        crashed = true;
+                }
+                if crashed {
+                        file.save()?;
+                }
+                Ok(file)
+        }
+
+        pub(crate) fn plugins(&self) -> &BTreeMap<String, PluginState> {
+                &self.plugins
+        }
+
+        /// Returns the state of the plugin named `name`, or the default one if it's unknown.
+        pub(crate) fn get(&self, name: &str) -> PluginState {
+                self.plugins.get(name).cloned().unwrap_or_default()
+        }
+
+        /// Changes the state of the plugin named `name` and saves the file.
+        pub(crate) fn update(&mut self, name: &str, f: impl FnOnce(&mut PluginState)) -> Result<(), VPluginError> {
+                f(self.plugins.entry(name.to_owned()).or_default());
+                self.save()
+        }
+
+        /// Replaces the file, so a crash never leaves it half written.
+        fn save(&self) -> Result<(), VPluginError> {
+                if let Some(dir) = self.path.parent() {
+                        fs::create_dir_all(dir).map_err(|e| VPluginError::io(dir, e))?;
+                }
+                let contents = toml::to_string(&Contents { plugins: self.plugins.clone() })
+                        .map_err(|e| VPluginError::InternalError { err: e.to_string() })?;
+                let tmp = self.path.with_extension("toml.tmp");
+                fs::write(&tmp, contents).map_err(|e| VPluginError::io(&tmp, e))?;
+                fs::rename(&tmp, &self.path).map_err(|e| VPluginError::io(&self.path, e))
+        }
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        /// A state file where `spell` was still starting when the previous run ended.
+        fn crashed(name: &str) -> PathBuf {
+                let dir = std::env::temp_dir().join(format!("vplugin-state-{}-{}", std::process::id(), name));
+                fs::create_dir_all(&dir).unwrap();
+                let path = dir.join(STATE_FILE);
+                fs::write(&path, "[plugins.spell]\nstarting = true\n\n[plugins.dict]\nenabled = true\n").unwrap();
+                path
+        }
+
+        #[test]
+        fn safe_mode_disables_crashed_plugins() {
+                let path  = crashed("safe");
+                let file  = StateFile::open(path.clone(), true).unwrap();
+                let spell = file.get("spell");
+                assert!(!spell.enabled && !spell.starting);
+                assert!(spell.last_failure.is_some());
+                assert_eq!(file.get("dict"), PluginState::default());
+
+                /* And it's saved, so it stays disabled. */
+                assert_eq!(StateFile::open(path.clone(), true).unwrap().get("spell"), spell);
+                fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn crashed_plugins_stay_enabled_without_safe_mode() {
+                let path  = crashed("unsafe");
+                let file  = StateFile::open(path.clone(), false).unwrap();
+                let spell = file.get("spell");
+                assert!(spell.enabled && spell.starting);
+                assert!(spell.last_failure.is_none());
+                fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn malformed_state_files_are_reported() {
+                let path = crashed("malformed");
+                fs::write(&path, "[plugins.spell\n").unwrap();
+                let err  = StateFile::open(path.clone(), false).unwrap_err();
+                assert!(matches!(err, VPluginError::InvalidState { path: ref p, .. } if *p == path));
+                fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn updates_are_saved() {
+                let dir  = std::env::temp_dir().join(format!("vplugin-state-{}-update", std::process::id()));
+                let path = dir.join(STATE_FILE);
+                let mut file = StateFile::open(path.clone(), true).unwrap();
+                assert!(file.plugins().is_empty());
+
+                file.update("spell", |s| s.pinned_version = Some("1.2.0".into())).unwrap();
+                let file = StateFile::open(path, true).unwrap();
+                assert_eq!(file.get("spell").pinned_version.as_deref(), Some("1.2.0"));
+                fs::remove_dir_all(dir).unwrap();
+        }
+}
@@ -0,0 +1,209 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+extern crate dirs;
+
+use std::ffi::{c_char, c_void, CString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::error::VPluginError;
+use crate::host::PluginContext;
+use crate::state::STATE_FILE;
+
+/// ## PluginDirs
+/// The directories a plugin keeps its files in across runs. Unlike the
+/// directory a plugin is extracted to, they are never deleted by VPlugin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginDirs {
+        /// Persistent data, eg. databases or downloaded content.
+        pub data  : PathBuf,
+        /// Settings, usually edited by the user.
+        pub config: PathBuf,
+        /// Files that can be recreated, and deleted at any time by the user.
+        pub cache : PathBuf,
+}
+
+/// Where a [PluginManager](crate::PluginManager) puts the directories of its plugins.
+#[derive(Debug, Clone)]
+pub(crate) struct StorageRoots {
+        pub(crate) application: String,
+        pub(crate) root       : Option<PathBuf>,
+}
+
+impl Default for StorageRoots {
+        fn default() -> Self {
+                Self {
+                        application: String::from("vplugin"),
+                        root       : None,
+                }
+        }
+}
+
+impl StorageRoots {
+        /// Returns the directories of the plugin named `plugin`:
+        /// - `<root>/{data,config,cache}/<plugin>` if a root was set,
+        /// - Otherwise `<app>/plugins/<plugin>` inside the platform's data, config and cache
+        ///   directories (`$XDG_DATA_HOME`, `$XDG_CONFIG_HOME` and `$XDG_CACHE_HOME` on Linux).
+        pub(crate) fn dirs_for(&self, plugin: &str) -> Result<PluginDirs, VPluginError> {
+                if let Some(root) = &self.root {
+                        return Ok(PluginDirs {
+                                data  : root.join("data").join(plugin),
+                                config: root.join("config").join(plugin),
+                                cache : root.join("cache").join(plugin),
+                        });
+                }
+
+                let base = |dir: Option<PathBuf>| match dir {
+                        Some(d) => Ok(d.join(&self.application).join("plugins").join(plugin)),
+                        None    => Err(VPluginError::InternalError { err: "No home directory available".into() }),
+                };
+                Ok(PluginDirs {
+                        data  : base(dirs::data_dir())?,
+                        config: base(dirs::config_dir())?,
+                        cache : base(dirs::cache_dir())?,
+                })
+        }
+
+        /// Returns where the state of the plugins is kept: `<root>/plugins.toml` if a root
+        /// was set, otherwise `<app>/plugins.toml` inside the platform's data directory.
+        pub(crate) fn state_file(&self) -> Result<PathBuf, VPluginError> {
+                if let Some(root) = &self.root {
+                        return Ok(root.join(STATE_FILE));
+                }
+                match dirs::data_dir() {
+                        Some(d) => Ok(d.join(&self.application).join(STATE_FILE)),
+                        None    => {
+                                log::error!("Couldn't determine the user's directories, set a storage root instead.");
+                                Err(VPluginError::InternalError { err: "No home directory available".into() })
+                        }
+                }
+        }
+
+        /// Returns where the extraction cache is kept by default: `<root>/extracted` if a
+        /// root was set, otherwise `<app>/extracted` inside the platform's cache directory.
+        pub(crate) fn extraction_cache(&self) -> Result<PathBuf, VPluginError> {
+                if let Some(root) = &self.root {
+                        return Ok(root.join("extracted"));
+                }
+                match dirs::cache_dir() {
+                        Some(d) => Ok(d.join(&self.application).join("extracted")),
+                        None    => {
+                                log::error!("Couldn't determine the user's directories, set a storage root instead.");
+                                Err(VPluginError::InternalError { err: "No home directory available".into() })
+                        }
+                }
+        }
+
+}
+
+impl PluginDirs {
+        /// Creates the directories, if they don't exist yet.
+        pub(crate) fn create(&self, plugin: &str) -> Result<(), VPluginError> {
+                for dir in [&self.data, &self.config, &self.cache] {
+                        create(dir, plugin)?;
+                }
+                Ok(())
+        }
+}
+
+fn create(dir: &Path, plugin: &str) -> Result<(), VPluginError> {
+        fs::create_dir_all(dir).map_err(|e| {
+                log::error!("Couldn't create directory '{}' of plugin '{}': {}", dir.display(), plugin, e);
+                VPluginError::io(dir, e)
+        })
+}
+
+/// The directories of a plugin as C strings, handed out by the host callbacks.
+#[derive(Debug)]
+pub(crate) struct DirNames {
+        dirs  : PluginDirs,
+        data  : CString,
+        config: CString,
+        cache : CString,
+}
+
+impl DirNames {
+        pub(crate) fn new(dirs: &PluginDirs) -> Option<Self> {
+                let c = |path: &Path| CString::new(path.to_string_lossy().into_owned()).ok();
+                Some(Self {
+                        dirs  : dirs.clone(),
+                        data  : c(&dirs.data)?,
+                        config: c(&dirs.config)?,
+                        cache : c(&dirs.cache)?,
+                })
+        }
+}
+
+/// Returns the directory `which` picks, or NULL. It's created again
+/// if it was deleted since the plugin was loaded (eg. the cache).
+unsafe fn dir_name(context: *mut c_void, which: impl Fn(&DirNames) -> (&Path, &CString)) -> *const c_char {
+        let Some(context) = PluginContext::from_raw(context) else {
+                return std::ptr::null();
+        };
+        match context.dirs.as_ref().map(which) {
+                Some((path, name)) if create(path, &context.plugin).is_ok() => name.as_ptr(),
+                _ => std::ptr::null(),
+        }
+}
+
+/// Host callback: returns the plugin's data directory, or NULL.
+pub(crate) unsafe extern "C" fn data_dir(context: *mut c_void) -> *const c_char {
+        dir_name(context, |d| (&d.dirs.data, &d.data))
+}
+
+/// Host callback: returns the plugin's config directory, or NULL.
+pub(crate) unsafe extern "C" fn config_dir(context: *mut c_void) -> *const c_char {
+        dir_name(context, |d| (&d.dirs.config, &d.config))
+}
+
+/// Host callback: returns the plugin's cache directory, or NULL.
+pub(crate) unsafe extern "C" fn cache_dir(context: *mut c_void) -> *const c_char {
+        dir_name(context, |d| (&d.dirs.cache, &d.cache))
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn dirs_are_kept_in_the_storage_root() {
+                let root  = std::env::temp_dir().join(format!("vplugin-storage-{}", std::process::id()));
+                let roots = StorageRoots { application: "app".into(), root: Some(root.clone()) };
+                let dirs  = roots.dirs_for("spell").unwrap();
+                assert_eq!(dirs, PluginDirs {
+                        data  : root.join("data/spell"),
+                        config: root.join("config/spell"),
+                        cache : root.join("cache/spell"),
+                });
+
+                dirs.create("spell").unwrap();
+                assert!(dirs.data.is_dir() && dirs.config.is_dir() && dirs.cache.is_dir());
+                fs::remove_dir_all(&root).unwrap();
+        }
+
+        #[test]
+        fn dirs_default_to_the_user_directories() {
+                let roots = StorageRoots { application: "app".into(), root: None };
+                let base  = |dir: Option<PathBuf>| dir.map(|d| d.join("app/plugins/spell"));
+                match (base(dirs::data_dir()), base(dirs::config_dir()), base(dirs::cache_dir())) {
+                        (Some(data), Some(config), Some(cache)) => {
+                                assert_eq!(roots.dirs_for("spell").unwrap(), PluginDirs { data, config, cache });
+                        }
+                        /* No home directory, and no root to fall back on. */
+                        _ => assert!(roots.dirs_for("spell").is_err()),
+                }
+        }
+}
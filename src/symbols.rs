@@ -0,0 +1,609 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+extern crate object;
+
+use std::ffi::CStr;
+use std::fs;
+use std::path::Path;
+use object::{
+        elf,
+        Architecture,
+        BinaryFormat,
+        Endianness,
+        Object,
+        ObjectKind,
+        ObjectSegment,
+        ObjectSymbol,
+        ReadRef,
+        SymbolKind
+};
+use object::read::StringTable;
+use object::read::elf::{Dyn, ElfFile, FileHeader, GnuHashTable, HashTable, ProgramHeader, Sym};
+use crate::error::VPluginError;
+use crate::loader;
+
+/// The kind of a symbol exported by a plugin's object file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportedSymbolKind {
+        /// A function, which can be loaded as a hook.
+        Function,
+        /// A global variable or constant.
+        Data,
+        /// The object file format doesn't tell what the symbol is
+        /// (Usually the case for PE export tables).
+        Unknown,
+}
+
+/// A symbol exported by a plugin's object file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportedSymbol {
+        pub name: String,
+        pub kind: ExportedSymbolKind,
+}
+
+impl ExportedSymbol {
+        /// Returns whether the symbol is (or may be) a function.
+        pub fn is_function(&self) -> bool {
+                self.kind != ExportedSymbolKind::Data
+        }
+}
+
+fn symbol_kind(kind: SymbolKind) -> ExportedSymbolKind {
+        match kind {
+                SymbolKind::Text => ExportedSymbolKind::Function,
+                SymbolKind::Data
+                | SymbolKind::Tls => ExportedSymbolKind::Data,
+                _ => ExportedSymbolKind::Unknown,
+        }
+}
+
+fn elf_symbol_kind(kind: u8) -> ExportedSymbolKind {
+        match kind {
+                elf::STT_FUNC
+                | elf::STT_GNU_IFUNC => ExportedSymbolKind::Function,
+                elf::STT_OBJECT
+                | elf::STT_COMMON
+                | elf::STT_TLS       => ExportedSymbolKind::Data,
+                _                    => ExportedSymbolKind::Unknown,
+        }
+}
+
+/// Parses the object file at `path` and returns every symbol it exports.
+/// This never loads (Or executes) the object file itself.
+pub(crate) fn read_exported_symbols(path: &Path) -> Result<Vec<ExportedSymbol>, VPluginError> {
+        let data = match fs::read(path) {
+                Ok (d) => d,
+                Err(e) => {
+                        log::error!("Couldn't read object file {}: {}", path.display(), e);
+                        return Err(VPluginError::io(path, e));
+                }
+        };
+        match ObjectInfo::parse(&data) {
+                Ok (info) => Ok(info.exports),
+                Err(e)    => {
+                        log::error!("Couldn't parse object file {}: {}", path.display(), e);
+                        Err(VPluginError::invalid_plugin(path.display().to_string(), e.to_string()))
+                }
+        }
+}
+
+/// The dynamic linking information of an object file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct DynamicInfo {
+        /// The name other object files refer to it by (`DT_SONAME`).
+        pub(crate) soname: Option<String>,
+        /// The libraries it needs (`DT_NEEDED`), in order.
+        pub(crate) needed: Vec<String>,
+        /// The directories searched for them first (`DT_RUNPATH`, or `DT_RPATH`).
+        pub(crate) runpath: Vec<String>,
+        /// Whether it's a position-independent executable rather than a library.
+        pub(crate) pie   : bool,
+}
+
+/// Everything read from an object file before it is loaded,
+/// so it only has to be parsed once.
+#[derive(Debug, Clone)]
+pub(crate) struct ObjectInfo {
+        pub(crate) format : BinaryFormat,
+        pub(crate) is_64  : bool,
+        pub(crate) little : bool,
+        pub(crate) arch   : Architecture,
+        pub(crate) kind   : ObjectKind,
+        /// Only ELF files record it, it's empty for other formats.
+        pub(crate) dynamic: DynamicInfo,
+        /// Every symbol it exports, sorted by name.
+        pub(crate) exports: Vec<ExportedSymbol>,
+        /// What it exports as `VPLUGIN_ABI_VERSION` and `VPLUGIN_BUILD_INFO`.
+        pub(crate) abi    : Option<u32>,
+        pub(crate) build  : Option<String>,
+}
+
+impl ObjectInfo {
+        /// Reads the object file `object` of `plugin` (Extracted at `path`),
+        /// without loading (Or executing) it.
+        pub(crate) fn read(plugin: &str, object: &str, path: &Path) -> Result<Self, VPluginError> {
+                let data = fs::read(path).map_err(|e| VPluginError::io(path, e))?;
+                Self::parse(&data).map_err(|e| {
+                        log::error!("Object file '{}' of plugin '{}' is not a shared object ({}).", object, plugin, e);
+                        VPluginError::NotSharedObject { plugin: plugin.into(), object: object.into(), kind: e.to_string() }
+                })
+        }
+
+        pub(crate) fn parse(data: &[u8]) -> object::Result<Self> {
+                let file = object::File::parse(data)?;
+                let (dynamic, mut symbols) = match &file {
+                        object::File::Elf32(elf) => elf_info(elf)?,
+                        object::File::Elf64(elf) => elf_info(elf)?,
+                        _ => (DynamicInfo::default(), exported_symbols(&file)?),
+                };
+                symbols.retain(|(s, _)| !s.name.is_empty());
+                symbols.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+                symbols.dedup_by(|(a, _), (b, _)| a.name == b.name);
+
+                /* Read from the file's contents, at the address the symbols are loaded at. */
+                let contents = |name: &str| {
+                        let (_, address) = symbols.iter().find(|(s, _)| s.name == name)?;
+                        file.segments().find_map(|s| {
+                                let offset = address.checked_sub(s.address()).filter(|o| *o < s.size())?;
+                                s.data().ok()?.get(offset as usize..)
+                        })
+                };
+                let abi = contents("VPLUGIN_ABI_VERSION")
+                        .and_then(|d| d.get(..4)?.try_into().ok())
+                        .map(|bytes| match file.is_little_endian() {
+                                true  => u32::from_le_bytes(bytes),
+                                false => u32::from_be_bytes(bytes),
+                        });
+                let build = contents("VPLUGIN_BUILD_INFO")
+                        .and_then(|d| CStr::from_bytes_until_nul(d).ok())
+                        .map(|s| s.to_string_lossy().into_owned());
+
+                Ok(Self {
+                        format : file.format(),
+                        is_64  : file.is_64(),
+                        little : file.is_little_endian(),
+                        arch   : file.architecture(),
+                        kind   : file.kind(),
+                        dynamic,
+                        exports: symbols.into_iter().map(|(s, _)| s).collect(),
+                        abi,
+                        build,
+                })
+        }
+}
+
+/// Returns the symbols exported by a non-ELF object file, along with their address.
+fn exported_symbols(file: &object::File) -> object::Result<Vec<(ExportedSymbol, u64)>> {
+        Ok(match file.format() {
+                /* Mach-O prefixes every C symbol with an underscore. */
+                BinaryFormat::MachO => file
+                        .symbols()
+                        .filter(|s| s.is_definition() && s.is_global())
+                        .filter_map(|s| Some((
+                                ExportedSymbol {
+                                        name: s.name().ok()?.strip_prefix('_')?.to_owned(),
+                                        kind: symbol_kind(s.kind()),
+                                },
+                                s.address()
+                        )))
+                        .collect(),
+                _ => file
+                        .exports()?
+                        .iter()
+                        .filter_map(|e| Some((
+                                ExportedSymbol {
+                                        name: std::str::from_utf8(e.name()).ok()?.to_owned(),
+                                        kind: ExportedSymbolKind::Unknown,
+                                },
+                                e.address()
+                        )))
+                        .collect(),
+        })
+}
+
+/// Maps an address of an ELF file to where it is in the file, through its `PT_LOAD` segments.
+fn elf_offset<Elf: FileHeader<Endian = Endianness>>(file: &ElfFile<'_, Elf>, address: u64) -> Option<u64> {
+        let endian = file.endian();
+        file.elf_program_headers()
+                .iter()
+                .filter(|p| p.p_type(endian) == elf::PT_LOAD)
+                .find_map(|p| {
+                        let start: u64 = p.p_vaddr(endian).into();
+                        let size : u64 = p.p_filesz(endian).into();
+                        let file : u64 = p.p_offset(endian).into();
+                        (start..start + size).contains(&address).then(|| address - start + file)
+                })
+}
+
+/// Reads the dynamic linking information and exported symbols of an ELF file. They are found
+/// through the section table, or the `PT_DYNAMIC` segment for files without one (eg. stripped
+/// with `sstrip`), which is all the dynamic linker uses.
+fn elf_info<Elf: FileHeader<Endian = Endianness>>(
+        file: &ElfFile<'_, Elf>
+) -> object::Result<(DynamicInfo, Vec<(ExportedSymbol, u64)>)> {
+        let endian   = file.endian();
+        let data     = file.data();
+        let sections = file.elf_section_table();
+
+        let mut symbols: Vec<(ExportedSymbol, u64)> = file
+                .dynamic_symbols()
+                .filter(|s| s.is_definition() && s.is_global())
+                .filter_map(|s| Some((
+                        ExportedSymbol { name: s.name().ok()?.to_owned(), kind: symbol_kind(s.kind()) },
+                        s.address()
+                )))
+                .collect();
+
+        let (entries, strings) = match sections.dynamic(endian, data)? {
+                Some((entries, link)) => (entries, sections.strings(endian, data, link)?),
+                None => {
+                        let Some(entries) = file
+                                .elf_program_headers()
+                                .iter()
+                                .find_map(|p| p.dynamic(endian, data).transpose())
+                                .transpose()?
+                        else {
+                                return Ok((DynamicInfo::default(), symbols));
+                        };
+                        let value = |tag: u32| entries
+                                .iter()
+                                .find(|d| d.tag32(endian) == Some(tag))
+                                .map(|d| d.d_val(endian).into());
+                        let strings = match (value(elf::DT_STRTAB).and_then(|a| elf_offset(file, a)), value(elf::DT_STRSZ)) {
+                                (Some(start), Some(size)) => StringTable::new(data, start, start + size),
+                                _ => StringTable::default(),
+                        };
+                        if sections.is_empty() {
+                                symbols = elf_dynamic_symbols(file, &value, strings);
+                        }
+                        (entries, strings)
+                }
+        };
+
+        let mut info = DynamicInfo::default();
+        for entry in entries {
+                let name = || entry.string(endian, strings).map(|s| String::from_utf8_lossy(s).into_owned());
+                match entry.tag32(endian) {
+                        Some(elf::DT_NEEDED) => info.needed.push(name()?),
+                        Some(elf::DT_SONAME) => info.soname = Some(name()?),
+                        Some(elf::DT_RUNPATH) => info.runpath = name()?.split(':').map(String::from).collect(),
+                        Some(elf::DT_RPATH) if info.runpath.is_empty() => {
+                                info.runpath = name()?.split(':').map(String::from).collect()
+                        },
+                        Some(elf::DT_FLAGS_1) => info.pie = entry.val32(endian).is_some_and(|f| f & elf::DF_1_PIE != 0),
+                        _ => (),
+                }
+        }
+        Ok((info, symbols))
+}
+
+/// Reads the dynamic symbol table of an ELF file without a section table: It starts at
+/// `DT_SYMTAB`, and its length is only known from the hash table (`DT_HASH` or `DT_GNU_HASH`).
+fn elf_dynamic_symbols<Elf: FileHeader<Endian = Endianness>>(
+        file   : &ElfFile<'_, Elf>,
+        value  : &dyn Fn(u32) -> Option<u64>,
+        strings: StringTable<'_>
+) -> Vec<(ExportedSymbol, u64)> {
+        let endian = file.endian();
+        let data   = file.data();
+        let at     = |tag| value(tag).and_then(|a| elf_offset(file, a)).and_then(|o| data.get(o as usize..));
+
+        let count = match (at(elf::DT_HASH), at(elf::DT_GNU_HASH)) {
+                (Some(hash), _) => HashTable::<Elf>::parse(endian, hash).ok().map(|h| h.symbol_table_length()),
+                (_, Some(hash)) => GnuHashTable::<Elf>::parse(endian, hash).ok().and_then(|h| h.symbol_table_length(endian)),
+                _ => None,
+        };
+        let symbols = value(elf::DT_SYMTAB)
+                .and_then(|a| elf_offset(file, a))
+                .zip(count)
+                .and_then(|(offset, count)| data.read_slice_at::<Elf::Sym>(offset, count as usize).ok())
+                .unwrap_or_default();
+
+        symbols
+                .iter()
+                .filter(|s| s.st_shndx(endian) != elf::SHN_UNDEF && s.st_bind() != elf::STB_LOCAL)
+                .filter_map(|s| Some((
+                        ExportedSymbol {
+                                name: String::from_utf8(s.name(endian, strings).ok()?.to_vec()).ok()?,
+                                kind: elf_symbol_kind(s.st_type()),
+                        },
+                        s.st_value(endian).into()
+                )))
+                .collect()
+}
+
+/// Whether the host loads ELF object files, the only ones checked before loading.
+const HOST_ELF: bool = cfg!(not(any(target_os = "windows", target_vendor = "apple")));
+
+/// The architecture the host was built for, if the object crate knows it.
+fn host_architecture() -> Option<Architecture> {
+        let wide = cfg!(target_pointer_width = "64");
+        Some(match std::env::consts::ARCH {
+                "x86_64"      if wide => Architecture::X86_64,
+                "x86_64"              => Architecture::X86_64_X32,
+                "x86"                 => Architecture::I386,
+                "aarch64"     if wide => Architecture::Aarch64,
+                "aarch64"             => Architecture::Aarch64_Ilp32,
+                "arm"                 => Architecture::Arm,
+                "riscv64"             => Architecture::Riscv64,
+                "riscv32"             => Architecture::Riscv32,
+                "powerpc64"           => Architecture::PowerPc64,
+                "powerpc"             => Architecture::PowerPc,
+                "s390x"               => Architecture::S390x,
+                "mips64"              => Architecture::Mips64,
+                "mips"                => Architecture::Mips,
+                "loongarch64"         => Architecture::LoongArch64,
+                "sparc64"             => Architecture::Sparc64,
+                "m68k"                => Architecture::M68k,
+                "csky"                => Architecture::Csky,
+                _                     => return None,
+        })
+}
+
+/// Describes what an object file was built for, eg. `ELF 64-bit little endian X86_64`.
+fn describe_target(format: BinaryFormat, is_64: bool, little_endian: bool, architecture: Option<Architecture>) -> String {
+        format!(
+                "{} {}-bit {} endian {}",
+                format!("{:?}", format).to_uppercase(),
+                if is_64 { 64 } else { 32 },
+                if little_endian { "little" } else { "big" },
+                architecture.map(|a| format!("{:?}", a)).unwrap_or_else(|| std::env::consts::ARCH.into())
+        )
+}
+
+/// Checks, without loading (Or executing) it, that the object file `object` of `plugin`
+/// (Extracted at `path`, and read into `info`) can be loaded on this system: It must be an ELF
+/// shared object built for the host's architecture, and every library it needs must either be
+/// one of the `bundled` ones (Their file names and sonames) or installed. Other formats aren't checked.
+pub(crate) fn check_loadable(
+        plugin : &str,
+        object : &str,
+        path   : &Path,
+        info   : &ObjectInfo,
+        bundled: &[String]
+) -> Result<(), VPluginError> {
+        if !HOST_ELF {
+                return Ok(());
+        }
+
+        let host  = host_architecture();
+        let found = describe_target(info.format, info.is_64, info.little, Some(info.arch));
+        if info.format != BinaryFormat::Elf
+                || info.is_64 != cfg!(target_pointer_width = "64")
+                || info.little != cfg!(target_endian = "little")
+                || host.is_some_and(|a| a != info.arch)
+        {
+                let expected = describe_target(
+                        BinaryFormat::Elf,
+                        cfg!(target_pointer_width = "64"),
+                        cfg!(target_endian = "little"),
+                        host
+                );
+                log::error!("Object file '{}' of plugin '{}' was built for {}, not {}.", object, plugin, found, expected);
+                return Err(VPluginError::WrongArchitecture { plugin: plugin.into(), object: object.into(), found, expected });
+        }
+
+        let kind = match info.kind {
+                ObjectKind::Dynamic if info.dynamic.pie => Some("position-independent executable"),
+                ObjectKind::Dynamic                     => None,
+                ObjectKind::Executable                  => Some("executable"),
+                ObjectKind::Relocatable                 => Some("relocatable object file"),
+                ObjectKind::Core                        => Some("core dump"),
+                _                                       => Some("unknown kind of object file"),
+        };
+        if let Some(kind) = kind {
+                log::error!("Object file '{}' of plugin '{}' is not a shared object ({}).", object, plugin, kind);
+                return Err(VPluginError::NotSharedObject { plugin: plugin.into(), object: object.into(), kind: kind.into() });
+        }
+
+        let origin = path.parent().unwrap_or(Path::new("."));
+        for library in &info.dynamic.needed {
+                if !bundled.contains(library) && !loader::library_installed(library, origin, &info.dynamic.runpath) {
+                        log::error!(
+                                "Object file '{}' of plugin '{}' needs library '{}', which is neither bundled nor installed.",
+                                object,
+                                plugin,
+                                library
+                        );
+                        return Err(VPluginError::MissingLibrary { plugin: plugin.into(), object: object.into(), library: library.clone() });
+                }
+        }
+        Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        fn align(data: &mut Vec<u8>) -> u64 {
+                data.resize(data.len().next_multiple_of(8), 0);
+                data.len() as u64
+        }
+
+        /// Builds a little endian ELF64 shared object for `machine`, without a section table
+        /// (Like `sstrip` leaves them). It exports `test_hook` and `VPLUGIN_ABI_VERSION` (7),
+        /// and needs `libm.so.6`.
+        fn sectionless_object(machine: u16) -> Vec<u8> {
+                let mut strings = vec![0];
+                let mut string = |s: &str| {
+                        let offset = strings.len() as u64;
+                        strings.extend(s.as_bytes());
+                        strings.push(0);
+                        offset
+                };
+                let needed = string("libm.so.6");
+                let soname = string("libtest.so.1");
+                /* Neither the local symbol nor the undefined one are exported. */
+                let symbols = [
+                        (string("test_hook"), elf::STB_GLOBAL, elf::STT_FUNC, 1),
+                        (string("VPLUGIN_ABI_VERSION"), elf::STB_GLOBAL, elf::STT_OBJECT, 1),
+                        (string("local_symbol"), elf::STB_LOCAL, elf::STT_FUNC, 1),
+                        (string("undefined_symbol"), elf::STB_GLOBAL, elf::STT_FUNC, elf::SHN_UNDEF),
+                ];
+
+                /* Room for the file and program headers, then what they point to (Loaded where it is in the file). */
+                let mut body = vec![0; 64 + 2 * 56];
+                let strtab = body.len() as u64;
+                body.extend(&strings);
+
+                let hash = align(&mut body);
+                let count = symbols.len() as u32 + 1;
+                for value in [1, count, 0].into_iter().chain((0..count).map(|_| 0)) {
+                        body.extend(value.to_le_bytes());
+                }
+
+                let symtab  = align(&mut body);
+                let version = symtab + 24 * count as u64;
+                body.extend([0; 24]);
+                for (name, bind, kind, section) in symbols {
+                        body.extend((name as u32).to_le_bytes());
+                        body.extend([(bind << 4) | kind, 0]);
+                        body.extend(section.to_le_bytes());
+                        let value: u64 = if kind == elf::STT_OBJECT { version } else { 0 };
+                        body.extend(value.to_le_bytes());
+                        body.extend(4u64.to_le_bytes());
+                }
+                body.extend(7u32.to_le_bytes());
+
+                let dynamic = align(&mut body);
+                for (tag, value) in [
+                        (elf::DT_NEEDED, needed),
+                        (elf::DT_SONAME, soname),
+                        (elf::DT_STRTAB, strtab),
+                        (elf::DT_STRSZ, strings.len() as u64),
+                        (elf::DT_SYMTAB, symtab),
+                        (elf::DT_SYMENT, 24),
+                        (elf::DT_HASH, hash),
+                        (elf::DT_NULL, 0),
+                ] {
+                        body.extend((tag as u64).to_le_bytes());
+                        body.extend(value.to_le_bytes());
+                }
+                let size = body.len() as u64;
+
+                let mut data = Vec::new();
+                data.extend([0x7f, b'E', b'L', b'F', elf::ELFCLASS64, elf::ELFDATA2LSB, elf::EV_CURRENT, 0]);
+                data.extend([0; 8]);
+                data.extend(elf::ET_DYN.to_le_bytes());
+                data.extend(machine.to_le_bytes());
+                data.extend((elf::EV_CURRENT as u32).to_le_bytes());
+                /* No entry point, program headers right after the header, no section table and no flags. */
+                data.extend(0u64.to_le_bytes());
+                data.extend(64u64.to_le_bytes());
+                data.extend(0u64.to_le_bytes());
+                data.extend(0u32.to_le_bytes());
+                for value in [64u16, 56, 2, 0, 0, 0] {
+                        data.extend(value.to_le_bytes());
+                }
+                for (kind, offset, size) in [(elf::PT_LOAD, 0, size), (elf::PT_DYNAMIC, dynamic, size - dynamic)] {
+                        data.extend(kind.to_le_bytes());
+                        data.extend(elf::PF_R.to_le_bytes());
+                        for value in [offset, offset, offset, size, size, 8] {
+                                data.extend(value.to_le_bytes());
+                        }
+                }
+                data.extend(&body[data.len()..]);
+                data
+        }
+
+        #[test]
+        fn reads_exports_without_a_section_table() {
+                let info = ObjectInfo::parse(&sectionless_object(elf::EM_X86_64)).unwrap();
+                assert_eq!(info.exports, [
+                        ExportedSymbol { name: "VPLUGIN_ABI_VERSION".into(), kind: ExportedSymbolKind::Data },
+                        ExportedSymbol { name: "test_hook".into(), kind: ExportedSymbolKind::Function },
+                ]);
+                assert!(info.exports[1].is_function() && !info.exports[0].is_function());
+        }
+
+        #[test]
+        #[cfg(all(target_os = "linux", target_env = "gnu"))]
+        fn reads_exports_of_the_test_binary() {
+                let path    = std::env::current_exe().unwrap();
+                let symbols = read_exported_symbols(&path).unwrap();
+                let mut names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+                assert!(!names.contains(&""));
+                names.dedup();
+                assert!(names.windows(2).all(|w| w[0] < w[1]));
+        }
+
+        #[test]
+        fn refuses_files_that_are_not_object_files() {
+                let path = std::env::temp_dir().join(format!("vplugin-symbols-{}.so", std::process::id()));
+                fs::write(&path, "not an object file").unwrap();
+                let result = read_exported_symbols(&path);
+                fs::remove_file(&path).unwrap();
+                assert!(matches!(result, Err(VPluginError::InvalidPlugin { .. })));
+        }
+
+        #[test]
+        fn reads_dynamic_section_without_a_section_table() {
+                let info = ObjectInfo::parse(&sectionless_object(elf::EM_X86_64)).unwrap();
+                assert_eq!(info.dynamic, DynamicInfo {
+                        soname : Some("libtest.so.1".into()),
+                        needed : vec!["libm.so.6".into()],
+                        runpath: vec![],
+                        pie    : false,
+                });
+                assert_eq!((info.abi, info.build), (Some(7), None));
+                assert_eq!((info.format, info.arch, info.kind), (BinaryFormat::Elf, Architecture::X86_64, ObjectKind::Dynamic));
+                assert!(info.is_64 && info.little);
+        }
+
+        #[test]
+        #[cfg(all(target_os = "linux", target_env = "gnu"))]
+        fn reads_libraries_needed_by_the_test_binary() {
+                let path = std::env::current_exe().unwrap();
+                let info = ObjectInfo::read("test", "test", &path).unwrap();
+                assert!(info.dynamic.needed.iter().any(|l| l.starts_with("libc.so")));
+        }
+
+        #[test]
+        #[cfg(all(target_os = "linux", target_env = "gnu", target_arch = "x86_64"))]
+        fn refuses_libraries_neither_bundled_nor_installed() {
+                let path     = Path::new("/nonexistent/libtest.so");
+                let mut info = ObjectInfo::parse(&sectionless_object(elf::EM_X86_64)).unwrap();
+                assert!(check_loadable("test", "libtest.so", path, &info, &[]).is_ok());
+
+                info.dynamic.needed.push("libvplugin-missing.so".into());
+                assert!(matches!(
+                        check_loadable("test", "libtest.so", path, &info, &[]),
+                        Err(VPluginError::MissingLibrary { library, .. }) if library == "libvplugin-missing.so"
+                ));
+                assert!(check_loadable("test", "libtest.so", path, &info, &["libvplugin-missing.so".into()]).is_ok());
+        }
+
+        #[test]
+        #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+        fn refuses_objects_built_for_another_architecture() {
+                let machine = if cfg!(target_arch = "x86_64") { elf::EM_AARCH64 } else { elf::EM_X86_64 };
+                let info    = ObjectInfo::parse(&sectionless_object(machine)).unwrap();
+                assert!(matches!(
+                        check_loadable("test", "libtest.so", Path::new("libtest.so"), &info, &[]),
+                        Err(VPluginError::WrongArchitecture { .. })
+                ));
+        }
+
+        #[test]
+        fn refuses_objects_that_cannot_be_parsed() {
+                let path = std::env::temp_dir().join(format!("vplugin-object-{}.so", std::process::id()));
+                fs::write(&path, [0x7f, b'E', b'L', b'F']).unwrap();
+                let result = ObjectInfo::read("test", "libtest.so", &path);
+                fs::remove_file(&path).unwrap();
+                assert!(matches!(result, Err(VPluginError::NotSharedObject { .. })));
+        }
+}
@@ -0,0 +1,116 @@
+/*
+ * Copyright 2022 Aggelos Tselios.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+//! Runs the `vplugin` command line tool on a plugin packed from a system library.
+#![cfg(all(target_os = "linux", target_env = "gnu"))]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn vplugin(args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_vplugin"))
+                .args(args)
+                .output()
+                .expect("couldn't run vplugin")
+}
+
+fn stdout(output: &Output) -> String {
+        String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+        String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Returns a directory of its own for a test, emptied first.
+fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vplugin-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+}
+
+/// Returns the path of the system's `libm.so.6`, which serves as the object file of a plugin
+/// (Exporting `cos`), so no compiler is needed to build one.
+fn system_library() -> PathBuf {
+        use std::ffi::CStr;
+
+        unsafe {
+                let handle = libc::dlopen(c"libm.so.6".as_ptr(), libc::RTLD_NOW);
+                assert!(!handle.is_null(), "libm.so.6 is not installed");
+                let mut info: libc::Dl_info = std::mem::zeroed();
+                assert_ne!(libc::dladdr(libc::dlsym(handle, c"cos".as_ptr()), &mut info), 0);
+                PathBuf::from(CStr::from_ptr(info.dli_fname).to_str().unwrap())
+        }
+}
+
+/// Packs a plugin directory holding `libm.so.6` as its object file into `<dir>/test.vpl`.
+fn pack(dir: &Path) -> PathBuf {
+        let plugin = dir.join("plugin");
+        fs::create_dir_all(&plugin).unwrap();
+        fs::write(plugin.join("metadata.toml"), "[metadata]\nname = \"test\"\nversion = \"1.0\"\nobjfile = \"libtest.so\"\n").unwrap();
+        fs::copy(system_library(), plugin.join("libtest.so")).unwrap();
+
+        let archive = dir.join("test.vpl");
+        let output  = vplugin(&["pack", plugin.to_str().unwrap(), "-o", archive.to_str().unwrap()]);
+        assert!(output.status.success(), "{:?}", output);
+        assert!(stdout(&output).contains("Packed 'test'"));
+        archive
+}
+
+#[test]
+fn validates_packed_plugins() {
+        let dir     = test_dir("validate");
+        let archive = pack(&dir);
+        let archive = archive.to_str().unwrap();
+
+        let output = vplugin(&["validate", "--entry", "cos", archive]);
+        assert!(output.status.success(), "{:?}", output);
+        assert!(stdout(&output).ends_with(": OK\n"));
+
+        let output = vplugin(&["validate", "--entry", "vplugin_missing_entry", archive]);
+        assert!(!output.status.success());
+        assert!(stdout(&output).contains("Entry point 'vplugin_missing_entry' is not exported"));
+
+        let output = vplugin(&["inspect", archive]);
+        assert!(output.status.success(), "{:?}", output);
+        assert!(stdout(&output).contains("Plugin:      test"));
+        fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn refuses_directories_without_metadata() {
+        let dir    = test_dir("pack");
+        let output = vplugin(&["pack", dir.to_str().unwrap()]);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains("metadata.toml"));
+        fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reports_running_as_root() {
+        if unsafe { libc::geteuid() } != 0 {
+                return;
+        }
+        let dir     = test_dir("run");
+        let archive = pack(&dir);
+
+        let output = vplugin(&["run", archive.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("superuser"));
+        fs::remove_dir_all(&dir).unwrap();
+}